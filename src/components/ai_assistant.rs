@@ -6,6 +6,7 @@ use std::collections::VecDeque;
 use chrono::Local;
 use std::sync::Arc;
 use tokio::runtime::Runtime;
+use crate::core::edit_history::EditKind;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ContextFile { // Make this struct public
@@ -324,8 +325,7 @@ impl AIAssistant {
                             if let Some(active_buffer) = code_editor.get_active_buffer_mut() {
                                 let code_block = extract_code_block(&last_response);
                                 if !code_block.is_empty() {
-                                    active_buffer.content = code_block.trim().to_string();
-                                    active_buffer.is_modified = true;
+                                    active_buffer.replace_content(code_block.trim(), EditKind::AiApply);
                                     self.last_ai_response = None;
                                 }
                             }
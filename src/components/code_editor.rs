@@ -1,17 +1,31 @@
 use eframe::egui;
-use syntect::easy::HighlightLines;
-use syntect::highlighting::{ThemeSet, Style};
+use syntect::highlighting::ThemeSet;
 use syntect::parsing::SyntaxSet;
-use syntect::util::LinesWithEndings;
 use std::rc::Rc;
 use std::sync::Arc;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::ops::Range;
 use std::time::{Duration, Instant};
 use serde::{Deserialize, Serialize};
 use lru::LruCache;
 use std::num::NonZeroUsize;
 use crate::core::constants::AppConstants;
 use crate::core::file_system::FileSystem;
+use crate::core::edit_history::{EditHistory, EditKind, EditOp};
+use crate::core::selection::{Selection, Selections};
+use crate::core::folding::{self, FoldStrategy, Folding};
+use crate::core::syntax_highlighter::{IncrementalHighlighter, LineSpans};
+use crate::core::smart_edit::{self, EditorSettings};
+use crate::core::line_commands::{self, LineCommand};
+use crate::core::syntaxes;
+use crate::components::editor_view::EditorView;
+use crate::components::find_bar::FindBar;
+use crate::utils::themes;
+use crate::components::gutter::{Bookmarks, Diagnostics, GitHunks, Gutter, GutterMarkerProvider, SearchHits};
+use ropey::Rope;
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct CursorPosition {
@@ -20,7 +34,7 @@ pub struct CursorPosition {
 }
 
 struct HighlightCache {
-    jobs: LruCache<(String, String), egui::text::LayoutJob>,
+    highlighters: LruCache<u64, IncrementalHighlighter>,
 }
 
 impl Default for HighlightCache {
@@ -32,55 +46,665 @@ impl Default for HighlightCache {
 impl HighlightCache {
     fn new() -> Self {
         Self {
-            jobs: LruCache::new(NonZeroUsize::new(100).unwrap()),
+            highlighters: LruCache::new(NonZeroUsize::new(16).unwrap()),
         }
     }
+
+    /// Returns the highlighter for `buffer`, patched for any edits made since it
+    /// was last used.
+    fn highlighter(&mut self, buffer: &Buffer, theme: &str) -> &mut IncrementalHighlighter {
+        let highlighter = self.highlighters
+            .get_or_insert_mut(buffer.id(), || IncrementalHighlighter::new(buffer, theme));
+        highlighter.sync(buffer, theme);
+        highlighter
+    }
+}
+
+/// Lays out a line in the colors of its highlight spans. `marked` byte ranges,
+/// e.g. a matching bracket pair, get `marked_background` behind them.
+fn line_layout_job(
+    spans: Option<&LineSpans>,
+    line: &str,
+    font_id: &egui::FontId,
+    default_color: egui::Color32,
+    marked: &[Range<usize>],
+    marked_background: egui::Color32,
+) -> egui::text::LayoutJob {
+    let mut job = egui::text::LayoutJob::default();
+    let spans = spans.filter(|spans| {
+        spans.last().is_none_or(|(_, range)| range.end <= line.len())
+    });
+    let mut append = |range: Range<usize>, color: egui::Color32| {
+        let mut start = range.start;
+        for mark in marked.iter().filter(|mark| mark.start < range.end && mark.end > range.start) {
+            let mark = mark.start.max(range.start)..mark.end.min(range.end);
+            if mark.start > start {
+                job.append(&line[start..mark.start], 0.0, egui::TextFormat::simple(font_id.clone(), color));
+            }
+            let format = egui::TextFormat {
+                background: marked_background,
+                ..egui::TextFormat::simple(font_id.clone(), color)
+            };
+            job.append(&line[mark.clone()], 0.0, format);
+            start = mark.end;
+        }
+        if start < range.end {
+            job.append(&line[start..range.end], 0.0, egui::TextFormat::simple(font_id.clone(), color));
+        }
+    };
+
+    match spans {
+        Some(spans) => {
+            let mut last_end = 0;
+            for (color, range) in spans {
+                if range.start < last_end || !line.is_char_boundary(range.start) || !line.is_char_boundary(range.end) {
+                    continue;
+                }
+                if range.start > last_end {
+                    append(last_end..range.start, default_color);
+                }
+                append(range.clone(), *color);
+                last_end = range.end;
+            }
+            if last_end < line.len() {
+                append(last_end..line.len(), default_color);
+            }
+        }
+        None => append(0..line.len(), default_color),
+    }
+    job
+}
+
+/// Visuals for the code area: the background, text, selection, caret and
+/// current line colors of the color scheme where it sets them, the IDE
+/// theme's otherwise. The gutter follows them too.
+fn editor_visuals(visuals: &egui::Visuals, scheme: &syntect::highlighting::Theme) -> egui::Visuals {
+    let to_color32 = |color: syntect::highlighting::Color| {
+        egui::Color32::from_rgba_unmultiplied(color.r, color.g, color.b, color.a)
+    };
+    let settings = &scheme.settings;
+    let mut visuals = visuals.clone();
+    if let Some(background) = settings.background {
+        visuals.extreme_bg_color = to_color32(background);
+    }
+    if let Some(foreground) = settings.foreground {
+        visuals.override_text_color = Some(to_color32(foreground));
+    }
+    if let Some(selection) = settings.selection {
+        visuals.selection.bg_fill = to_color32(selection);
+    }
+    if let Some(caret) = settings.caret {
+        visuals.text_cursor.stroke.color = to_color32(caret);
+    }
+    visuals.faint_bg_color = settings
+        .line_highlight
+        .map(to_color32)
+        .unwrap_or_else(|| visuals.text_color().gamma_multiply(0.05));
+    visuals
+}
+
+fn editor_id(buffer_index: usize) -> egui::Id {
+    egui::Id::new(format!("buffer_{}_editor", buffer_index))
 }
 
 fn determine_syntax_from_path(path: &Path, syntax_set: &SyntaxSet) -> String {
-    syntax_set
-        .find_syntax_for_file(path)
-        .ok()
-        .flatten()
+    syntaxes::find_syntax_for_file(syntax_set, path)
         .map(|syntax| syntax.name.clone())
         .unwrap_or_else(|| "Plain Text".to_string())
 }
 
+static NEXT_BUFFER_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Line-level summary of a single change to a buffer, so views that keep
+/// per-line data can patch it instead of rebuilding it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineChange {
+    /// Buffer revision produced by this change.
+    pub revision: u64,
+    /// First line touched by the change.
+    pub start_line: usize,
+    /// Number of line breaks removed after `start_line`.
+    pub removed_lines: usize,
+    /// Number of line breaks inserted after `start_line`.
+    pub inserted_lines: usize,
+}
+
+impl LineChange {
+    /// Where `line` ends up after this change. Lines that were removed collapse
+    /// onto `start_line`.
+    pub fn map_line(&self, line: usize) -> usize {
+        if line <= self.start_line {
+            line
+        } else if line <= self.start_line + self.removed_lines {
+            self.start_line
+        } else {
+            line - self.removed_lines + self.inserted_lines
+        }
+    }
+}
+
+/// An edit made for one selection by [`Buffer::edit_selections`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelectionEdit {
+    /// Characters to replace. May reach beyond the selection.
+    pub range: Range<usize>,
+    pub text: String,
+    /// What to select afterwards, as character offsets into `text`.
+    pub selection: Range<usize>,
+}
+
 #[derive(Debug, Clone)]
 pub struct Buffer {
-    pub content: String,
+    id: u64,
+    text: Rope,
     pub file_path: Option<String>,
     pub syntax: String,
     pub is_modified: bool,
+    /// Edits are ignored, e.g. for files inside an archive.
+    pub read_only: bool,
     pub cursor_position: CursorPosition,
+    selections: Selections,
+    history: EditHistory,
+    saved_group_id: Option<u64>,
+    revision: u64,
+    line_changes: VecDeque<LineChange>,
+    folding: Folding,
+    scroll_to_cursor: bool,
 }
 
 impl Buffer {
+    const MAX_LINE_CHANGES: usize = 256;
+
     pub fn new() -> Self {
         Self {
-            content: String::new(),
+            id: NEXT_BUFFER_ID.fetch_add(1, Ordering::Relaxed),
+            text: Rope::new(),
             file_path: None,
             syntax: "Plain Text".to_string(),
             is_modified: false,
+            read_only: false,
             cursor_position: CursorPosition { line: 0, column: 0 },
+            selections: Selections::default(),
+            history: EditHistory::new(),
+            saved_group_id: None,
+            revision: 0,
+            line_changes: VecDeque::new(),
+            folding: Folding::default(),
+            scroll_to_cursor: false,
         }
     }
 
     pub fn from_file(content: String, file_path: String, syntax: String) -> Self {
         Self {
-            content,
+            text: Rope::from_str(&content),
             file_path: Some(file_path),
             syntax,
-            is_modified: false,
-            cursor_position: CursorPosition { line: 0, column: 0 },
+            ..Self::new()
+        }
+    }
+
+    /// Unique identifier of this buffer for the lifetime of the process.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Incremented on every change to the text.
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Changes made after `revision`, oldest first. Returns `None` when the log
+    /// no longer reaches back that far and callers must start over.
+    pub fn line_changes_since(&self, revision: u64) -> Option<impl Iterator<Item = &LineChange>> {
+        if revision > self.revision {
+            return None;
+        }
+        if revision < self.revision && self.line_changes.front().is_none_or(|c| c.revision > revision + 1) {
+            return None;
         }
+        Some(self.line_changes.iter().filter(move |change| change.revision > revision))
+    }
+
+    /// Returns the whole text. This copies the buffer, so prefer the line
+    /// accessors for anything that runs every frame.
+    pub fn text(&self) -> String {
+        self.text.to_string()
+    }
+
+    /// A copy of the text for use on another thread. Ropes share their
+    /// chunks, so this is cheap even for large files.
+    pub fn snapshot(&self) -> Rope {
+        self.text.clone()
+    }
+
+    pub fn len_chars(&self) -> usize {
+        self.text.len_chars()
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.text.len_lines()
+    }
+
+    /// Returns the line at `line_index` without its line ending.
+    pub fn line(&self, line_index: usize) -> Cow<'_, str> {
+        match self.text.get_line(line_index) {
+            Some(line) => trim_line_ending(line.into()),
+            None => Cow::Borrowed(""),
+        }
+    }
+
+    /// Iterates over all lines without their line endings.
+    pub fn lines(&self) -> impl Iterator<Item = Cow<'_, str>> {
+        self.text.lines().map(|line| trim_line_ending(line.into()))
+    }
+
+    /// Iterates over all lines, keeping the trailing `\n`.
+    pub fn lines_with_endings(&self) -> impl Iterator<Item = Cow<'_, str>> {
+        self.text.lines().map(Cow::from)
+    }
+
+    /// The line ending used by the buffer, going by its first line.
+    pub fn line_ending(&self) -> &'static str {
+        if self.line_with_ending(0).ends_with("\r\n") {
+            "\r\n"
+        } else {
+            "\n"
+        }
+    }
+
+    /// Returns the line at `line_index` including its line ending.
+    pub fn line_with_ending(&self, line_index: usize) -> Cow<'_, str> {
+        self.text.get_line(line_index).map(Cow::from).unwrap_or(Cow::Borrowed(""))
+    }
+
+    /// Number of characters in the line, excluding the line ending.
+    pub fn line_len_chars(&self, line_index: usize) -> usize {
+        self.line(line_index).chars().count()
+    }
+
+    pub fn line_to_char(&self, line_index: usize) -> usize {
+        self.text.line_to_char(line_index.min(self.text.len_lines()))
+    }
+
+    pub fn line_to_byte(&self, line_index: usize) -> usize {
+        self.text.line_to_byte(line_index.min(self.text.len_lines()))
+    }
+
+    pub fn char_to_line(&self, char_index: usize) -> usize {
+        self.text.char_to_line(char_index.min(self.text.len_chars()))
+    }
+
+    pub fn char_to_byte(&self, char_index: usize) -> usize {
+        self.text.char_to_byte(char_index.min(self.text.len_chars()))
+    }
+
+    pub fn byte_to_char(&self, byte_index: usize) -> usize {
+        self.text.byte_to_char(byte_index.min(self.text.len_bytes()))
+    }
+
+    /// Converts a character index into a 0-based `(line, column)` pair.
+    pub fn line_column(&self, char_index: usize) -> (usize, usize) {
+        let line = self.char_to_line(char_index);
+        (line, char_index.min(self.text.len_chars()) - self.text.line_to_char(line))
+    }
+
+    /// Converts a 0-based `(line, column)` pair into a character index, clamping
+    /// the column to the length of the line.
+    pub fn char_index(&self, line: usize, column: usize) -> usize {
+        let line = line.min(self.line_count().saturating_sub(1));
+        self.line_to_char(line) + column.min(self.line_len_chars(line))
+    }
+
+    pub fn slice(&self, char_range: Range<usize>) -> String {
+        let end = char_range.end.min(self.text.len_chars());
+        let start = char_range.start.min(end);
+        self.text.slice(start..end).to_string()
+    }
+
+    pub fn char_at(&self, char_index: usize) -> Option<char> {
+        self.text.get_char(char_index)
+    }
+
+    /// Finds the first occurrence of `pattern`, scanning line by line.
+    /// Returns the character index of the match.
+    pub fn find(&self, pattern: &str) -> Option<usize> {
+        if pattern.is_empty() || pattern.contains('\n') {
+            return None;
+        }
+        self.lines().enumerate().find_map(|(line_index, line)| {
+            line.find(pattern).map(|byte_offset| {
+                self.line_to_char(line_index) + line[..byte_offset].chars().count()
+            })
+        })
+    }
+
+    /// Finds the next occurrence of `pattern` starting at `from_char`, wrapping
+    /// around to the start of the buffer. Unlike [`Buffer::find`] the pattern may
+    /// span several lines.
+    pub fn find_next(&self, pattern: &str, from_char: usize) -> Option<usize> {
+        if pattern.is_empty() {
+            return None;
+        }
+        let text = self.text();
+        let from_byte = self.char_to_byte(from_char);
+        let byte_offset = text[from_byte..]
+            .find(pattern)
+            .map(|offset| from_byte + offset)
+            .or_else(|| text.find(pattern))?;
+        Some(self.byte_to_char(byte_offset))
     }
 
     pub fn set_cursor_position(&mut self, line: usize, column: usize) {
-        self.cursor_position = CursorPosition { 
-            line: line.saturating_sub(1), // Convert to 0-based index
-            column 
+        let line = line.saturating_sub(1); // Convert to 0-based index
+        let char_index = self.char_index(line, column);
+        self.set_selection(Selection::caret(char_index));
+    }
+
+    /// The primary selection.
+    pub fn selection(&self) -> Selection {
+        self.selections.primary()
+    }
+
+    pub fn selections(&self) -> &Selections {
+        &self.selections
+    }
+
+    /// Replaces all selections with a single one.
+    pub fn set_selection(&mut self, selection: Selection) {
+        self.set_selections(Selections::single(selection));
+    }
+
+    pub fn set_selections(&mut self, mut selections: Selections) {
+        selections.clamp(self.text.len_chars());
+        self.selections = selections;
+        self.sync_cursor_position();
+        self.scroll_to_cursor = true;
+    }
+
+    /// Adds a selection (e.g. another cursor) and makes it the primary one.
+    pub fn add_selection(&mut self, selection: Selection) {
+        let mut selections = self.selections.clone();
+        selections.push(selection);
+        self.set_selections(selections);
+    }
+
+    /// Replaces every selection with the result of `f`, keeping the primary one.
+    pub fn map_selections(&mut self, mut f: impl FnMut(&Buffer, Selection) -> Selection) {
+        let mapped: Vec<Selection> = self.selections.iter().map(|selection| f(self, *selection)).collect();
+        let primary = self.selections.primary_index();
+        self.set_selections(Selections::new(mapped, primary));
+    }
+
+    /// Returns `true` once after the cursor moved in a way the view should follow.
+    pub fn take_scroll_to_cursor(&mut self) -> bool {
+        std::mem::take(&mut self.scroll_to_cursor)
+    }
+
+    /// Replaces the characters in `char_range` with `text` and records the change
+    /// in the edit history. Every mutation of the buffer goes through here.
+    pub fn edit(&mut self, char_range: Range<usize>, text: &str, kind: EditKind) {
+        if self.read_only {
+            return;
+        }
+        let removed = self.apply_raw(char_range.start, char_range.end - char_range.start, text);
+        self.history.record(
+            EditOp {
+                char_start: char_range.start,
+                removed,
+                inserted: text.to_string(),
+            },
+            kind,
+        );
+        self.update_modified();
+    }
+
+    /// Replaces every selection with `text` and leaves a cursor after each insertion.
+    pub fn insert_at_cursor(&mut self, text: &str) {
+        self.replace_selections(|_, _| text.to_string());
+    }
+
+    /// Replaces every selection with the text `text_for` returns for it, given its
+    /// index in document order. All replacements form a single undo step.
+    pub fn replace_selections(&mut self, mut text_for: impl FnMut(usize, Selection) -> String) {
+        if self.read_only {
+            return;
+        }
+        let selections: Vec<Selection> = self.selections.iter().copied().collect();
+        let mut ops = Vec::with_capacity(selections.len());
+        // Work back to front so earlier selections are unaffected by the edits
+        for (index, selection) in selections.iter().enumerate().rev() {
+            let text = text_for(index, *selection);
+            let range = selection.range();
+            let removed = self.apply_raw(range.start, range.end - range.start, &text);
+            ops.push(EditOp {
+                char_start: range.start,
+                removed,
+                inserted: text,
+            });
+        }
+        self.history.record_batch(ops, EditKind::Typing);
+        self.update_modified();
+        self.scroll_to_cursor = true;
+    }
+
+    /// Applies one edit per selection, given in document order, as a single
+    /// typing step and then selects what each edit asks for. Unlike
+    /// [`Buffer::replace_selections`] an edit may cover more than its
+    /// selection, e.g. the indentation before a typed `}`. The edits must not
+    /// overlap.
+    pub fn edit_selections(&mut self, edits: Vec<SelectionEdit>) {
+        if self.read_only || edits.is_empty() {
+            return;
+        }
+        let primary = self.selections.primary_index();
+        let mut ops = Vec::with_capacity(edits.len());
+        for edit in edits.iter().rev() {
+            let removed = self.apply_raw(edit.range.start, edit.range.len(), &edit.text);
+            ops.push(EditOp {
+                char_start: edit.range.start,
+                removed,
+                inserted: edit.text.clone(),
+            });
+        }
+        self.history.record_batch(ops, EditKind::Typing);
+
+        // Every edit moves the ones after it by the change in length
+        let mut shift = 0isize;
+        let mut selections = Vec::with_capacity(edits.len());
+        for edit in &edits {
+            let start = edit.range.start.saturating_add_signed(shift);
+            selections.push(Selection::new(start + edit.selection.start, start + edit.selection.end));
+            shift += edit.text.chars().count() as isize - edit.range.len() as isize;
+        }
+        self.set_selections(Selections::new(selections, primary));
+        self.update_modified();
+    }
+
+    /// Replaces several non-overlapping character ranges at once, e.g. every
+    /// search match, as a single undo step.
+    pub fn replace_ranges(&mut self, mut edits: Vec<(Range<usize>, String)>, kind: EditKind) {
+        if self.read_only {
+            return;
+        }
+        edits.sort_by_key(|(range, _)| range.start);
+        let mut ops = Vec::with_capacity(edits.len());
+        for (range, text) in edits.into_iter().rev() {
+            let removed = self.apply_raw(range.start, range.end - range.start, &text);
+            ops.push(EditOp {
+                char_start: range.start,
+                removed,
+                inserted: text,
+            });
+        }
+        self.history.seal();
+        self.history.record_batch(ops, kind);
+        self.update_modified();
+    }
+
+    /// Text of every selection, joined by newlines, as copied to the clipboard.
+    pub fn selected_text(&self) -> String {
+        self.selections
+            .iter()
+            .map(|selection| self.slice(selection.range()))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Replaces the whole content as a single undoable step. Only the region that
+    /// actually differs is recorded so large files stay cheap to keep in history.
+    pub fn replace_content(&mut self, new_content: &str, kind: EditKind) {
+        let prefix_chars = self.text
+            .chars()
+            .zip(new_content.chars())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let old_rest: Vec<char> = self.text.chars_at(prefix_chars).collect();
+        let new_rest: Vec<char> = new_content.chars().skip(prefix_chars).collect();
+        let suffix_chars = old_rest
+            .iter()
+            .rev()
+            .zip(new_rest.iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        if old_rest.len() == new_rest.len() && suffix_chars == old_rest.len() {
+            return;
+        }
+
+        let inserted: String = new_rest[..new_rest.len() - suffix_chars].iter().collect();
+        let end = prefix_chars + old_rest.len() - suffix_chars;
+        self.edit(prefix_chars..end, &inserted, kind);
+    }
+
+    /// Replaces the content with what is on disk. The reload itself can be undone,
+    /// but the buffer is considered saved afterwards.
+    pub fn reload(&mut self, content: &str) {
+        // Read-only buffers follow their file too, e.g. an entry of a rebuilt archive
+        let read_only = std::mem::replace(&mut self.read_only, false);
+        self.replace_content(content, EditKind::Reload);
+        self.mark_saved();
+        self.read_only = read_only;
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.history.seal();
+        self.saved_group_id = self.history.current_group_id();
+        self.is_modified = false;
+    }
+
+    /// Reverts the most recent edit group and moves the cursor to where it happened.
+    pub fn undo(&mut self) -> bool {
+        if self.read_only {
+            return false;
+        }
+        let Some(group) = self.history.undo() else {
+            return false;
+        };
+        for op in group.ops.iter().rev() {
+            self.apply_raw(op.char_start, op.inserted.chars().count(), &op.removed);
+        }
+        self.update_modified();
+        self.set_selection(Selection::caret(group.undo_cursor()));
+        true
+    }
+
+    /// Re-applies the most recently undone edit group.
+    pub fn redo(&mut self) -> bool {
+        if self.read_only {
+            return false;
+        }
+        let Some(group) = self.history.redo() else {
+            return false;
+        };
+        for op in &group.ops {
+            self.apply_raw(op.char_start, op.removed.chars().count(), &op.inserted);
+        }
+        self.update_modified();
+        self.set_selection(Selection::caret(group.redo_cursor()));
+        true
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.history.can_undo()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.history.can_redo()
+    }
+
+    /// Fold regions and folded state. Call [`Buffer::update_fold_regions`] first
+    /// so the regions match the current text.
+    pub fn folding(&self) -> &Folding {
+        &self.folding
+    }
+
+    pub fn folding_mut(&mut self) -> &mut Folding {
+        &mut self.folding
+    }
+
+    /// Recomputes the fold regions if the text or syntax changed since they were
+    /// last computed.
+    pub fn update_fold_regions(&mut self) {
+        let strategy = FoldStrategy::for_buffer(self);
+        if self.folding.is_stale(self.revision, strategy) {
+            let regions = folding::compute_regions(self, strategy);
+            self.folding.set_regions(regions, self.revision, strategy);
+        }
+    }
+
+    /// Closes the current undo group, e.g. after the cursor was moved.
+    pub fn seal_history(&mut self) {
+        self.history.seal();
+    }
+
+    fn update_modified(&mut self) {
+        self.is_modified = self.history.current_group_id() != self.saved_group_id;
+    }
+
+    fn sync_cursor_position(&mut self) {
+        let (line, column) = self.line_column(self.selections.primary().head);
+        self.cursor_position = CursorPosition { line, column };
+    }
+
+    /// Splices the text without touching the history, returning what was removed.
+    fn apply_raw(&mut self, char_start: usize, removed_chars: usize, inserted: &str) -> String {
+        let end = (char_start + removed_chars).min(self.text.len_chars());
+        let start = char_start.min(end);
+        let removed = self.text.slice(start..end).to_string();
+        let start_line = self.text.char_to_line(start);
+        self.text.remove(start..end);
+        self.text.insert(start, inserted);
+        self.revision += 1;
+
+        let change = LineChange {
+            revision: self.revision,
+            start_line,
+            removed_lines: removed.matches('\n').count(),
+            inserted_lines: inserted.matches('\n').count(),
         };
+        self.folding.apply_change(&change);
+        if self.line_changes.len() == Self::MAX_LINE_CHANGES {
+            self.line_changes.pop_front();
+        }
+        self.line_changes.push_back(change);
+
+        self.selections.map_edit(start, end, inserted.chars().count());
+        self.sync_cursor_position();
+        removed
+    }
+}
+
+fn trim_line_ending(line: Cow<'_, str>) -> Cow<'_, str> {
+    let trimmed_len = line.trim_end_matches(['\n', '\r']).len();
+    if trimmed_len == line.len() {
+        return line;
+    }
+    match line {
+        Cow::Borrowed(s) => Cow::Borrowed(&s[..trimmed_len]),
+        Cow::Owned(mut s) => {
+            s.truncate(trimmed_len);
+            Cow::Owned(s)
+        }
     }
 }
 
@@ -93,10 +717,23 @@ pub struct CodeEditor {
     pub search_highlight_expires_at: Option<Instant>,
     syntax_set: Arc<SyntaxSet>,
     theme_set: Arc<ThemeSet>,
+    /// Name of the color scheme in `theme_set` code is highlighted with.
+    color_scheme: String,
+    pub editor_settings: EditorSettings,
     pub search_selected_line: Option<usize>,
     pub logo_texture: Option<egui::TextureHandle>,
     highlight_cache: HighlightCache,
     pub selected_match_position: Option<(usize, usize)>,
+    pub bookmarks: Bookmarks,
+    pub search_hits: SearchHits,
+    pub diagnostics: Diagnostics,
+    pub git_hunks: GitHunks,
+    pub find_bar: FindBar,
+    /// Disk content of files that changed on disk while their buffer had unsaved
+    /// edits, by path, until the user picks a version.
+    pub changed_on_disk: HashMap<String, String>,
+    /// Additional gutter providers, e.g. from plugins. Drawn after the built-in ones.
+    pub marker_providers: Vec<Box<dyn GutterMarkerProvider>>,
 }
 
 impl CodeEditor {
@@ -108,15 +745,39 @@ impl CodeEditor {
             current_file: None,
             search_highlight_text: None,
             search_highlight_expires_at: None,
-            syntax_set: Arc::new(SyntaxSet::load_defaults_newlines()),
-            theme_set: Arc::new(ThemeSet::load_defaults()),
+            syntax_set: Arc::new(syntaxes::load()),
+            theme_set: Arc::new(themes::load_color_schemes()),
+            color_scheme: themes::DARK_COLOR_SCHEME.to_string(),
+            editor_settings: EditorSettings::default(),
             search_selected_line: None,
             logo_texture: None,
             highlight_cache: HighlightCache::new(),
             selected_match_position: None,
+            bookmarks: Bookmarks::new(),
+            search_hits: SearchHits::new(),
+            diagnostics: Diagnostics::new(),
+            git_hunks: GitHunks::new(),
+            find_bar: FindBar::new(),
+            changed_on_disk: HashMap::new(),
+            marker_providers: Vec::new(),
+        }
+    }
+
+    /// Highlights code with another color scheme, e.g. after the IDE theme
+    /// changed. Unknown names fall back to the default dark scheme.
+    pub fn set_color_scheme(&mut self, name: &str) {
+        if self.color_scheme != name {
+            self.color_scheme = name.to_string();
         }
     }
 
+    /// Reads the color schemes again, e.g. after one was imported.
+    pub fn reload_color_schemes(&mut self) {
+        self.theme_set = Arc::new(themes::load_color_schemes());
+        // Highlights made with an older version of a scheme are dropped
+        self.highlight_cache = HighlightCache::new();
+    }
+
     pub fn create_new_buffer(&mut self) -> usize {
         let buffer = Buffer::new();
         self.buffers.push(buffer);
@@ -139,13 +800,39 @@ impl CodeEditor {
         index
     }
 
-    pub fn reload_all_buffers(&mut self, fs: &Rc<FileSystem>, log: &mut impl FnMut(&str)) {
+    /// Name of the syntax files at `path` are highlighted with.
+    pub fn syntax_for_path(&self, path: &Path) -> String {
+        determine_syntax_from_path(path, &self.syntax_set)
+    }
+
+    /// Points the buffers of files at or below `from` to where they were moved.
+    pub fn path_moved(&mut self, from: &Path, to: &Path) {
         for buffer in &mut self.buffers {
-            if let Some(file_path) = &buffer.file_path {
-                match fs.open_file(Path::new(file_path)) {
+            let Some(file_path) = buffer.file_path.clone() else {
+                continue;
+            };
+            let Ok(rest) = Path::new(&file_path).strip_prefix(from) else {
+                continue;
+            };
+            let new_path = if rest.as_os_str().is_empty() { to.to_path_buf() } else { to.join(rest) };
+            let new_path = new_path.to_string_lossy().into_owned();
+            buffer.syntax = determine_syntax_from_path(Path::new(&new_path), &self.syntax_set);
+            if let Some(content) = self.changed_on_disk.remove(&file_path) {
+                self.changed_on_disk.insert(new_path.clone(), content);
+            }
+            buffer.file_path = Some(new_path);
+        }
+    }
+
+    pub fn reload_all_buffers(&mut self, fs: &Rc<FileSystem>, log: &mut impl FnMut(&str)) {
+        // Reloads follow checkouts and resets, which can move HEAD
+        self.git_hunks.invalidate();
+        // Read-only buffers come from archives, not from the project's files
+        for buffer in self.buffers.iter_mut().filter(|buffer| !buffer.read_only) {
+            if let Some(file_path) = buffer.file_path.clone() {
+                match fs.open_file(Path::new(&file_path)) {
                     Ok(content) => {
-                        buffer.content = content;
-                        buffer.is_modified = false;
+                        buffer.reload(&content);
                         log(&format!("Reloaded {}", file_path));
                     },
                     Err(e) => log(&format!("Failed to reload {}: {}", file_path, e)),
@@ -154,6 +841,58 @@ impl CodeEditor {
         }
     }
 
+    /// Brings the buffers of files at or below `paths` in line with the disk.
+    /// Unmodified buffers are reloaded; modified ones keep their text and show a
+    /// prompt to pick a version.
+    pub fn sync_with_disk(&mut self, fs: &FileSystem, paths: &[PathBuf], log: &mut dyn FnMut(&str)) {
+        // Archive entries can't be read from disk, see `FileModal::sync_archive_buffers`
+        for buffer in self.buffers.iter_mut().filter(|buffer| !buffer.read_only) {
+            let Some(file_path) = buffer.file_path.clone() else {
+                continue;
+            };
+            let path = Path::new(&file_path);
+            if !paths.iter().any(|changed| path.starts_with(changed)) {
+                continue;
+            }
+            match fs.open_file(path) {
+                // Also what our own saves look like
+                Ok(content) if content == buffer.text() => {
+                    self.changed_on_disk.remove(&file_path);
+                }
+                Ok(content) if !buffer.is_modified => {
+                    buffer.reload(&content);
+                    log(&format!("Reloaded {} (changed on disk)", file_path));
+                }
+                Ok(content) => {
+                    log(&format!("{} changed on disk and has unsaved changes", file_path));
+                    self.changed_on_disk.insert(file_path, content);
+                }
+                Err(_) if !path.exists() => log(&format!("{} was deleted on disk", file_path)),
+                Err(e) => log(&format!("Failed to reload {}: {}", file_path, e)),
+            }
+        }
+    }
+
+    /// Offers to reload a modified buffer whose file changed on disk.
+    fn show_disk_change_bar(ui: &mut egui::Ui, buffer: &mut Buffer, changed_on_disk: &mut HashMap<String, String>) {
+        let Some(file_path) = buffer.file_path.clone() else {
+            return;
+        };
+        if !changed_on_disk.contains_key(&file_path) {
+            return;
+        }
+        ui.horizontal(|ui| {
+            ui.colored_label(ui.visuals().warn_fg_color, "⚠ This file changed on disk and you have unsaved changes.");
+            if ui.button("Reload").on_hover_text("Discard your changes and load the file from disk").clicked() {
+                if let Some(disk_content) = changed_on_disk.remove(&file_path) {
+                    buffer.reload(&disk_content);
+                }
+            } else if ui.button("Keep Mine").on_hover_text("Keep your version; saving overwrites the file").clicked() {
+                changed_on_disk.remove(&file_path);
+            }
+        });
+    }
+
     pub fn load_logo(&mut self, ctx: &egui::Context) -> Result<(), image::ImageError> {
         if self.logo_texture.is_none() {
             // Use the icon from AppConstants
@@ -179,7 +918,8 @@ impl CodeEditor {
 
     pub fn close_buffer(&mut self, index: usize) {
         if index < self.buffers.len() {
-            self.buffers.remove(index);
+            let buffer = self.buffers.remove(index);
+            self.bookmarks.remove_buffer(buffer.id());
             
             if let Some(active_index) = self.active_buffer_index {
                 if active_index == index {
@@ -225,7 +965,11 @@ impl CodeEditor {
                     .unwrap_or("untitled");
         
                 ui.horizontal(|ui| {
-                    let mut text = egui::RichText::new(file_name);
+                    let mut text = if buffer.read_only {
+                        egui::RichText::new(format!("🔒 {}", file_name))
+                    } else {
+                        egui::RichText::new(file_name)
+                    };
                     if buffer.is_modified {
                         text = text.italics();
                     }
@@ -276,9 +1020,13 @@ impl CodeEditor {
                             
                             ui.label("Shortcuts:");
                             ui.label("Ctrl+O: Open folder");
+                            ui.label("Ctrl+1: Show or hide the project tree");
                             ui.label("Ctrl+P: Search files");
                             ui.label("Ctrl+F: Find in current file");
+                            ui.label("Ctrl+H: Replace in current file");
                             ui.label("Ctrl+Shift+F: Find in project");
+                            ui.label("F4 / Shift+F4: Next / previous search result");
+                            ui.label("Ctrl+Shift+H: Replace in project");
                             ui.label("Ctrl+M: Open settings");
                             ui.label("Ctrl+S: Save current file");
                             ui.add_space(20.0);
@@ -307,43 +1055,115 @@ impl CodeEditor {
                         }
                     });
     
+                Self::show_disk_change_bar(ui, buffer, &mut self.changed_on_disk);
+                self.find_bar.show(ui, buffer, &mut self.search_hits);
+
                 let header_height = ui.min_rect().height();
                 let editor_height = available_height - header_height;
-                let search_highlight = self.search_highlight_text.clone();
-                let selected_line = self.search_selected_line;
-    
-                // Create a persistent ScrollArea
-                egui::ScrollArea::vertical()
-                    .id_source(format!("buffer_{}_scroll_area", active_index))
-                    .auto_shrink([false; 2])
-                    .max_height(editor_height)
-                    .show(ui, |ui| {  // Changed from show_viewport to show
-                        let mut layouter = |ui: &egui::Ui, string: &str, wrap_width: f32| {
-                            let mut layout_job = highlight_syntax(
-                                string,
-                                &self.syntax_set,
-                                &self.theme_set,
-                                &buffer.syntax,
-                                search_highlight.as_deref(),
-                                selected_line,
-                                &mut self.highlight_cache,
-                                self.selected_match_position
-                            );
-                            layout_job.wrap.max_width = wrap_width;
-                            ui.fonts(|f| f.layout_job(layout_job))
-                        };
-
-                        // Remove the viewport intersection check
-                        if ui.add_sized(
-                            [ui.available_width(), ui.available_height()],  // Use available_height instead of fixed editor_height
-                            egui::TextEdit::multiline(&mut buffer.content)
-                                .desired_width(f32::INFINITY)
-                                .font(egui::TextStyle::Monospace)
-                                .layouter(&mut layouter)
-                        ).changed() {
-                            buffer.is_modified = true;
-                        }
+                let editor_id = editor_id(active_index);
+
+                // Undo/redo also work when nothing else has focus, so a bad AI apply
+                // can be reverted straight away.
+                let editor_focused = ui.memory(|m| m.has_focus(editor_id) || m.focused().is_none());
+                if editor_focused {
+                    let (undo, redo) = ui.input_mut(|i| {
+                        let redo = i.consume_key(egui::Modifiers::COMMAND | egui::Modifiers::SHIFT, egui::Key::Z)
+                            || i.consume_key(egui::Modifiers::COMMAND, egui::Key::Y);
+                        let undo = i.consume_key(egui::Modifiers::COMMAND, egui::Key::Z);
+                        (undo, redo)
                     });
+                    if undo {
+                        buffer.undo();
+                    } else if redo {
+                        buffer.redo();
+                    }
+
+                    let (toggle_bookmark, next_bookmark, previous_bookmark) = ui.input_mut(|i| (
+                        i.consume_key(egui::Modifiers::COMMAND, egui::Key::F2),
+                        i.consume_key(egui::Modifiers::NONE, egui::Key::F2),
+                        i.consume_key(egui::Modifiers::SHIFT, egui::Key::F2),
+                    ));
+                    let cursor_line = buffer.cursor_position.line;
+                    if toggle_bookmark {
+                        self.bookmarks.toggle(buffer, cursor_line);
+                    }
+                    let target = if next_bookmark {
+                        self.bookmarks.next(buffer, cursor_line)
+                    } else if previous_bookmark {
+                        self.bookmarks.previous(buffer, cursor_line)
+                    } else {
+                        None
+                    };
+                    if let Some(line) = target {
+                        buffer.set_cursor_position(line + 1, 0);
+                    }
+                }
+
+                // Lines are highlighted on demand as the view lays them out, so only
+                // the stale lines up to the bottom of the viewport are processed.
+                let color_scheme = self.color_scheme.as_str();
+                let theme = self.theme_set.themes
+                    .get(color_scheme)
+                    .unwrap_or_else(|| &self.theme_set.themes[themes::DARK_COLOR_SCHEME]);
+                let syntax_set = &self.syntax_set;
+                let highlighter = self.highlight_cache.highlighter(buffer, color_scheme);
+                highlighter.begin_frame();
+                let font_id = egui::TextStyle::Monospace.resolve(ui.style());
+                let visuals = editor_visuals(ui.visuals(), theme);
+                let default_color = visuals.text_color();
+                let bracket_background = theme.settings.brackets_background
+                    .map(|color| egui::Color32::from_rgba_unmultiplied(color.r, color.g, color.b, color.a))
+                    .unwrap_or_else(|| default_color.gamma_multiply(0.2));
+                let ctx = ui.ctx().clone();
+                // The view edits the buffer before painting, so the pair is looked
+                // up again whenever the text or the cursor changed
+                let mut brackets_key = None;
+                let mut brackets = None;
+                let mut line_layouter = |buffer: &Buffer, line_index: usize, line: &str| {
+                    highlighter.sync(buffer, color_scheme);
+                    if !highlighter.highlight_until(buffer, line_index + 1, syntax_set, theme) {
+                        ctx.request_repaint();
+                    }
+                    let key = Some((buffer.revision(), buffer.selection().head));
+                    if brackets_key != key {
+                        brackets_key = key;
+                        brackets = smart_edit::matching_brackets(buffer);
+                    }
+                    let line_start = buffer.line_to_byte(line_index);
+                    let marked: Vec<Range<usize>> = brackets
+                        .into_iter()
+                        .flat_map(|(first, second)| [first, second])
+                        .filter(|&index| buffer.char_to_line(index) == line_index)
+                        .filter_map(|index| {
+                            let start = buffer.char_to_byte(index) - line_start;
+                            Some(start..start + buffer.char_at(index)?.len_utf8())
+                        })
+                        .collect();
+                    line_layout_job(highlighter.spans(line_index), line, &font_id, default_color, &marked, bracket_background)
+                };
+
+                self.git_hunks.update(ui.ctx(), buffer);
+                let mut gutter = Gutter::new()
+                    .with_provider(&mut self.git_hunks)
+                    .with_provider(&mut self.diagnostics)
+                    .with_provider(&mut self.bookmarks)
+                    .with_provider(&mut self.search_hits);
+                for provider in self.marker_providers.iter_mut() {
+                    gutter.add_provider(provider.as_mut());
+                }
+
+                // Search result highlights only last while the search highlight is active
+                let search_active = self.search_highlight_text.is_some();
+                ui.scope(|ui| {
+                    *ui.visuals_mut() = visuals;
+                    EditorView::new(buffer, editor_id)
+                        .settings(self.editor_settings)
+                        .search_selected_line(self.search_selected_line.filter(|_| search_active))
+                        .selected_match(self.selected_match_position.filter(|_| search_active))
+                        .find_matches(self.find_bar.matches())
+                        .gutter(gutter)
+                        .show(ui, editor_height, &mut line_layouter);
+                });
             }
         }
     }
@@ -352,15 +1172,21 @@ impl CodeEditor {
         self.search_highlight_text = Some(search_term.to_string());
         self.search_highlight_expires_at = Some(Instant::now() + Duration::from_secs_f64(0.5));
         self.search_selected_line = selected_line_number;
+        self.search_hits.set_pattern(Some(search_term.to_string()));
 
         if let Some(buffer) = self.get_active_buffer_mut() {
-            if let Some(position) = buffer.content.find(search_term) {
-                let (line, column) = calculate_line_column(&buffer.content, position);
-                buffer.set_cursor_position(line, column);
+            if let Some(position) = buffer.find(search_term) {
+                buffer.set_selection(Selection::caret(position));
             }
         }
     }
 
+    /// Opens the find bar for the active buffer, with the replace row if `replace` is set.
+    pub fn open_find_bar(&mut self, replace: bool) {
+        let buffer = self.active_buffer_index.and_then(|i| self.buffers.get(i));
+        self.find_bar.open(buffer, replace);
+    }
+
     pub fn clear_expired_highlights(&mut self) {
         if let Some(expires_at) = self.search_highlight_expires_at {
             if Instant::now() >= expires_at {
@@ -370,6 +1196,24 @@ impl CodeEditor {
         }
     }
 
+    /// Whether keys are meant for the active buffer: its editor has focus, or
+    /// no other widget does.
+    pub fn has_focus(&self, ctx: &egui::Context) -> bool {
+        let Some(index) = self.active_buffer_index else {
+            return false;
+        };
+        ctx.memory(|m| m.focused().is_none_or(|id| id == editor_id(index)))
+    }
+
+    /// Runs `command` on the active buffer, with the comment markers of its syntax.
+    pub fn run_line_command(&mut self, command: LineCommand) {
+        let Some(buffer) = self.active_buffer_index.and_then(|i| self.buffers.get_mut(i)) else {
+            return;
+        };
+        let comments = syntaxes::comment_tokens(&self.syntax_set, &buffer.syntax);
+        line_commands::run(buffer, command, &comments);
+    }
+
     pub fn get_active_buffer(&self) -> Option<&Buffer> {
         self.active_buffer_index.and_then(|i| self.buffers.get(i))
     }
@@ -380,131 +1224,7 @@ impl CodeEditor {
 
     pub fn get_active_content(&self) -> String {
         self.get_active_buffer()
-            .map(|buffer| buffer.content.clone())
+            .map(|buffer| buffer.text())
             .unwrap_or_default()
     }
 }
-
-fn highlight_syntax(
-    code: &str,
-    syntax_set: &SyntaxSet,
-    theme_set: &ThemeSet,
-    current_syntax: &str,
-    search_highlight: Option<&str>,
-    selected_line: Option<usize>,
-    cache: &mut HighlightCache,
-    selected_match_position: Option<(usize, usize)>, // Add this parameter
-) -> egui::text::LayoutJob {
-    let syntax = syntax_set.find_syntax_by_name(current_syntax)
-        .unwrap_or_else(|| syntax_set.find_syntax_plain_text());
-    let mut highlighter = HighlightLines::new(syntax, &theme_set.themes["base16-ocean.dark"]);
-
-    let mut job = egui::text::LayoutJob::default();
-    
-    if search_highlight.is_none() && selected_line.is_none() && selected_match_position.is_none() {
-        let cache_key = (current_syntax.to_string(), code.to_string());
-        if let Some(cached_job) = cache.jobs.get(&cache_key) {
-            return cached_job.clone();
-        }
-    }
-
-    let mut absolute_position = 0;
-    for (line_index, line) in LinesWithEndings::from(code).enumerate() {
-        let is_selected_line = selected_line.map_or(false, |sel| line_index + 1 == sel);
-        
-        if is_selected_line {
-            job.append(
-                "",
-                0.0,
-                egui::TextFormat {
-                    background: egui::Color32::from_rgba_unmultiplied(60, 60, 60, 255),
-                    ..Default::default()
-                },
-            );
-        }
-
-        if let Some(search_text) = search_highlight {
-            let mut last_end = 0;
-            for (start, end) in find_all_occurrences(line, search_text) {
-                let abs_start = absolute_position + start;
-                let abs_end = absolute_position + end;
-                
-                // Only highlight if this is the selected match position
-                let should_highlight = selected_match_position
-                    .map_or(false, |(sel_start, sel_end)| {
-                        abs_start == sel_start && abs_end == sel_end
-                    });
-
-                // Add non-highlighted text before match
-                if start > last_end {
-                    for (style, text) in highlighter.highlight_line(&line[last_end..start], syntax_set).unwrap() {
-                        job.append(text, 0.0, style_to_text_format(style));
-                    }
-                }
-
-                // Add highlighted or normal text for the match
-                if should_highlight {
-                    let highlight_format = egui::TextFormat {
-                        background: egui::Color32::from_rgba_unmultiplied(255, 215, 0, 100),
-                        ..Default::default()
-                    };
-                    job.append(&line[start..end], 0.0, highlight_format);
-                } else {
-                    for (style, text) in highlighter.highlight_line(&line[start..end], syntax_set).unwrap() {
-                        job.append(text, 0.0, style_to_text_format(style));
-                    }
-                }
-
-                last_end = end;
-            }
-
-            // Add remaining non-highlighted text
-            if last_end < line.len() {
-                for (style, text) in highlighter.highlight_line(&line[last_end..], syntax_set).unwrap() {
-                    job.append(text, 0.0, style_to_text_format(style));
-                }
-            }
-        } else {
-            // No search highlight, just apply syntax highlighting
-            for (style, text) in highlighter.highlight_line(line, syntax_set).unwrap() {
-                job.append(text, 0.0, style_to_text_format(style));
-            }
-        }
-        
-        absolute_position += line.len();
-    }
-
-    if search_highlight.is_none() && selected_line.is_none() && selected_match_position.is_none() {
-        let cache_key = (current_syntax.to_string(), code.to_string());
-        cache.jobs.put(cache_key, job.clone());
-    }
-
-    job
-}
-
-fn find_all_occurrences(text: &str, pattern: &str) -> Vec<(usize, usize)> {
-    let mut results = Vec::new();
-    let mut start = 0;
-    while let Some(pos) = text[start..].find(pattern) {
-        let absolute_pos = start + pos;
-        results.push((absolute_pos, absolute_pos + pattern.len()));
-        start = absolute_pos + 1;
-    }
-    results
-}
-
-fn calculate_line_column(text: &str, position: usize) -> (usize, usize) {
-    let lines = text[..position].split('\n');
-    let line = lines.count();
-    let last_line = text[..position].lines().last().unwrap_or("");
-    let column = last_line.len() + 1;
-    (line, column)
-}
-
-fn style_to_text_format(style: Style) -> egui::TextFormat {
-    let color = egui::Color32::from_rgb(style.foreground.r, style.foreground.g, style.foreground.b);
-    egui::TextFormat {
-        color,
-        ..egui::TextFormat::default()
-    }
-}
\ No newline at end of file
@@ -49,6 +49,19 @@ impl ConsolePanel {
         self.terminal.handle_keyboard_shortcuts(ctx);
     }
 
+    /// Sets the color scheme the terminal highlights code in its output with.
+    /// 
+    /// # Arguments
+    /// - `name`: The name of the color scheme.
+    pub fn set_color_scheme(&mut self, name: &str) {
+        self.terminal.set_color_scheme(name);
+    }
+
+    /// Reloads the terminal's color schemes, e.g. after one was imported.
+    pub fn reload_color_schemes(&mut self) {
+        self.terminal.reload_color_schemes();
+    }
+
     /// Logs a message to the terminal output.
     /// 
     /// # Arguments
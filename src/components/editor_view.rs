@@ -0,0 +1,868 @@
+use eframe::egui::{self, Color32, Event, Key, Modifiers, Pos2, Rect, Sense, Stroke, Vec2};
+use std::ops::Range;
+use std::sync::Arc;
+use crate::components::code_editor::Buffer;
+use crate::components::gutter::Gutter;
+use crate::core::line_commands;
+use crate::core::selection::{Selection, Selections};
+use crate::core::smart_edit::{self, EditorSettings};
+
+const TEXT_MARGIN: f32 = 4.0;
+
+/// Builds the layout job for a line from the buffer, the line index and the line
+/// text without its ending. Only called for lines that are about to be shown.
+pub type LineLayouter<'l> = dyn FnMut(&Buffer, usize, &str) -> egui::text::LayoutJob + 'l;
+
+/// A line-based code editor widget.
+///
+/// Unlike `egui::TextEdit`, only the lines inside the visible viewport are laid
+/// out and painted, and every edit is applied through [`Buffer`] so it ends up in
+/// the buffer's history.
+pub struct EditorView<'a> {
+    buffer: &'a mut Buffer,
+    id: egui::Id,
+    search_selected_line: Option<usize>,
+    selected_match: Option<(usize, usize)>,
+    find_matches: &'a [Range<usize>],
+    gutter: Option<Gutter<'a>>,
+    settings: EditorSettings,
+}
+
+/// Screen-space layout of the text area for the current frame.
+struct TextArea {
+    origin: Pos2,
+    row_height: f32,
+    char_width: f32,
+    gutter_width: f32,
+    /// The gutter's screen rect, pinned to the left edge of the viewport.
+    gutter_rect: Rect,
+    lines: LineMap,
+}
+
+/// Maps buffer lines to the rows they are shown on, skipping folded lines.
+struct LineMap {
+    /// Hidden lines, sorted and non-overlapping.
+    hidden: Vec<Range<usize>>,
+    line_count: usize,
+}
+
+impl LineMap {
+    fn new(buffer: &Buffer) -> Self {
+        Self {
+            hidden: buffer.folding().hidden_ranges(),
+            line_count: buffer.line_count(),
+        }
+    }
+
+    fn row_count(&self) -> usize {
+        self.line_count - self.hidden.iter().map(|range| range.len()).sum::<usize>()
+    }
+
+    /// The hidden range that contains `line`, if it is folded away.
+    fn hidden_range(&self, line: usize) -> Option<Range<usize>> {
+        self.hidden.iter().find(|range| range.contains(&line)).cloned()
+    }
+
+    /// Row of `line`; hidden lines map to the row of the fold that hides them.
+    fn line_to_row(&self, line: usize) -> usize {
+        let line = self.hidden_range(line).map_or(line, |range| range.start - 1);
+        line - self.hidden
+            .iter()
+            .take_while(|range| range.start <= line)
+            .map(|range| range.len())
+            .sum::<usize>()
+    }
+
+    fn row_to_line(&self, row: usize) -> usize {
+        let mut line = row;
+        for range in &self.hidden {
+            if range.start > line {
+                break;
+            }
+            line += range.len();
+        }
+        line.min(self.line_count.saturating_sub(1))
+    }
+}
+
+impl TextArea {
+    fn line_top(&self, line_index: usize) -> f32 {
+        self.origin.y + self.lines.line_to_row(line_index) as f32 * self.row_height
+    }
+
+    fn text_left(&self) -> f32 {
+        self.origin.x + self.gutter_width + TEXT_MARGIN
+    }
+
+    fn line_at(&self, y: f32) -> usize {
+        let row = ((y - self.origin.y) / self.row_height).floor().max(0.0) as usize;
+        self.lines.row_to_line(row)
+    }
+
+    /// Column under `x`, counting past the end of the line as if it were padded
+    /// with spaces. Used for column selection.
+    fn virtual_column_at(&self, x: f32) -> usize {
+        ((x - self.text_left()) / self.char_width).round().max(0.0) as usize
+    }
+}
+
+impl<'a> EditorView<'a> {
+    pub fn new(buffer: &'a mut Buffer, id: egui::Id) -> Self {
+        Self {
+            buffer,
+            id,
+            search_selected_line: None,
+            selected_match: None,
+            find_matches: &[],
+            gutter: None,
+            settings: EditorSettings::default(),
+        }
+    }
+
+    /// Indentation and auto-closing used while typing.
+    pub fn settings(mut self, settings: EditorSettings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    /// Shows line numbers and the markers of the gutter's providers.
+    pub fn gutter(mut self, gutter: Gutter<'a>) -> Self {
+        self.gutter = Some(gutter);
+        self
+    }
+
+    /// Highlights a 1-based line, e.g. the line of a search result.
+    pub fn search_selected_line(mut self, line: Option<usize>) -> Self {
+        self.search_selected_line = line;
+        self
+    }
+
+    /// Highlights an absolute byte range, e.g. the selected search match.
+    pub fn selected_match(mut self, byte_range: Option<(usize, usize)>) -> Self {
+        self.selected_match = byte_range;
+        self
+    }
+
+    /// Highlights every match of the find bar, given as sorted character ranges.
+    pub fn find_matches(mut self, matches: &'a [Range<usize>]) -> Self {
+        self.find_matches = matches;
+        self
+    }
+
+    pub fn show(
+        mut self,
+        ui: &mut egui::Ui,
+        max_height: f32,
+        line_layouter: &mut LineLayouter,
+    ) -> egui::Response {
+        let font_id = egui::TextStyle::Monospace.resolve(ui.style());
+        let row_height = ui.fonts(|f| f.row_height(&font_id));
+        let char_width = ui.fonts(|f| f.glyph_width(&font_id, 'M'));
+        let gutter_width = self.gutter
+            .as_ref()
+            .map_or(0.0, |gutter| gutter.width(self.buffer.line_count(), char_width, row_height));
+        let content_width = self.content_width(ui, char_width) + gutter_width;
+
+        // The cursor may have been moved into a folded block, e.g. by a search
+        self.buffer.update_fold_regions();
+        let cursor_line = self.buffer.char_to_line(self.buffer.selection().head);
+        self.buffer.folding_mut().reveal_line(cursor_line);
+
+        egui::ScrollArea::both()
+            .id_source(self.id.with("scroll_area"))
+            .auto_shrink([false; 2])
+            .max_height(max_height)
+            .show_viewport(ui, |ui, viewport| {
+                let lines = LineMap::new(self.buffer);
+                let desired_size = Vec2::new(
+                    content_width.max(ui.available_width()),
+                    (lines.row_count() as f32 * row_height + max_height * 0.5).max(ui.available_height()),
+                );
+                let (rect, _) = ui.allocate_exact_size(desired_size, Sense::hover());
+                let response = ui.interact(rect, self.id, Sense::click_and_drag());
+                let visible = viewport.translate(rect.min.to_vec2());
+                let area = TextArea {
+                    origin: rect.min,
+                    row_height,
+                    char_width,
+                    gutter_width,
+                    gutter_rect: Rect::from_min_size(visible.min, Vec2::new(gutter_width, visible.height())),
+                    lines,
+                };
+
+                self.handle_pointer(ui, &response, &area, line_layouter);
+
+                let has_focus = ui.memory(|m| m.has_focus(self.id));
+                if has_focus {
+                    ui.memory_mut(|m| {
+                        m.set_focus_lock_filter(
+                            self.id,
+                            egui::EventFilter {
+                                tab: true,
+                                horizontal_arrows: true,
+                                vertical_arrows: true,
+                                escape: false,
+                            },
+                        )
+                    });
+                    let page_rows = ((viewport.height() / row_height) as usize).max(1);
+                    self.handle_keyboard(ui, &area.lines, page_rows);
+                }
+
+                if self.buffer.take_scroll_to_cursor() {
+                    let caret = self.caret_rect(ui, &area, line_layouter);
+                    ui.scroll_to_rect(caret.expand2(Vec2::new(char_width * 4.0, row_height)), None);
+                }
+
+                // Folds may have changed while handling input
+                let area = TextArea { lines: LineMap::new(self.buffer), ..area };
+                self.paint(ui, rect, viewport, &area, has_focus, line_layouter);
+                response
+            })
+            .inner
+    }
+
+    /// Width of the widest line, recomputed only when the buffer changes.
+    fn content_width(&self, ui: &egui::Ui, char_width: f32) -> f32 {
+        let cache_id = self.id.with("content_width");
+        let revision = self.buffer.revision();
+        let cached = ui.data(|d| d.get_temp::<(u64, u64, usize)>(cache_id));
+        let widest = match cached {
+            Some((buffer_id, cached_revision, widest))
+                if buffer_id == self.buffer.id() && cached_revision == revision => widest,
+            _ => {
+                let widest = self.buffer.lines().map(|line| line.chars().count()).max().unwrap_or(0);
+                ui.data_mut(|d| d.insert_temp(cache_id, (self.buffer.id(), revision, widest)));
+                widest
+            }
+        };
+        (widest + 4) as f32 * char_width + TEXT_MARGIN * 2.0
+    }
+
+    fn layout_line(
+        &self,
+        ui: &egui::Ui,
+        line_index: usize,
+        line_layouter: &mut LineLayouter,
+    ) -> Arc<egui::Galley> {
+        let line = self.buffer.line(line_index);
+        let job = line_layouter(self.buffer, line_index, &line);
+        ui.fonts(|f| f.layout_job(job))
+    }
+
+    fn char_index_at(
+        &self,
+        ui: &egui::Ui,
+        area: &TextArea,
+        pos: Pos2,
+        line_layouter: &mut LineLayouter,
+    ) -> usize {
+        let line_index = area.line_at(pos.y).min(self.buffer.line_count().saturating_sub(1));
+        let galley = self.layout_line(ui, line_index, line_layouter);
+        let cursor = galley.cursor_from_pos(Vec2::new(pos.x - area.text_left(), 0.0));
+        self.buffer.char_index(line_index, cursor.ccursor.index)
+    }
+
+    /// Screen x position of a column within a laid-out line.
+    fn column_x(galley: &egui::Galley, area: &TextArea, column: usize) -> f32 {
+        let cursor = galley.from_ccursor(egui::text::CCursor::new(column));
+        area.text_left() + galley.pos_from_cursor(&cursor).min.x
+    }
+
+    fn caret_rect(
+        &self,
+        ui: &egui::Ui,
+        area: &TextArea,
+        line_layouter: &mut LineLayouter,
+    ) -> Rect {
+        let (line, column) = self.buffer.line_column(self.buffer.selection().head);
+        let galley = self.layout_line(ui, line, line_layouter);
+        let x = Self::column_x(&galley, area, column);
+        let top = area.line_top(line);
+        Rect::from_min_max(Pos2::new(x, top), Pos2::new(x + 1.0, top + area.row_height))
+    }
+
+    fn handle_pointer(
+        &mut self,
+        ui: &egui::Ui,
+        response: &egui::Response,
+        area: &TextArea,
+        line_layouter: &mut LineLayouter,
+    ) {
+        let hover_pos = ui.input(|i| i.pointer.hover_pos());
+        if response.hovered() && !hover_pos.is_some_and(|pos| area.gutter_rect.contains(pos)) {
+            ui.ctx().set_cursor_icon(egui::CursorIcon::Text);
+        }
+        if response.clicked_elsewhere() && ui.memory(|m| m.has_focus(self.id)) {
+            ui.memory_mut(|m| m.surrender_focus(self.id));
+        }
+
+        // Presses that start on the gutter go to its providers, not the text
+        let press_origin = ui.input(|i| i.pointer.press_origin());
+        if press_origin.is_some_and(|pos| area.gutter_rect.contains(pos)) {
+            if let (Some(gutter), Some(pos)) = (self.gutter.as_mut(), response.interact_pointer_pos()) {
+                let line = area.line_at(pos.y);
+                let button = if response.secondary_clicked() {
+                    Some(egui::PointerButton::Secondary)
+                } else if response.clicked() {
+                    Some(egui::PointerButton::Primary)
+                } else {
+                    None
+                };
+                let on_fold_marker = gutter.fold_lane(area.gutter_rect, area.row_height).contains(&pos.x)
+                    && self.buffer.folding().region_at(line).is_some();
+                match button.filter(|_| line < self.buffer.line_count()) {
+                    Some(egui::PointerButton::Primary) if on_fold_marker => {
+                        self.buffer.folding_mut().toggle(line);
+                        move_out_of_folds(self.buffer);
+                    }
+                    Some(button) => gutter.click(self.buffer, line, button),
+                    None => {}
+                }
+            }
+            return;
+        }
+
+        let Some(pointer_pos) = response.interact_pointer_pos() else {
+            return;
+        };
+        let index = self.char_index_at(ui, area, pointer_pos, line_layouter);
+        let (pressed, modifiers) = ui.input(|i| (i.pointer.primary_pressed(), i.modifiers));
+        let box_anchor_id = self.id.with("box_selection_anchor");
+
+        if response.triple_clicked() {
+            let line = self.buffer.char_to_line(index);
+            let start = self.buffer.line_to_char(line);
+            let end = self.buffer.line_to_char(line + 1);
+            self.buffer.set_selection(Selection::new(start, end));
+        } else if response.double_clicked() {
+            let (start, end) = word_range_at(self.buffer, index);
+            if modifiers.alt {
+                self.buffer.add_selection(Selection::new(start, end));
+            } else {
+                self.buffer.set_selection(Selection::new(start, end));
+            }
+        } else if pressed && response.contains_pointer() {
+            ui.memory_mut(|m| m.request_focus(self.id));
+            self.buffer.seal_history();
+            // Clicking the placeholder after a folded line unfolds it
+            let line = area.line_at(pointer_pos.y);
+            if self.buffer.folding().is_folded(line) {
+                let line_end = area.text_left() + self.layout_line(ui, line, line_layouter).size().x;
+                if pointer_pos.x >= line_end {
+                    self.buffer.folding_mut().unfold(line);
+                    return;
+                }
+            }
+            if modifiers.alt {
+                // Alt+click adds a cursor; dragging from here selects a column
+                let anchor = self.virtual_position(area, pointer_pos);
+                ui.data_mut(|d| d.insert_temp(box_anchor_id, anchor));
+                self.buffer.add_selection(Selection::caret(index));
+            } else {
+                ui.data_mut(|d| d.remove::<(usize, usize)>(box_anchor_id));
+                let anchor = if modifiers.shift { self.buffer.selection().anchor } else { index };
+                self.buffer.set_selection(Selection::new(anchor, index));
+            }
+        } else if response.dragged() {
+            match ui.data(|d| d.get_temp::<(usize, usize)>(box_anchor_id)) {
+                Some(anchor) => self.select_column(anchor, self.virtual_position(area, pointer_pos)),
+                None => {
+                    let anchor = self.buffer.selection().anchor;
+                    self.buffer.set_selection(Selection::new(anchor, index));
+                }
+            }
+        }
+    }
+
+    /// Line and virtual column under `pos`.
+    fn virtual_position(&self, area: &TextArea, pos: Pos2) -> (usize, usize) {
+        let line = area.line_at(pos.y).min(self.buffer.line_count().saturating_sub(1));
+        (line, area.virtual_column_at(pos.x))
+    }
+
+    /// Selects the rectangle between two `(line, column)` corners, one selection
+    /// per line. Lines shorter than the rectangle get a cursor at their end.
+    fn select_column(&mut self, anchor: (usize, usize), head: (usize, usize)) {
+        let (anchor_line, anchor_column) = anchor;
+        let (head_line, head_column) = head;
+        let lines = anchor_line.min(head_line)..=anchor_line.max(head_line);
+        let ranges: Vec<Selection> = lines
+            .map(|line| {
+                Selection::new(
+                    self.buffer.char_index(line, anchor_column),
+                    self.buffer.char_index(line, head_column),
+                )
+            })
+            .collect();
+        // The line under the pointer is primary so the view follows it
+        let primary = if head_line >= anchor_line { ranges.len() - 1 } else { 0 };
+        self.buffer.set_selections(Selections::new(ranges, primary));
+    }
+
+    fn handle_keyboard(&mut self, ui: &egui::Ui, lines: &LineMap, page_rows: usize) {
+        let events = ui.input(|i| i.events.clone());
+        for event in &events {
+            let has_selected_text = self.buffer.selections().iter().any(|selection| !selection.is_empty());
+            match event {
+                Event::Text(text) if !text.is_empty() && text != "\n" && text != "\r" => {
+                    let handled = smart_edit::type_text(self.buffer, &self.settings, text);
+                    if !handled {
+                        self.buffer.insert_at_cursor(text);
+                    }
+                }
+                Event::Paste(text) => {
+                    self.buffer.seal_history();
+                    self.paste(&text.replace("\r\n", "\n"));
+                    self.buffer.seal_history();
+                }
+                Event::Ime(egui::ImeEvent::Commit(text)) if !text.is_empty() => {
+                    self.buffer.insert_at_cursor(text);
+                }
+                Event::Copy if has_selected_text => {
+                    ui.ctx().copy_text(self.buffer.selected_text());
+                }
+                Event::Cut if has_selected_text => {
+                    ui.ctx().copy_text(self.buffer.selected_text());
+                    self.buffer.seal_history();
+                    self.buffer.insert_at_cursor("");
+                }
+                Event::Key { key, pressed: true, modifiers, .. } => {
+                    self.handle_key(*key, *modifiers, lines, page_rows);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Pastes `text`, giving each cursor one line of it when the line count
+    /// matches the number of cursors (e.g. text copied from a column selection).
+    fn paste(&mut self, text: &str) {
+        let lines: Vec<&str> = text.split('\n').collect();
+        if self.buffer.selections().is_multiple() && lines.len() == self.buffer.selections().count() {
+            self.buffer.replace_selections(|index, _| lines[index].to_string());
+        } else {
+            self.buffer.insert_at_cursor(text);
+        }
+    }
+
+    fn handle_key(&mut self, key: Key, modifiers: Modifiers, lines: &LineMap, page_rows: usize) {
+        let buffer = &mut *self.buffer;
+        let settings = &self.settings;
+        let by_word = modifiers.command;
+        let extend = modifiers.shift;
+
+        match key {
+            Key::Enter => smart_edit::insert_newline(buffer, settings),
+            Key::Tab if modifiers.shift => line_commands::dedent(buffer, settings),
+            Key::Tab => line_commands::indent(buffer, settings),
+            Key::Backspace => {
+                buffer.map_selections(|buffer, selection| {
+                    if !selection.is_empty() {
+                        return selection;
+                    }
+                    let head = selection.head;
+                    let start = if by_word { word_start_before(buffer, head) } else { head.saturating_sub(1) };
+                    Selection::new(head, start)
+                });
+                buffer.insert_at_cursor("");
+            }
+            Key::Delete => {
+                buffer.map_selections(|buffer, selection| {
+                    if !selection.is_empty() {
+                        return selection;
+                    }
+                    let head = selection.head;
+                    let end = if by_word { word_end_after(buffer, head) } else { (head + 1).min(buffer.len_chars()) };
+                    Selection::new(head, end)
+                });
+                buffer.insert_at_cursor("");
+            }
+            Key::ArrowLeft => move_selections(buffer, lines, extend, |buffer, selection| {
+                if !extend && !selection.is_empty() {
+                    selection.start()
+                } else if by_word {
+                    word_start_before(buffer, selection.head)
+                } else {
+                    selection.head.saturating_sub(1)
+                }
+            }),
+            Key::ArrowRight => move_selections(buffer, lines, extend, |buffer, selection| {
+                if !extend && !selection.is_empty() {
+                    selection.end()
+                } else if by_word {
+                    word_end_after(buffer, selection.head)
+                } else {
+                    (selection.head + 1).min(buffer.len_chars())
+                }
+            }),
+            Key::ArrowUp if modifiers.command && modifiers.alt => add_cursor_vertically(buffer, -1),
+            Key::ArrowDown if modifiers.command && modifiers.alt => add_cursor_vertically(buffer, 1),
+            Key::ArrowUp => move_vertically(buffer, lines, -1, extend),
+            Key::ArrowDown => move_vertically(buffer, lines, 1, extend),
+            Key::PageUp => move_vertically(buffer, lines, -(page_rows as isize), extend),
+            Key::PageDown => move_vertically(buffer, lines, page_rows as isize, extend),
+            Key::Home => move_selections(buffer, lines, extend, |buffer, selection| {
+                if modifiers.command {
+                    0
+                } else {
+                    smart_home(buffer, selection.head)
+                }
+            }),
+            Key::End => move_selections(buffer, lines, extend, |buffer, selection| {
+                if modifiers.command {
+                    buffer.len_chars()
+                } else {
+                    let line = buffer.char_to_line(selection.head);
+                    buffer.char_index(line, usize::MAX)
+                }
+            }),
+            Key::A if modifiers.command => {
+                let len = buffer.len_chars();
+                buffer.set_selection(Selection::new(0, len));
+            }
+            Key::D if modifiers.command => add_next_occurrence(buffer),
+            Key::OpenBracket if modifiers.command && modifiers.alt => {
+                let line = buffer.char_to_line(buffer.selection().head);
+                let region = buffer.folding()
+                    .region_at(line)
+                    .filter(|region| !buffer.folding().is_folded(region.start_line))
+                    .or_else(|| buffer.folding().innermost_containing(line));
+                if let Some(region) = region {
+                    buffer.folding_mut().fold(region.start_line);
+                    move_out_of_folds(buffer);
+                }
+            }
+            Key::CloseBracket if modifiers.command && modifiers.alt => {
+                let line = buffer.char_to_line(buffer.selection().head);
+                if buffer.folding().is_folded(line) {
+                    buffer.folding_mut().unfold(line);
+                }
+            }
+            Key::Minus if modifiers.command && modifiers.alt => {
+                buffer.folding_mut().fold_all();
+                move_out_of_folds(buffer);
+            }
+            Key::Equals if modifiers.command && modifiers.alt => buffer.folding_mut().unfold_all(),
+            Key::Escape => {
+                let primary = buffer.selection();
+                if buffer.selections().is_multiple() {
+                    buffer.set_selection(primary);
+                } else {
+                    buffer.set_selection(Selection::caret(primary.head));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn paint(
+        &mut self,
+        ui: &egui::Ui,
+        rect: Rect,
+        viewport: Rect,
+        area: &TextArea,
+        has_focus: bool,
+        line_layouter: &mut LineLayouter,
+    ) {
+        let painter = ui.painter();
+        let visuals = ui.visuals();
+        let visible = viewport.translate(rect.min.to_vec2());
+        let text_clip = Rect::from_min_max(Pos2::new(area.gutter_rect.right(), visible.top()), visible.max);
+        painter.rect_filled(visible, 0.0, visuals.extreme_bg_color);
+
+        let row_count = area.lines.row_count();
+        let first_row = (viewport.min.y / area.row_height).floor().max(0.0) as usize;
+        let last_row = ((viewport.max.y / area.row_height).ceil() as usize + 1).min(row_count);
+        let visible_lines: Vec<usize> = (first_row..last_row).map(|row| area.lines.row_to_line(row)).collect();
+        let painter = painter.with_clip_rect(text_clip.intersect(ui.clip_rect()));
+
+        let selections = self.buffer.selections();
+        let primary = selections.primary();
+        let selected_match = self.selected_match.map(|(start, end)| {
+            self.buffer.byte_to_char(start)..self.buffer.byte_to_char(end)
+        });
+
+        for &line_index in &visible_lines {
+            let top = area.line_top(line_index);
+            let line_rect = Rect::from_min_max(
+                Pos2::new(visible.left(), top),
+                Pos2::new(visible.right(), top + area.row_height),
+            );
+            let galley = self.layout_line(ui, line_index, line_layouter);
+            let line_start = self.buffer.line_to_char(line_index);
+            let line_len = galley.job.text.chars().count();
+            let line_end = line_start + line_len;
+            let line_selections: Vec<Selection> = selections
+                .iter()
+                .skip(selections.first_ending_at_or_after(line_start))
+                .take_while(|selection| selection.start() <= line_end)
+                .copied()
+                .collect();
+            let has_caret = line_selections
+                .iter()
+                .any(|selection| selection.is_empty() && selection.head >= line_start);
+
+            if self.search_selected_line == Some(line_index + 1) {
+                painter.rect_filled(line_rect, 0.0, visuals.selection.bg_fill.gamma_multiply(0.5));
+            } else if has_caret {
+                painter.rect_filled(line_rect, 0.0, visuals.faint_bg_color);
+            }
+
+            let paint_range = |range: std::ops::Range<usize>, color: Color32, past_line_end: bool| {
+                let start = range.start.clamp(line_start, line_end) - line_start;
+                let end = range.end.clamp(line_start, line_end) - line_start;
+                let x_start = Self::column_x(&galley, area, start);
+                let mut x_end = Self::column_x(&galley, area, end);
+                if past_line_end {
+                    x_end += area.row_height * 0.4;
+                }
+                if x_end > x_start {
+                    let rect = Rect::from_min_max(Pos2::new(x_start, top), Pos2::new(x_end, top + area.row_height));
+                    painter.rect_filled(rect, 0.0, color);
+                }
+            };
+
+            let first_match = self.find_matches.partition_point(|range| range.end < line_start);
+            for range in self.find_matches[first_match..].iter().take_while(|range| range.start <= line_end) {
+                paint_range(range.clone(), Color32::from_rgba_unmultiplied(255, 215, 0, 40), range.end > line_end);
+            }
+            for selection in line_selections.iter().filter(|selection| !selection.is_empty()) {
+                paint_range(selection.range(), visuals.selection.bg_fill, selection.end() > line_end);
+            }
+            if let Some(range) = selected_match.clone() {
+                if range.start <= line_end && range.end >= line_start {
+                    paint_range(range, Color32::from_rgba_unmultiplied(255, 215, 0, 100), false);
+                }
+            }
+
+            painter.galley(Pos2::new(area.text_left(), top), galley.clone(), visuals.text_color());
+
+            if self.buffer.folding().is_folded(line_index) {
+                let placeholder = Rect::from_min_size(
+                    Pos2::new(area.text_left() + galley.size().x + area.char_width * 0.5, top + 1.0),
+                    Vec2::new(area.char_width * 3.0, area.row_height - 2.0),
+                );
+                painter.rect_filled(placeholder, 3.0, visuals.widgets.inactive.bg_fill);
+                painter.text(
+                    placeholder.center(),
+                    egui::Align2::CENTER_CENTER,
+                    "⋯",
+                    egui::TextStyle::Monospace.resolve(ui.style()),
+                    visuals.weak_text_color(),
+                );
+            }
+
+            if !has_focus {
+                continue;
+            }
+            let carets = line_selections
+                .iter()
+                .filter(|selection| (line_start..=line_end).contains(&selection.head));
+            for selection in carets {
+                let x = Self::column_x(&galley, area, selection.head - line_start);
+                let caret = Rect::from_min_max(Pos2::new(x, top), Pos2::new(x, top + area.row_height));
+                painter.line_segment([caret.center_top(), caret.center_bottom()], Stroke::new(2.0, visuals.text_cursor.stroke.color));
+                if *selection == primary {
+                    ui.output_mut(|o| {
+                        o.ime = Some(egui::output::IMEOutput { rect: visible, cursor_rect: caret });
+                    });
+                }
+            }
+        }
+
+        if let Some(gutter) = self.gutter.as_mut() {
+            let line_top = |line_index: usize| area.line_top(line_index);
+            let folding = self.buffer.folding();
+            let lines: Vec<(usize, Option<bool>)> = visible_lines
+                .iter()
+                .map(|&line| (line, folding.region_at(line).map(|_| folding.is_folded(line))))
+                .collect();
+            gutter.paint(ui, self.buffer, area.gutter_rect, &lines, &line_top, area.row_height);
+        }
+    }
+}
+
+/// Moves the head of every selection to the position `target` returns for it,
+/// extending the selection instead of collapsing it when `extend` is set.
+fn move_selections(buffer: &mut Buffer, lines: &LineMap, extend: bool, mut target: impl FnMut(&Buffer, Selection) -> usize) {
+    buffer.seal_history();
+    buffer.map_selections(|buffer, selection| {
+        let head = skip_folded(buffer, lines, target(buffer, selection), selection.head);
+        Selection {
+            anchor: if extend { selection.anchor } else { head },
+            head,
+            preferred_column: None,
+        }
+    });
+}
+
+/// Moves every cursor by `rows` visible rows, skipping folded lines.
+fn move_vertically(buffer: &mut Buffer, lines: &LineMap, rows: isize, extend: bool) {
+    buffer.seal_history();
+    buffer.map_selections(|buffer, selection| {
+        let (line, column) = buffer.line_column(selection.head);
+        let preferred_column = selection.preferred_column.unwrap_or(column);
+        let last_row = lines.row_count().saturating_sub(1) as isize;
+        let target_row = lines.line_to_row(line) as isize + rows;
+
+        let head = if target_row < 0 {
+            0
+        } else if target_row > last_row {
+            buffer.len_chars()
+        } else {
+            buffer.char_index(lines.row_to_line(target_row as usize), preferred_column)
+        };
+        Selection {
+            anchor: if extend { selection.anchor } else { head },
+            head,
+            preferred_column: Some(preferred_column),
+        }
+    });
+}
+
+/// Keeps a horizontal move from landing inside a folded block: moving forwards
+/// jumps past the block, moving backwards stops at the end of the fold's first line.
+fn skip_folded(buffer: &Buffer, lines: &LineMap, target: usize, from: usize) -> usize {
+    let Some(hidden) = lines.hidden_range(buffer.char_to_line(target)) else {
+        return target;
+    };
+    if target >= from {
+        buffer.line_to_char(hidden.end).min(buffer.len_chars())
+    } else {
+        buffer.char_index(hidden.start - 1, usize::MAX)
+    }
+}
+
+/// Moves cursors that ended up inside a block that was just folded to the end
+/// of the fold's first line.
+fn move_out_of_folds(buffer: &mut Buffer) {
+    let lines = LineMap::new(buffer);
+    buffer.map_selections(|buffer, selection| match lines.hidden_range(buffer.char_to_line(selection.head)) {
+        Some(hidden) => Selection::caret(buffer.char_index(hidden.start - 1, usize::MAX)),
+        None => selection,
+    });
+}
+
+/// Adds a cursor on the line above the topmost cursor (or below the bottommost
+/// one), keeping its column.
+fn add_cursor_vertically(buffer: &mut Buffer, direction: isize) {
+    let edge = if direction < 0 {
+        buffer.selections().iter().next()
+    } else {
+        buffer.selections().iter().next_back()
+    };
+    let Some(edge) = edge.copied() else {
+        return;
+    };
+    let (line, column) = buffer.line_column(edge.head);
+    let target_line = line as isize + direction;
+    if target_line < 0 || target_line >= buffer.line_count() as isize {
+        return;
+    }
+    let preferred_column = edge.preferred_column.unwrap_or(column);
+    let head = buffer.char_index(target_line as usize, preferred_column);
+    buffer.add_selection(Selection {
+        anchor: head,
+        head,
+        preferred_column: Some(preferred_column),
+    });
+}
+
+/// Selects the word under the primary cursor or, when the primary selection is
+/// not empty, adds a selection at the next occurrence of its text.
+fn add_next_occurrence(buffer: &mut Buffer) {
+    let primary = buffer.selection();
+    if primary.is_empty() {
+        let (start, end) = word_range_at(buffer, primary.head);
+        if start < end {
+            buffer.map_selections(|_, selection| if selection == primary { Selection::new(start, end) } else { selection });
+        }
+        return;
+    }
+
+    let pattern = buffer.slice(primary.range());
+    let pattern_len = pattern.chars().count();
+    let mut from = primary.end();
+    for _ in 0..buffer.selections().count() {
+        let Some(start) = buffer.find_next(&pattern, from) else {
+            return;
+        };
+        let end = start + pattern_len;
+        let already_selected = buffer.selections().iter().any(|selection| selection.range() == (start..end));
+        if !already_selected {
+            buffer.add_selection(Selection::new(start, end));
+            return;
+        }
+        from = end;
+    }
+}
+
+/// Toggles between the first non-whitespace character of the line and its start.
+fn smart_home(buffer: &Buffer, head: usize) -> usize {
+    let (line, column) = buffer.line_column(head);
+    let indent = buffer.line(line).chars().take_while(|c| c.is_whitespace()).count();
+    let line_start = buffer.line_to_char(line);
+    if column == indent {
+        line_start
+    } else {
+        line_start + indent
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn word_start_before(buffer: &Buffer, index: usize) -> usize {
+    let mut position = index;
+    while position > 0 && buffer.char_at(position - 1).is_some_and(|c| c.is_whitespace() && c != '\n') {
+        position -= 1;
+    }
+    let Some(kind) = buffer.char_at(position.saturating_sub(1)).map(is_word_char) else {
+        return position;
+    };
+    if position == index && position > 0 && buffer.char_at(position - 1) == Some('\n') {
+        return position - 1;
+    }
+    while position > 0 && buffer.char_at(position - 1).is_some_and(|c| is_word_char(c) == kind && !c.is_whitespace()) {
+        position -= 1;
+    }
+    position
+}
+
+fn word_end_after(buffer: &Buffer, index: usize) -> usize {
+    let len = buffer.len_chars();
+    let mut position = index;
+    while position < len && buffer.char_at(position).is_some_and(|c| c.is_whitespace() && c != '\n') {
+        position += 1;
+    }
+    if position == index && buffer.char_at(position) == Some('\n') {
+        return position + 1;
+    }
+    let Some(kind) = buffer.char_at(position).map(is_word_char) else {
+        return position;
+    };
+    while position < len && buffer.char_at(position).is_some_and(|c| is_word_char(c) == kind && !c.is_whitespace()) {
+        position += 1;
+    }
+    position
+}
+
+/// Range of the word (or run of punctuation) touching `index`.
+pub fn word_range_at(buffer: &Buffer, index: usize) -> (usize, usize) {
+    let len = buffer.len_chars();
+    let Some(kind) = buffer.char_at(index.min(len.saturating_sub(1))).map(is_word_char) else {
+        return (index, index);
+    };
+    let same_kind = |c: char| is_word_char(c) == kind && c != '\n' && (kind || !c.is_whitespace());
+    let mut start = index.min(len);
+    while start > 0 && buffer.char_at(start - 1).is_some_and(same_kind) {
+        start -= 1;
+    }
+    let mut end = index.min(len);
+    while end < len && buffer.char_at(end).is_some_and(same_kind) {
+        end += 1;
+    }
+    (start, end)
+}
@@ -1,26 +1,107 @@
 use eframe::egui;
 use std::path::{PathBuf, Path};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::io;
 use std::rc::Rc;
 use rfd::FileDialog;
+use serde::{Deserialize, Serialize};
 use std::sync::atomic::{AtomicBool, Ordering};
+use crate::core::app_state::AppState;
+use crate::core::archive::ArchiveVfs;
+use crate::core::file_operations::{self, FileOperationKind, FileOperations, TrashSession};
 use crate::core::file_system::FileSystem;
+use crate::core::file_templates::{self, FileTemplate};
+use crate::core::git_status::{GitFileStatus, GitStatusTracker};
+use crate::core::project_files::ProjectFiles;
+use crate::core::vfs::Vfs;
+use crate::core::project_walker::ProjectWalker;
+use crate::components::ai_assistant::AIAssistant;
 use crate::components::code_editor::CodeEditor;
 
 pub struct FileModal {
     pub show: bool,
+    pub dock: TreeDock,
     pub file_system: Option<Rc<FileSystem>>,
     pub project_path: Option<PathBuf>,
     pub expanded_folders: HashSet<PathBuf>,
     pub selected_folder: Option<PathBuf>,
     pub selected_item: Option<PathBuf>,
+    /// Expanded folders of the other projects opened before, by project.
+    expanded_by_project: HashMap<PathBuf, Vec<PathBuf>>,
+    /// Only files whose path contains this are shown, with their folders.
+    pub filter: String,
+    /// What `filter` leaves in the tree, worked out again when either changes.
+    filter_matches: Option<HashSet<PathBuf>>,
+    /// The rows of the tree as last drawn, top to bottom.
+    rows: Vec<TreeRow>,
+    /// Whether the arrow keys move through the tree.
+    tree_focused: bool,
+    scroll_to_selected: bool,
+    /// Archives that were expanded in the tree, by path.
+    archives: HashMap<PathBuf, Rc<FileSystem<ArchiveVfs>>>,
+    /// Git status of the project, shown on the tree entries.
+    pub git_status: GitStatusTracker,
+    /// Files of the project, for the filter and the file finder.
+    pub project_files: ProjectFiles,
+    /// Undo history of the file operations done here.
+    pub file_operations: FileOperations,
+    /// Keeps other windows from clearing this one's trash.
+    _trash_session: Option<TrashSession>,
+    clipboard: Option<FileClipboard>,
+    /// A tree item dropped onto a folder, moved once the tree is drawn.
+    pending_move: Option<(PathBuf, PathBuf)>,
+    /// Folder under the pointer, where files dropped from outside go.
+    drop_folder: Option<PathBuf>,
     editing_item: Option<(PathBuf, String)>,
-    creating_item: Option<(PathBuf, String, bool)>,
+    creating_item: Option<(PathBuf, String, NewItem)>,
     context_menu: Option<ContextMenuState>,
     new_item_focus: bool,
     is_initializing: AtomicBool,
 }
 
+/// Where the project tree is shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum TreeDock {
+    #[default]
+    Left,
+    Right,
+    Floating,
+}
+
+/// What is being named in the tree before it is created.
+enum NewItem {
+    File,
+    Folder,
+    Template(FileTemplate),
+}
+
+/// A row of the tree, for moving through it with the keyboard.
+#[derive(Clone)]
+struct TreeRow {
+    path: PathBuf,
+    is_dir: bool,
+    /// A folder or an archive.
+    expandable: bool,
+    expanded: bool,
+}
+
+/// A file or folder that was cut or copied in the tree.
+#[derive(Clone)]
+struct FileClipboard {
+    path: PathBuf,
+    cut: bool,
+}
+
+/// Drag-and-drop payload of a tree item.
+struct DraggedPath(PathBuf);
+
+/// The parts of the IDE that tree entries act on when clicked.
+struct TreeContext<'a> {
+    code_editor: &'a mut CodeEditor,
+    log: &'a mut dyn FnMut(&str),
+    ai_assistant: &'a mut AIAssistant,
+}
+
 struct ContextMenuState {
     path: PathBuf,
     is_dir: bool,
@@ -29,13 +110,29 @@ struct ContextMenuState {
 
 impl FileModal {
     pub fn new() -> Self {
+        let (file_operations, trash_session) = Self::file_operations();
         Self {
-            show: false,
+            show: true,
+            dock: TreeDock::default(),
             file_system: None,
             project_path: None,
             expanded_folders: HashSet::new(),
             selected_folder: None,
             selected_item: None,
+            expanded_by_project: HashMap::new(),
+            filter: String::new(),
+            filter_matches: None,
+            rows: Vec::new(),
+            tree_focused: false,
+            scroll_to_selected: false,
+            archives: HashMap::new(),
+            git_status: GitStatusTracker::new(),
+            project_files: ProjectFiles::new(),
+            file_operations,
+            _trash_session: trash_session,
+            clipboard: None,
+            pending_move: None,
+            drop_folder: None,
             editing_item: None,
             creating_item: None,
             context_menu: None,
@@ -44,89 +141,350 @@ impl FileModal {
         }
     }
 
+    /// File operations that delete into this window's own folder in the trash.
+    fn file_operations() -> (FileOperations, Option<TrashSession>) {
+        let trash_root = AppState::config_dir()
+            .map(|dir| dir.join("trash"))
+            .unwrap_or_else(|| std::env::temp_dir().join("zzz-trash"));
+        match TrashSession::start(&trash_root) {
+            Ok(session) => (FileOperations::new(session.dir().to_path_buf()), Some(session)),
+            Err(e) => {
+                eprintln!("Error setting up the trash in {}: {}", trash_root.display(), e);
+                (FileOperations::new(trash_root.join(std::process::id().to_string())), None)
+            }
+        }
+    }
+
     pub fn show(&mut self, ctx: &egui::Context, code_editor: &mut CodeEditor, log: &mut dyn FnMut(&str), ai_assistant: &mut crate::components::ai_assistant::AIAssistant) {
+        self.git_status.poll(ctx, self.project_path.as_deref());
+        if self.project_files.poll(ctx, self.project_path.as_deref()) {
+            self.filter_matches = None;
+        }
         if !self.show {
             return;
         }
 
-        let modal_size = egui::vec2(500.0, 500.0);
-        egui::Window::new("File Browser")
-            .fixed_size(modal_size)
-            .collapsible(false)
-            .resizable(false)
-            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
-            .show(ctx, |ui| {
-                ui.set_min_size(modal_size);
-                
-                ui.vertical(|ui| {
-                    ui.heading("Files");
+        let tree_rect = match self.dock {
+            TreeDock::Left => Some(egui::SidePanel::left("project_tree")
+                .resizable(true)
+                .default_width(260.0)
+                .min_width(160.0)
+                .show(ctx, |ui| self.show_tree(ui, ctx, code_editor, log, ai_assistant))
+                .response
+                .rect),
+            TreeDock::Right => Some(egui::SidePanel::right("project_tree")
+                .resizable(true)
+                .default_width(260.0)
+                .min_width(160.0)
+                .show(ctx, |ui| self.show_tree(ui, ctx, code_editor, log, ai_assistant))
+                .response
+                .rect),
+            TreeDock::Floating => {
+                let mut open = true;
+                let window = egui::Window::new("File Browser")
+                    .open(&mut open)
+                    .default_size(egui::vec2(320.0, 500.0))
+                    .resizable(true)
+                    .collapsible(false)
+                    .show(ctx, |ui| self.show_tree(ui, ctx, code_editor, log, ai_assistant));
+                self.show = open;
+                window.map(|window| window.response.rect)
+            }
+        };
+
+        // Keys go to the tree until something outside it is clicked
+        if ctx.input(|i| i.pointer.any_pressed()) {
+            let pointer = ctx.input(|i| i.pointer.interact_pos());
+            if !pointer.zip(tree_rect).is_some_and(|(pointer, rect)| rect.contains(pointer)) {
+                self.tree_focused = false;
+            }
+        }
+
+        if let Some((from, folder)) = self.pending_move.take() {
+            self.move_into(&from, &folder, code_editor, log);
+        }
+        self.import_dropped_files(ctx, log);
+        self.handle_context_menu(ctx, code_editor, log);
+    }
+
+    fn show_tree(
+        &mut self,
+        ui: &mut egui::Ui,
+        ctx: &egui::Context,
+        code_editor: &mut CodeEditor,
+        log: &mut dyn FnMut(&str),
+        ai_assistant: &mut crate::components::ai_assistant::AIAssistant,
+    ) {
+        self.handle_tree_keys(ui, code_editor, log);
+        if !self.filter.is_empty() && self.filter_matches.is_none() {
+            self.filter_matches = Some(self.matching_paths());
+        }
 
-                    ui.horizontal(|ui| {
-                        if ui.button("Open Folder").clicked() {
-                            self.open_folder(log);
+        ui.vertical(|ui| {
+            ui.heading("Files");
+
+            ui.horizontal_wrapped(|ui| {
+                if ui.button("Open Folder").clicked() {
+                    self.open_folder(log);
+                }
+
+                if self.project_path.is_some() {
+                    if ui.button("New File").clicked() {
+                        if let Some(target_path) = self.selected_folder.as_ref().or(self.project_path.as_ref()).cloned() {
+                            self.start_create_item(NewItem::File, &target_path);
                         }
-                        
-                        if self.project_path.is_some() {
-                            if ui.button("New File").clicked() {
-                                let target_path = self.selected_folder.as_ref()
-                                    .or(self.project_path.as_ref())
-                                    .map(|p| p.clone())
-                                    .unwrap();
-                                self.start_create_item(false, &target_path);
-                            }
-                            if ui.button("New Folder").clicked() {
-                                let target_path = self.selected_folder.as_ref()
-                                    .or(self.project_path.as_ref())
-                                    .map(|p| p.clone())
-                                    .unwrap();
-                                self.start_create_item(true, &target_path);
-                            }
-                            if ui.button("Save").clicked() {
-                                self.save_current_file(code_editor, log);
-                            }
-                            if ui.button("Collapse All").clicked() {
-                                self.collapse_all_folders();
+                    }
+                    if ui.button("New Folder").clicked() {
+                        if let Some(target_path) = self.selected_folder.as_ref().or(self.project_path.as_ref()).cloned() {
+                            self.start_create_item(NewItem::Folder, &target_path);
+                        }
+                    }
+                    ui.menu_button("New…", |ui| {
+                        if let Some(target_path) = self.selected_folder.as_ref().or(self.project_path.as_ref()).cloned() {
+                            if self.template_menu(ui, &target_path) {
+                                ui.close_menu();
                             }
                         }
                     });
-                    
-                    ui.separator();
-
-                    if let (Some(fs), Some(project_path)) = (&self.file_system, &self.project_path) {
-                        let fs = fs.clone();
-                        let project_path = project_path.clone();
-                        egui::ScrollArea::vertical().show(ui, |ui| {
-                            ui.set_min_width(ui.available_width());
-                            self.render_folder_contents(
-                                ui, ctx, &project_path, &fs, code_editor,
-                                log, 0, ai_assistant,
-                            );
-                        });
-                    } else {
-                        ui.label("No project opened. Click 'Open Folder' to start.");
+                    if ui.button("Save").clicked() {
+                        self.save_current_file(code_editor, log);
+                    }
+                    if ui.button("Collapse All").clicked() {
+                        self.collapse_all_folders();
+                    }
+                    let active_file = code_editor.get_active_buffer().and_then(|buffer| buffer.file_path.clone());
+                    let reveal = ui.add_enabled(active_file.is_some(), egui::Button::new("Reveal"));
+                    if reveal.on_hover_text("Show the active file in the tree").clicked() {
+                        if let Some(file_path) = active_file {
+                            self.reveal(Path::new(&file_path));
+                        }
+                    }
+                    let undo_hint = self.file_operations.next_undo().map(|operation| format!("Undo {}", operation.description()));
+                    let undo = ui.add_enabled(undo_hint.is_some(), egui::Button::new("Undo"));
+                    if undo.on_hover_text(undo_hint.unwrap_or_else(|| "Nothing to undo".to_string())).clicked() {
+                        self.undo_file_operation(code_editor, log);
+                    }
+                    let redo_hint = self.file_operations.next_redo().map(|operation| format!("Redo {}", operation.description()));
+                    let redo = ui.add_enabled(redo_hint.is_some(), egui::Button::new("Redo"));
+                    if redo.on_hover_text(redo_hint.unwrap_or_else(|| "Nothing to redo".to_string())).clicked() {
+                        self.redo_file_operation(code_editor, log);
+                    }
+                    if ui.button("Empty Trash").on_hover_text("Deletes can't be undone afterwards").clicked() {
+                        self.empty_trash(log);
+                    }
+                }
+                ui.menu_button("Dock", |ui| {
+                    for (dock, label) in [(TreeDock::Left, "Left"), (TreeDock::Right, "Right"), (TreeDock::Floating, "Floating")] {
+                        if ui.radio_value(&mut self.dock, dock, label).clicked() {
+                            ui.close_menu();
+                        }
                     }
                 });
             });
 
-        self.handle_context_menu(ctx, log);
+            if self.project_path.is_some() {
+                let filter = egui::TextEdit::singleline(&mut self.filter)
+                    .hint_text("Filter files")
+                    .desired_width(f32::INFINITY);
+                if ui.add(filter).changed() {
+                    self.filter_matches = None;
+                }
+            }
+
+            ui.separator();
+
+            if self.project_path.is_some() && ctx.input(|i| !i.raw.hovered_files.is_empty()) {
+                ui.label("Drop to copy into the folder under the pointer");
+            }
+
+            self.rows.clear();
+            if let (Some(fs), Some(project_path)) = (&self.file_system, &self.project_path) {
+                let fs = fs.clone();
+                let project_path = project_path.clone();
+                egui::ScrollArea::vertical().auto_shrink([false, false]).show(ui, |ui| {
+                    let mut tree = TreeContext { code_editor, log, ai_assistant };
+                    self.render_folder_contents(ui, &project_path, fs.as_ref(), &mut tree, 0);
+                    if self.rows.is_empty() && !self.filter.is_empty() {
+                        ui.label("No files match the filter");
+                    }
+                });
+            } else {
+                ui.label("No project opened. Click 'Open Folder' to start.");
+            }
+        });
+    }
+
+    /// Arrow keys move through the tree while it has keyboard focus: up and
+    /// down between rows, right and left to expand and collapse or go to the
+    /// first child and the parent, Enter to open. Anything typed goes into the
+    /// filter.
+    fn handle_tree_keys(&mut self, ui: &egui::Ui, code_editor: &mut CodeEditor, log: &mut dyn FnMut(&str)) {
+        // Text fields, including the editor, keep their keys
+        if !self.tree_focused || ui.memory(|m| m.focused().is_some()) {
+            return;
+        }
+        let (up, down, left, right, enter, escape, backspace, undo, redo, typed) = ui.input_mut(|i| {
+            let redo = i.consume_key(egui::Modifiers::COMMAND | egui::Modifiers::SHIFT, egui::Key::Z)
+                || i.consume_key(egui::Modifiers::COMMAND, egui::Key::Y);
+            (
+                i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowUp),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowDown),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowLeft),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowRight),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::Enter),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::Escape),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::Backspace),
+                i.consume_key(egui::Modifiers::COMMAND, egui::Key::Z),
+                redo,
+                i.events.iter().filter_map(|event| match event {
+                    egui::Event::Text(text) => Some(text.as_str()),
+                    _ => None,
+                }).collect::<String>(),
+            )
+        });
+
+        if undo {
+            self.undo_file_operation(code_editor, log);
+        } else if redo {
+            self.redo_file_operation(code_editor, log);
+        }
+
+        if !typed.is_empty() || (backspace && !self.filter.is_empty()) || (escape && !self.filter.is_empty()) {
+            if escape {
+                self.filter.clear();
+            } else if backspace {
+                self.filter.pop();
+            } else {
+                self.filter.push_str(&typed);
+            }
+            self.filter_matches = None;
+        } else if escape {
+            self.tree_focused = false;
+        }
+
+        let current = self.selected_item.as_ref().and_then(|selected| self.rows.iter().position(|row| &row.path == selected));
+        if up || down {
+            let next = match current {
+                Some(index) if up => index.saturating_sub(1),
+                Some(index) => (index + 1).min(self.rows.len().saturating_sub(1)),
+                None => 0,
+            };
+            if let Some(row) = self.rows.get(next).cloned() {
+                self.select_row(&row);
+            }
+            return;
+        }
+        let Some(row) = current.and_then(|index| self.rows.get(index)).cloned() else {
+            return;
+        };
+        if right && row.expandable {
+            if !row.expanded {
+                self.expanded_folders.insert(row.path.clone());
+            } else if let Some(child) = self.rows.get(current.unwrap_or_default() + 1).filter(|next| next.path.parent() == Some(&row.path)).cloned() {
+                self.select_row(&child);
+            }
+        } else if left {
+            if row.expandable && row.expanded {
+                self.expanded_folders.remove(&row.path);
+            } else if let Some(parent) = row.path.parent().and_then(|parent| self.rows.iter().find(|row| row.path == parent)).cloned() {
+                self.select_row(&parent);
+            }
+        } else if enter {
+            if !row.expandable {
+                self.open_entry(&row.path, code_editor, log);
+            } else if row.expanded {
+                self.expanded_folders.remove(&row.path);
+            } else {
+                self.expanded_folders.insert(row.path.clone());
+            }
+        }
     }
 
-    fn render_folder_contents(
+    fn select_row(&mut self, row: &TreeRow) {
+        self.selected_item = Some(row.path.clone());
+        self.selected_folder = if row.is_dir {
+            Some(row.path.clone())
+        } else {
+            row.path.parent().map(Path::to_path_buf)
+        };
+        self.scroll_to_selected = true;
+    }
+
+    /// Expands the folders above `path`, which may be inside an archive in the
+    /// project, then selects it and scrolls it into view.
+    pub fn reveal(&mut self, path: &Path) {
+        let Some(project_path) = self.project_path.clone() else {
+            return;
+        };
+        if !path.starts_with(&project_path) {
+            return;
+        }
+        for folder in path.ancestors().skip(1) {
+            self.expanded_folders.insert(folder.to_path_buf());
+            if folder == project_path {
+                break;
+            }
+        }
+        if self.filter_matches.as_ref().is_some_and(|matches| !matches.contains(path)) {
+            self.filter.clear();
+            self.filter_matches = None;
+        }
+        self.selected_item = Some(path.to_path_buf());
+        self.selected_folder = path.parent().map(Path::to_path_buf);
+        self.scroll_to_selected = true;
+        self.show = true;
+    }
+
+    /// The project files whose path below the project contains the filter,
+    /// ignoring case, together with the folders leading to them.
+    fn matching_paths(&self) -> HashSet<PathBuf> {
+        let mut matches = HashSet::new();
+        let Some(project_path) = &self.project_path else {
+            return matches;
+        };
+        let filter = self.filter.to_lowercase();
+        for path in self.project_files.files().iter() {
+            let relative = path.strip_prefix(project_path).unwrap_or(path);
+            if relative.to_string_lossy().to_lowercase().contains(&filter) {
+                for shown in path.ancestors() {
+                    if shown == project_path.as_path() || !matches.insert(shown.to_path_buf()) {
+                        break;
+                    }
+                }
+            }
+        }
+        matches
+    }
+
+    /// Lists `folder` and its expanded subfolders. Archives in the project
+    /// expand like folders, listing their entries through an [`ArchiveVfs`].
+    fn render_folder_contents<V: Vfs>(
         &mut self,
         ui: &mut egui::Ui,
-        ctx: &egui::Context,
         folder: &Path,
-        fs: &Rc<FileSystem>,
-        code_editor: &mut CodeEditor,
-        log: &mut dyn FnMut(&str),
+        fs: &FileSystem<V>,
+        tree: &mut TreeContext,
         indent_level: usize,
-        ai_assistant: &mut crate::components::ai_assistant::AIAssistant,
-    ) { 
+    ) {
         if let Ok(entries) = fs.list_directory(folder) {
+            // Archive contents aren't filtered, they're only listed once expanded
+            let filtering = !self.filter.is_empty() && !fs.is_read_only();
             for entry in entries {
                 let path = folder.join(&entry.name);
+                if filtering && !self.filter_matches.as_ref().is_some_and(|matches| matches.contains(&path)) {
+                    continue;
+                }
                 let is_dir = entry.is_dir;
-                let is_expanded = self.expanded_folders.contains(&path);
+                // Archives inside archives are shown as plain files
+                let is_archive = !is_dir && !fs.is_read_only() && ArchiveVfs::is_archive(&path);
+                // Folders with matches stay open while filtering
+                let is_expanded = (filtering && is_dir) || self.expanded_folders.contains(&path);
+                self.rows.push(TreeRow {
+                    path: path.clone(),
+                    is_dir,
+                    expandable: is_dir || is_archive,
+                    expanded: is_expanded,
+                });
     
                 ui.horizontal(|ui| {
                     ui.add_space(indent_level as f32 * 20.0);
@@ -146,7 +504,7 @@ impl FileModal {
     
                             // Check for pressing Enter (to finish rename) or Esc (to cancel rename)
                             if response.lost_focus() || ui.input(|i| i.key_pressed(egui::Key::Enter)) {
-                                self.finish_rename(log);
+                                self.finish_rename(tree.code_editor, tree.log);
                             } else if ui.input(|i| i.key_pressed(egui::Key::Escape)) {
                                 self.cancel_rename();
                             }
@@ -166,25 +524,64 @@ impl FileModal {
                             format!(" {}", entry.name)
                         };
     
+                        // Archive entries aren't in git
+                        let git_status = if fs.is_read_only() { None } else { self.git_status.status().get(&path) };
                         let text_color = if is_selected {
                             egui::Color32::from_rgb(100, 100, 255)
                         } else {
-                            ui.style().visuals.text_color()
+                            git_status.map_or_else(|| ui.style().visuals.text_color(), |status| Self::git_status_color(ui, status))
                         };
     
-                        let label = if is_dir {
+                        let label = if is_dir || is_archive {
                             egui::RichText::new(text).italics().color(text_color)
                         } else {
                             egui::RichText::new(text).color(text_color)
                         };
     
-                        let response = ui.add(egui::Label::new(label).sense(egui::Sense::click()));
+                        let response = ui.add(egui::Label::new(label).sense(egui::Sense::click_and_drag()));
+                        if let Some(status) = git_status {
+                            // Folders get a dot, files the first letter of their state
+                            let badge = match status {
+                                GitFileStatus::Ignored => "",
+                                _ if is_dir => "•",
+                                GitFileStatus::Untracked => "U",
+                                GitFileStatus::Added => "A",
+                                GitFileStatus::Modified => "M",
+                                GitFileStatus::Conflicted => "!",
+                            };
+                            if !badge.is_empty() {
+                                ui.label(egui::RichText::new(badge).small().color(Self::git_status_color(ui, status)))
+                                    .on_hover_text(status.description());
+                            }
+                        }
+                        if is_selected && self.scroll_to_selected {
+                            response.scroll_to_me(None);
+                            self.scroll_to_selected = false;
+                        }
+
+                        // Dragging an item onto a folder, or onto a file in it, moves it there
+                        if !fs.is_read_only() {
+                            let target_folder = if is_dir { path.clone() } else { folder.to_path_buf() };
+                            if response.drag_started() {
+                                response.dnd_set_drag_payload(DraggedPath(path.clone()));
+                            }
+                            if response.dnd_hover_payload::<DraggedPath>().is_some() {
+                                ui.painter().rect_stroke(response.rect.expand(2.0), 2.0, ui.visuals().selection.stroke);
+                            }
+                            if let Some(dragged) = response.dnd_release_payload::<DraggedPath>() {
+                                self.pending_move = Some((dragged.0.clone(), target_folder.clone()));
+                            }
+                            if response.contains_pointer() {
+                                self.drop_folder = Some(target_folder);
+                            }
+                        }
     
                         if response.hovered() {
                             ui.ctx().set_cursor_icon(egui::CursorIcon::PointingHand);
                         }
     
                         if response.clicked() {
+                            self.tree_focused = true;
                             self.selected_item = Some(path.clone());
                             if is_dir {
                                 if is_expanded {
@@ -193,21 +590,22 @@ impl FileModal {
                                     self.expanded_folders.insert(path.clone());
                                 }
                                 self.selected_folder = Some(path.clone());
+                            } else if is_archive {
+                                if is_expanded {
+                                    self.expanded_folders.remove(&path);
+                                } else {
+                                    self.expanded_folders.insert(path.clone());
+                                }
+                                self.selected_folder = Some(path.parent().unwrap().to_path_buf());
                             } else {
                                 self.selected_folder = Some(path.parent().unwrap().to_path_buf());
-                                match fs.open_file(&path) {
-                                    Ok(content) => {
-                                        code_editor.open_file(content, path.to_str().unwrap().to_string());
-                                        log(&format!("Opened file: {}", path.display()));
-                                    }
-                                    Err(e) => log(&format!("Error opening file {}: {}", path.display(), e)),
-                                }
+                                Self::open_in_editor(fs, &path, tree.code_editor, tree.log);
                             }
                         }
     
                         if response.double_clicked() {
-                            if !ai_assistant.context_files.iter().any(|f| f.path == path.to_str().unwrap()) {
-                                ai_assistant.context_files.push(crate::components::ai_assistant::ContextFile {
+                            if !tree.ai_assistant.context_files.iter().any(|f| f.path == path.to_str().unwrap()) {
+                                tree.ai_assistant.context_files.push(crate::components::ai_assistant::ContextFile {
                                     path: path.to_str().unwrap().to_string(),
                                     content: fs.open_file(&path).unwrap_or_default(),
                                     is_active: true,
@@ -215,8 +613,8 @@ impl FileModal {
                             }
                         }
     
-                        if response.secondary_clicked() {
-                            if let Some(pointer_pos) = ctx.pointer_interact_pos() {
+                        if response.secondary_clicked() && !fs.is_read_only() {
+                            if let Some(pointer_pos) = ui.ctx().pointer_interact_pos() {
                                 let screen_pos = pointer_pos;
                                 self.context_menu = Some(ContextMenuState {
                                     path: path.clone(),
@@ -228,9 +626,11 @@ impl FileModal {
     
                         if response.hovered() {
                             let hover_text = if is_dir {
-                                "Click to expand/collapse, double-click to rename"
+                                "Click to expand/collapse, drag to move"
+                            } else if is_archive {
+                                "Click to browse the archive"
                             } else {
-                                "Click to open, double-click to add to context"
+                                "Click to open, double-click to add to context, drag to move"
                             };
                             response.on_hover_text(hover_text);
                         }
@@ -238,25 +638,24 @@ impl FileModal {
                 });
     
                 if is_dir && (is_expanded || self.creating_item.as_ref().map_or(false, |(parent, _, _)| parent == &path)) {
-                    self.render_folder_contents(
-                        ui,
-                        ctx,
-                        &path,
-                        fs,
-                        code_editor,
-                        log,
-                        indent_level + 1,
-                        ai_assistant,
-                    );
+                    self.render_folder_contents(ui, &path, fs, tree, indent_level + 1);
+                } else if is_archive && is_expanded {
+                    match self.archive(&path) {
+                        Ok(archive) => self.render_folder_contents(ui, &path, archive.as_ref(), tree, indent_level + 1),
+                        Err(e) => {
+                            (tree.log)(&format!("Error opening archive {}: {}", path.display(), e));
+                            self.expanded_folders.remove(&path);
+                        }
+                    }
                 }
             }
         } else {
-            log(&format!("Error reading directory: {}", folder.display()));
+            (tree.log)(&format!("Error reading directory: {}", folder.display()));
         }
     
         // Render item being created
         let mut item_created = false;
-        if let Some((parent, name, _is_folder)) = &mut self.creating_item {
+        if let Some((parent, name, _)) = &mut self.creating_item {
             if parent == folder {
                 ui.horizontal(|ui| {
                     ui.add_space((indent_level + 1) as f32 * 20.0);
@@ -272,23 +671,86 @@ impl FileModal {
             }
         }
         if item_created {
-            if let Some((parent, name, is_folder)) = self.creating_item.take() {
-                self.finish_create_item(&parent, &name, is_folder, log);
+            if let Some((parent, name, item)) = self.creating_item.take() {
+                self.finish_create_item(&parent, &name, item, tree.code_editor, tree.log);
             }
         }
     }    
     
+    fn git_status_color(ui: &egui::Ui, status: GitFileStatus) -> egui::Color32 {
+        match status {
+            GitFileStatus::Ignored => ui.visuals().weak_text_color(),
+            GitFileStatus::Untracked => egui::Color32::from_rgb(115, 201, 145),
+            GitFileStatus::Added => egui::Color32::from_rgb(129, 184, 139),
+            GitFileStatus::Modified => egui::Color32::from_rgb(226, 192, 141),
+            GitFileStatus::Conflicted => egui::Color32::from_rgb(229, 115, 115),
+        }
+    }
+
+    /// Opens a file listed in the tree, from the project or from an archive.
+    fn open_entry(&self, path: &Path, code_editor: &mut CodeEditor, log: &mut dyn FnMut(&str)) {
+        if let Some(archive) = self.archives.iter().find(|(archive_path, _)| path.starts_with(archive_path)).map(|(_, archive)| archive) {
+            Self::open_in_editor(archive.as_ref(), path, code_editor, log);
+        } else if let Some(fs) = &self.file_system {
+            Self::open_in_editor(fs.as_ref(), path, code_editor, log);
+        }
+    }
+
+    fn open_in_editor<V: Vfs>(fs: &FileSystem<V>, path: &Path, code_editor: &mut CodeEditor, log: &mut dyn FnMut(&str)) {
+        match fs.open_file(path) {
+            Ok(content) => {
+                let index = code_editor.open_file(content, path.to_str().unwrap().to_string());
+                code_editor.buffers[index].read_only = fs.is_read_only();
+                log(&format!("Opened file: {}", path.display()));
+            }
+            Err(e) => log(&format!("Error opening file {}: {}", path.display(), e)),
+        }
+    }
+
+    /// Re-reads the buffers opened from archives at or below `paths`, e.g. after
+    /// Gradle rebuilt an APK, since the project's file system can't read them.
+    pub fn sync_archive_buffers(&mut self, code_editor: &mut CodeEditor, paths: &[PathBuf], log: &mut dyn FnMut(&str)) {
+        for buffer in code_editor.buffers.iter_mut().filter(|buffer| buffer.read_only) {
+            let Some(file_path) = buffer.file_path.clone() else {
+                continue;
+            };
+            let path = Path::new(&file_path);
+            let Some(archive_path) = self.archives
+                .keys()
+                .find(|archive| path.starts_with(archive) && paths.iter().any(|changed| archive.starts_with(changed)))
+                .cloned()
+            else {
+                continue;
+            };
+            match self.archive(&archive_path).and_then(|archive| archive.open_file(path)) {
+                Ok(content) => buffer.reload(&content),
+                Err(e) => log(&format!("Failed to reload {}: {}", file_path, e)),
+            }
+        }
+    }
+
+    /// The archive at `path`, opened again if it was rebuilt since.
+    fn archive(&mut self, path: &Path) -> io::Result<Rc<FileSystem<ArchiveVfs>>> {
+        if let Some(archive) = self.archives.get(path).filter(|archive| !archive.vfs().is_stale()) {
+            return Ok(archive.clone());
+        }
+        let archive = Rc::new(FileSystem::with_vfs(&path.to_string_lossy(), ArchiveVfs::open(path)?));
+        self.archives.insert(path.to_path_buf(), archive.clone());
+        Ok(archive)
+    }
+
     fn cancel_rename(&mut self) {
         self.editing_item = None;
     }
 
-    fn handle_context_menu(&mut self, ctx: &egui::Context, log: &mut dyn FnMut(&str)) {
+    fn handle_context_menu(&mut self, ctx: &egui::Context, code_editor: &mut CodeEditor, log: &mut dyn FnMut(&str)) {
         if let Some(menu_state) = &self.context_menu {
             let pos = menu_state.pos;
             let path = menu_state.path.clone();
             let is_dir = menu_state.is_dir;
 
-            egui::Area::new(egui::Id::new("context_menu"))
+            let paste_folder = if is_dir { path.clone() } else { path.parent().unwrap_or(&path).to_path_buf() };
+            let menu = egui::Area::new(egui::Id::new("context_menu"))
                 .fixed_pos(pos)
                 .order(egui::Order::Foreground)
                 .show(ctx, |ui| {
@@ -300,19 +762,41 @@ impl FileModal {
                                 self.start_rename(&path);
                                 self.context_menu = None;
                             }
+                            if ui.button("Cut").clicked() {
+                                self.clipboard = Some(FileClipboard { path: path.clone(), cut: true });
+                                self.context_menu = None;
+                            }
+                            if ui.button("Copy").clicked() {
+                                self.clipboard = Some(FileClipboard { path: path.clone(), cut: false });
+                                self.context_menu = None;
+                            }
+                            if ui.add_enabled(self.clipboard.is_some(), egui::Button::new("Paste")).clicked() {
+                                self.paste(&paste_folder, code_editor, log);
+                                self.context_menu = None;
+                            }
+                            if ui.button("Duplicate").clicked() {
+                                self.copy_into(&path, path.parent().unwrap_or(&path), log);
+                                self.context_menu = None;
+                            }
                             if ui.button("Delete").clicked() {
                                 self.delete_item(&path, log);
                                 self.context_menu = None;
                             }
                             if is_dir {
                                 if ui.button("New File").clicked() {
-                                    self.start_create_item(false, &path);
+                                    self.start_create_item(NewItem::File, &path);
                                     self.context_menu = None;
                                 }
                                 if ui.button("New Folder").clicked() {
-                                    self.start_create_item(true, &path);
+                                    self.start_create_item(NewItem::Folder, &path);
                                     self.context_menu = None;
                                 }
+                                ui.menu_button("New from Template", |ui| {
+                                    if self.template_menu(ui, &path) {
+                                        ui.close_menu();
+                                        self.context_menu = None;
+                                    }
+                                });
                             }
                         });
                 });
@@ -320,8 +804,7 @@ impl FileModal {
             // Close the context menu if clicked outside
             if ctx.input(|i| i.pointer.any_click()) {
                 if let Some(pointer_pos) = ctx.pointer_interact_pos() {
-                    let menu_rect = egui::Rect::from_min_size(pos, egui::Vec2::new(150.0, 100.0)); // Approximate size
-                    if !menu_rect.contains(pointer_pos) {
+                    if !menu.response.rect.contains(pointer_pos) {
                         self.context_menu = None;
                     }
                 }
@@ -335,58 +818,272 @@ impl FileModal {
         self.new_item_focus = true; // Mark that we need to focus on the input field
     }
 
-    fn finish_rename(&mut self, log: &mut dyn FnMut(&str)) {
+    fn finish_rename(&mut self, code_editor: &mut CodeEditor, log: &mut dyn FnMut(&str)) {
         if let Some((old_path, new_name)) = self.editing_item.take() {
             let new_path = old_path.with_file_name(&new_name);
+            if new_path == old_path {
+                return;
+            }
             if let Some(fs) = &self.file_system {
-                match fs.rename_file(&old_path, &new_path) {
-                    Ok(_) => log(&format!("Renamed '{}' to '{}'", old_path.display(), new_path.display())),
+                match self.file_operations.rename(fs, &old_path, &new_path) {
+                    Ok(_) => {
+                        self.path_moved(&old_path, &new_path);
+                        code_editor.path_moved(&old_path, &new_path);
+                        log(&format!("Renamed '{}' to '{}'", old_path.display(), new_path.display()));
+                    }
                     Err(e) => log(&format!("Error renaming: {}", e)),
                 }
             }
         }
     }
 
-    fn start_create_item(&mut self, is_folder: bool, parent: &Path) {
-        let name = if is_folder { "New Folder".to_string() } else { "New File.txt".to_string() };
-        self.creating_item = Some((parent.to_path_buf(), name, is_folder));
+    fn start_create_item(&mut self, item: NewItem, parent: &Path) {
+        let name = match &item {
+            NewItem::File => "New File.txt".to_string(),
+            NewItem::Folder => "New Folder".to_string(),
+            NewItem::Template(template) => template.default_file_name(),
+        };
+        self.creating_item = Some((parent.to_path_buf(), name, item));
         self.expanded_folders.insert(parent.to_path_buf());
         self.new_item_focus = true;
     }   
 
-    fn finish_create_item(&mut self, parent: &Path, name: &str, is_folder: bool, log: &mut dyn FnMut(&str)) {
-        if let Some(fs) = &self.file_system {
+    fn finish_create_item(&mut self, parent: &Path, name: &str, item: NewItem, code_editor: &mut CodeEditor, log: &mut dyn FnMut(&str)) {
+        if let Some(fs) = self.file_system.clone() {
             let new_path = parent.join(name);
-            if is_folder {
-                match fs.create_directory(&new_path) {
+            match item {
+                NewItem::Folder => match self.file_operations.create_directory(&fs, &new_path) {
                     Ok(_) => log(&format!("Created new folder: {}", new_path.display())),
                     Err(e) => log(&format!("Error creating folder: {}", e)),
-                }
-            } else {
-                match fs.create_new_file(parent, name) {
+                },
+                NewItem::File => match self.file_operations.create_file(&fs, parent, name) {
                     Ok(_) => log(&format!("Created new file: {}", new_path.display())),
                     Err(e) => log(&format!("Error creating file: {}", e)),
-                }
+                },
+                NewItem::Template(template) => self.create_from_template(&fs, parent, name, &template, code_editor, log),
             }
             self.expanded_folders.insert(parent.to_path_buf());
+            self.filter_matches = None;
+        }
+    }
+
+    /// Creates a file in `folder` from `template`, with the package worked
+    /// out from the folder, and opens it.
+    fn create_from_template(
+        &mut self,
+        fs: &FileSystem,
+        folder: &Path,
+        name: &str,
+        template: &FileTemplate,
+        code_editor: &mut CodeEditor,
+        log: &mut dyn FnMut(&str),
+    ) {
+        let file_name = template.file_name(name);
+        let package = file_templates::package_for(folder).unwrap_or_default();
+        let path = match self.file_operations.create_file(fs, folder, &file_name) {
+            Ok(path) => path,
+            Err(e) => {
+                log(&format!("Error creating file: {}", e));
+                return;
+            }
+        };
+        if let Err(e) = fs.save_file(&path, &template.fill(&file_name, &package)) {
+            log(&format!("Error writing {}: {}", path.display(), e));
+            return;
+        }
+        log(&format!("Created {} from the {} template", path.display(), template.name));
+
+        if template.registers_activity {
+            let class_name = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+            let class_name = if package.is_empty() { class_name } else { format!("{}.{}", package, class_name) };
+            match file_templates::register_activity(fs, folder, &class_name) {
+                Ok(edit) => {
+                    log(&format!("Registered {} in {}", class_name, edit.path.display()));
+                    // Undoing the creation takes the activity out again
+                    if edit.before != edit.after {
+                        self.file_operations.record_edit(edit);
+                    }
+                }
+                Err(e) => log(&format!("Error registering {}: {}", class_name, e)),
+            }
+        }
+        Self::open_in_editor(fs, &path, code_editor, log);
+        self.selected_item = Some(path);
+    }
+
+    /// Lists the built-in and project templates. Picking one starts naming a
+    /// new file in `folder`; returns whether one was picked.
+    fn template_menu(&mut self, ui: &mut egui::Ui, folder: &Path) -> bool {
+        let (Some(fs), Some(project_path)) = (&self.file_system, &self.project_path) else {
+            return false;
+        };
+        let mut chosen = None;
+        let mut after_built_in = false;
+        for template in file_templates::templates(fs.as_ref(), project_path) {
+            let button = if template.from_project {
+                if !after_built_in {
+                    ui.separator();
+                    after_built_in = true;
+                }
+                ui.button(&template.name).on_hover_text(format!("From {}", file_templates::PROJECT_TEMPLATE_DIR))
+            } else {
+                ui.button(&template.name)
+            };
+            if button.clicked() {
+                chosen = Some(template);
+            }
+        }
+        match chosen {
+            Some(template) => {
+                self.start_create_item(NewItem::Template(template), folder);
+                true
+            }
+            None => false,
         }
     }
 
     fn delete_item(&mut self, path: &Path, log: &mut dyn FnMut(&str)) {
         if let Some(fs) = &self.file_system {
-            match fs.delete_file(path) {
+            match self.file_operations.delete(fs, path) {
                 Ok(_) => {
                     self.selected_item = None;
-                    if path.is_dir() {
-                        self.expanded_folders.remove(path);
-                    }
-                    log(&format!("Deleted: {}", path.display()));
+                    self.expanded_folders.retain(|folder| !folder.starts_with(path));
+                    self.filter_matches = None;
+                    log(&format!("Moved to trash: {}", path.display()));
                 },
                 Err(e) => log(&format!("Error deleting {}: {:?}", path.display(), e)),
             }
         }
     }
     
+    fn undo_file_operation(&mut self, code_editor: &mut CodeEditor, log: &mut dyn FnMut(&str)) {
+        if let Some(fs) = &self.file_system {
+            match self.file_operations.undo(fs) {
+                Ok(Some(operation)) => {
+                    self.path_moved(&operation.to, &operation.from);
+                    // Buffers don't follow files into the trash
+                    if operation.kind == FileOperationKind::Rename {
+                        code_editor.path_moved(&operation.to, &operation.from);
+                    }
+                    log(&format!("Undid {}", operation.description()));
+                }
+                Ok(None) => {}
+                Err(e) => log(&format!("Error undoing: {}", e)),
+            }
+        }
+    }
+
+    fn redo_file_operation(&mut self, code_editor: &mut CodeEditor, log: &mut dyn FnMut(&str)) {
+        if let Some(fs) = &self.file_system {
+            match self.file_operations.redo(fs) {
+                Ok(Some(operation)) => {
+                    self.path_moved(&operation.from, &operation.to);
+                    if operation.kind == FileOperationKind::Rename {
+                        code_editor.path_moved(&operation.from, &operation.to);
+                    }
+                    log(&format!("Redid {}", operation.description()));
+                }
+                Ok(None) => {}
+                Err(e) => log(&format!("Error redoing: {}", e)),
+            }
+        }
+    }
+
+    /// Moves `from` into `folder`, keeping its name.
+    fn move_into(&mut self, from: &Path, folder: &Path, code_editor: &mut CodeEditor, log: &mut dyn FnMut(&str)) -> bool {
+        let Some(name) = from.file_name() else {
+            return false;
+        };
+        let to = folder.join(name);
+        // Dropped where it already is, or onto itself
+        if to == from || folder == from {
+            return false;
+        }
+        let Some(fs) = &self.file_system else {
+            return false;
+        };
+        match self.file_operations.rename(fs, from, &to) {
+            Ok(_) => {
+                self.path_moved(from, &to);
+                code_editor.path_moved(from, &to);
+                self.expanded_folders.insert(folder.to_path_buf());
+                log(&format!("Moved '{}' to '{}'", from.display(), folder.display()));
+                true
+            }
+            Err(e) => {
+                log(&format!("Error moving {}: {}", from.display(), e));
+                false
+            }
+        }
+    }
+
+    /// Copies `from` into `folder`, adding "copy" to the name if it is taken.
+    fn copy_into(&mut self, from: &Path, folder: &Path, log: &mut dyn FnMut(&str)) {
+        let (Some(fs), Some(name)) = (&self.file_system, from.file_name().and_then(|name| name.to_str())) else {
+            return;
+        };
+        let to = file_operations::free_copy_path(fs.as_ref(), folder, name);
+        match self.file_operations.copy(fs, from, &to) {
+            Ok(_) => {
+                self.expanded_folders.insert(folder.to_path_buf());
+                self.selected_item = Some(to.clone());
+                self.filter_matches = None;
+                log(&format!("Copied '{}' to '{}'", from.display(), to.display()));
+            }
+            Err(e) => log(&format!("Error copying {}: {}", from.display(), e)),
+        }
+    }
+
+    fn paste(&mut self, folder: &Path, code_editor: &mut CodeEditor, log: &mut dyn FnMut(&str)) {
+        let Some(clipboard) = self.clipboard.clone() else {
+            return;
+        };
+        if !clipboard.cut {
+            self.copy_into(&clipboard.path, folder, log);
+        } else if self.move_into(&clipboard.path, folder, code_editor, log) {
+            // A cut item can only be pasted once
+            self.clipboard = None;
+        }
+    }
+
+    /// Copies files dropped from outside the IDE into the folder under the
+    /// pointer, or the selected folder if the pointer isn't over the tree.
+    fn import_dropped_files(&mut self, ctx: &egui::Context, log: &mut dyn FnMut(&str)) {
+        let drop_folder = self.drop_folder.take();
+        let dropped: Vec<PathBuf> = ctx.input(|i| i.raw.dropped_files.iter().filter_map(|file| file.path.clone()).collect());
+        if dropped.is_empty() {
+            return;
+        }
+        let Some(folder) = drop_folder.or_else(|| self.selected_folder.clone()).or_else(|| self.project_path.clone()) else {
+            return;
+        };
+        for path in dropped {
+            self.copy_into(&path, &folder, log);
+        }
+    }
+
+    fn empty_trash(&mut self, log: &mut dyn FnMut(&str)) {
+        if let Some(fs) = &self.file_system {
+            match self.file_operations.empty_trash(fs) {
+                Ok(_) => log("Emptied the trash"),
+                Err(e) => log(&format!("Error emptying the trash: {}", e)),
+            }
+        }
+    }
+
+    /// Keeps folders expanded and items selected when they move.
+    fn path_moved(&mut self, from: &Path, to: &Path) {
+        let moved = |path: &Path| match path.strip_prefix(from) {
+            Ok(rest) if rest.as_os_str().is_empty() => to.to_path_buf(),
+            Ok(rest) => to.join(rest),
+            Err(_) => path.to_path_buf(),
+        };
+        self.expanded_folders = self.expanded_folders.iter().map(|folder| moved(folder)).collect();
+        self.selected_item = self.selected_item.as_deref().map(moved);
+        self.selected_folder = self.selected_folder.as_deref().map(moved);
+        self.filter_matches = None;
+    }
+
     pub fn open_folder(&mut self, log: &mut dyn FnMut(&str)) {
         if self.is_initializing.compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
             if let Some(folder_path) = FileDialog::new().pick_folder() {
@@ -396,21 +1093,27 @@ impl FileModal {
                     return;
                 }
                 
-                // Clear existing state first
-                self.expanded_folders.clear();
+                // Clear existing state first, keeping the tree of the old project
+                if let Some(old_project) = self.project_path.take() {
+                    let expanded = self.expanded_folders.drain().collect();
+                    self.expanded_by_project.insert(old_project, expanded);
+                }
                 self.selected_folder = None;
                 self.selected_item = None;
+                self.archives.clear();
                 self.editing_item = None;
                 self.creating_item = None;
                 self.context_menu = None;
+                self.filter.clear();
+                self.filter_matches = None;
                 
                 // Set up new project
                 self.project_path = Some(folder_path.clone());
                 let fs = Rc::new(FileSystem::new(folder_path.to_str().unwrap()));
                 self.file_system = Some(fs);
                 
-                // Expand root folder
-                self.expanded_folders.insert(folder_path.clone());
+                // Expand the folders that were open last time, or just the root
+                self.restore_expanded_folders();
                 log(&format!("Opened project: {}", folder_path.display()));
             }
             self.is_initializing.store(false, Ordering::SeqCst);
@@ -419,6 +1122,34 @@ impl FileModal {
         }
     }
 
+    /// Expanded folders of every project opened, including the current one.
+    pub fn expanded_by_project(&self) -> HashMap<PathBuf, Vec<PathBuf>> {
+        let mut expanded_by_project = self.expanded_by_project.clone();
+        if let Some(project_path) = &self.project_path {
+            let mut expanded: Vec<PathBuf> = self.expanded_folders.iter().cloned().collect();
+            expanded.sort();
+            expanded_by_project.insert(project_path.clone(), expanded);
+        }
+        expanded_by_project
+    }
+
+    /// Restores saved expanded folders, including those of the current project.
+    pub fn set_expanded_by_project(&mut self, expanded_by_project: HashMap<PathBuf, Vec<PathBuf>>) {
+        self.expanded_by_project = expanded_by_project;
+        self.restore_expanded_folders();
+    }
+
+    fn restore_expanded_folders(&mut self) {
+        if let Some(project_path) = &self.project_path {
+            self.expanded_folders = self.expanded_by_project
+                .remove(project_path)
+                .unwrap_or_default()
+                .into_iter()
+                .collect();
+            self.expanded_folders.insert(project_path.clone());
+        }
+    }
+
     fn collapse_all_folders(&mut self) {
         self.expanded_folders.clear();
     }
@@ -427,11 +1158,10 @@ impl FileModal {
         let buffers = code_editor.buffers.drain(..).collect::<Vec<_>>();
         
         for mut buffer in buffers {
-            if let Some(file_path) = &buffer.file_path {
-                match self.file_system.as_ref().unwrap().open_file(Path::new(file_path)) {
+            if let Some(file_path) = buffer.file_path.clone().filter(|_| !buffer.read_only) {
+                match self.file_system.as_ref().unwrap().open_file(Path::new(&file_path)) {
                     Ok(content) => {
-                        buffer.content = content;
-                        buffer.is_modified = false;
+                        buffer.reload(&content);
                         code_editor.buffers.push(buffer);
                     },
                     Err(e) => log(&format!("Failed to reload {}: {}", file_path, e))
@@ -444,14 +1174,18 @@ impl FileModal {
     
     pub fn save_current_file(&self, code_editor: &mut CodeEditor, log: &mut dyn FnMut(&str)) {
         if let Some(buffer) = code_editor.get_active_buffer() {
+            if buffer.read_only {
+                log(&format!("{} is read-only", buffer.file_path.as_deref().unwrap_or("untitled")));
+                return;
+            }
             if let Some(file_path) = &buffer.file_path {
                 if let Some(fs) = &self.file_system {
                     let path = Path::new(file_path);
-                    match fs.save_file(path, &buffer.content) {
+                    match fs.save_file(path, &buffer.text()) {
                         Ok(_) => {
                             log(&format!("Saved file: {}", file_path));
                             if let Some(buffer) = code_editor.get_active_buffer_mut() {
-                                buffer.is_modified = false;
+                                buffer.mark_saved();
                             }
                         },
                         Err(e) => log(&format!("Error saving file {}: {}", file_path, e)),
@@ -463,122 +1197,45 @@ impl FileModal {
         }
     }
 
-    pub fn search_files(&self, query: &str) -> Vec<String> {
-        let mut results = Vec::new();
-        if let Some(fs) = &self.file_system {
-            if let Some(project_path) = &self.project_path {
-                let excluded_dirs = vec![
-                    "build", "target", "out", "bin", "node_modules", ".gradle", "gradle", "captures",
-                    ".git", ".svn", ".idea", ".vscode", "app/build", "androidTest", "test", "debug",
-                    "release", "shared/build", "commonMain", "androidMain", "iosMain", "__MACOSX",
-                    ".DS_Store", "*.xcodeproj", "*.iml",
-                ];
-                self.search_directory(fs, project_path, query, &mut results, &excluded_dirs);
-            }
-        }
-        results
-    }
-
-    fn search_directory(&self, fs: &Rc<FileSystem>, dir: &Path, query: &str, results: &mut Vec<String>, excluded_dirs: &[&str]) {
-        let query_lower = query.to_lowercase();
-        if let Ok(entries) = fs.list_directory(dir) {
-            for entry in entries {
-                let path = dir.join(&entry.name);
-                if entry.is_dir {
-                    let dir_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
-                    if excluded_dirs.iter().any(|&excluded| dir_name == excluded || dir_name.starts_with(excluded) || dir_name.contains(excluded)) {
-                        continue;
-                    }
-                    self.search_directory(fs, &path, query, results, excluded_dirs);
-                } else {
-                    if entry.name.to_lowercase().contains(&query_lower) {
-                        results.push(path.to_str().unwrap().to_string());
-                    }
-                }
-            }
-        }
-    }
-
     pub fn get_all_file_paths(&self) -> Vec<String> {
         let mut all_paths = Vec::new();
-        
-        if let (Some(fs), Some(project_path)) = (&self.file_system, &self.project_path) {
-            // Minimal excluded directories (only truly unnecessary files)
-            let excluded_dirs = vec![
-                "node_modules", ".git", ".cache", "dist", "build", 
-                "package-lock.json", "yarn.lock", ".DS_Store"
-            ];
-            
-            // Use a recursive closure to traverse the directory tree
-            fn collect_files(
-                fs: &Rc<FileSystem>,
-                dir: &Path,
-                paths: &mut Vec<String>,
-                excluded_dirs: &[&str]
-            ) {
-                if let Ok(entries) = fs.list_directory(dir) {
-                    for entry in entries {
-                        let path = dir.join(&entry.name);
-                        
-                        if entry.is_dir {
-                            let dir_name = path.file_name()
-                                .and_then(|n| n.to_str())
-                                .unwrap_or("");
-                                
-                            // Skip only truly unnecessary directories (exact matches only)
-                            if excluded_dirs.contains(&dir_name) {
-                                continue;
-                            }
-                            
-                            // Always include common project directories
-                            if matches!(
-                                dir_name,
-                                "src" | "layouts" | "pages" | "components" | "lib" | "utils" | "public" | "assets"
-                            ) {
-                                collect_files(fs, &path, paths, excluded_dirs);
-                            } else {
-                                // For other directories, check if they contain relevant files
-                                collect_files(fs, &path, paths, excluded_dirs);
-                            }
-                        } else {
-                            // Get lowercase extension
-                            let extension = path.extension()
-                                .and_then(|ext| ext.to_str())
-                                .map(|ext| ext.to_lowercase())
-                                .unwrap_or_default();
-    
-                            // Include files with common extensions
-                            let is_relevant_file = matches!(
-                                extension.as_str(),
-                                // Web development
-                                "js" | "jsx" | "ts" | "tsx" | "css" | "scss" | "sass" | "html" |
-                                // Markdown and documentation
-                                "md" | "mdx" | "txt" |
-                                // Configuration
-                                "json" | "toml" | "yaml" | "yml" | "env" |
-                                // Common source files
-                                "rs" | "kt" | "java" | "go" | "py" | "rb" | "php" | "swift"
-                            );
-    
-                            // Include common config files regardless of extension
-                            let is_config_file = path.file_name()
-                                .and_then(|n| n.to_str())
-                                .map(|n| n.starts_with(".") || n == "config" || n.ends_with("config") || n == "package.json")
-                                .unwrap_or(false);
-    
-                            if is_relevant_file || is_config_file {
-                                if let Some(path_str) = path.to_str() {
-                                    paths.push(path_str.to_string());
-                                }
-                            }
-                        }
+
+        if let Some(project_path) = &self.project_path {
+            ProjectWalker::new(project_path).visit(&mut |path| {
+                // Get lowercase extension
+                let extension = path.extension()
+                    .and_then(|ext| ext.to_str())
+                    .map(|ext| ext.to_lowercase())
+                    .unwrap_or_default();
+
+                // Include files with common extensions
+                let is_relevant_file = matches!(
+                    extension.as_str(),
+                    // Web development
+                    "js" | "jsx" | "ts" | "tsx" | "css" | "scss" | "sass" | "html" |
+                    // Markdown and documentation
+                    "md" | "mdx" | "txt" |
+                    // Configuration
+                    "json" | "toml" | "yaml" | "yml" | "env" |
+                    // Common source files
+                    "rs" | "kt" | "java" | "go" | "py" | "rb" | "php" | "swift"
+                );
+
+                // Include common config files regardless of extension
+                let is_config_file = path.file_name()
+                    .and_then(|n| n.to_str())
+                    .map(|n| n.starts_with(".") || n == "config" || n.ends_with("config") || n == "package.json")
+                    .unwrap_or(false);
+
+                if is_relevant_file || is_config_file {
+                    if let Some(path_str) = path.to_str() {
+                        all_paths.push(path_str.to_string());
                     }
                 }
-            }
-            
-            collect_files(fs, project_path, &mut all_paths, &excluded_dirs);
+                true
+            });
         }
-        
+
         all_paths
     }
     
@@ -589,6 +1246,8 @@ impl FileModal {
             )));
             // Clear cached folder states
             self.expanded_folders.clear();
+            self.archives.clear();
+            self.filter_matches = None;
             self.expanded_folders.insert(project_path.clone());
         }
     }
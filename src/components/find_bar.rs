@@ -0,0 +1,265 @@
+use eframe::egui::{self, Key, Modifiers};
+use std::ops::Range;
+use crate::components::code_editor::Buffer;
+use crate::components::gutter::SearchHits;
+use crate::core::edit_history::EditKind;
+use crate::core::find::{FindOptions, FindQuery};
+use crate::core::selection::Selection;
+
+/// Find-and-replace bar shown above the editor of the active buffer.
+#[derive(Default)]
+pub struct FindBar {
+    visible: bool,
+    pub show_replace: bool,
+    pub pattern: String,
+    pub replacement: String,
+    pub options: FindOptions,
+    focus_requested: bool,
+    query: Option<FindQuery>,
+    /// Character ranges of all matches, sorted.
+    matches: Vec<Range<usize>>,
+    error: Option<String>,
+    /// Buffer id, revision, pattern and options the matches were computed for.
+    computed_for: Option<(u64, u64, String, FindOptions)>,
+}
+
+impl FindBar {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    /// Opens the bar and focuses the pattern field. A selection on a single line
+    /// becomes the new pattern.
+    pub fn open(&mut self, buffer: Option<&Buffer>, replace: bool) {
+        self.visible = true;
+        self.show_replace |= replace;
+        self.focus_requested = true;
+        if let Some(buffer) = buffer {
+            let selection = buffer.selection();
+            let selected = buffer.slice(selection.range());
+            if !selected.is_empty() && !selected.contains('\n') {
+                self.pattern = selected;
+            }
+        }
+    }
+
+    pub fn close(&mut self) {
+        self.visible = false;
+        self.query = None;
+        self.matches.clear();
+        self.computed_for = None;
+    }
+
+    /// Matches in the buffer the bar was last shown for, as character ranges.
+    pub fn matches(&self) -> &[Range<usize>] {
+        &self.matches
+    }
+
+    fn update_matches(&mut self, buffer: &Buffer) {
+        let key = (buffer.id(), buffer.revision(), self.pattern.clone(), self.options);
+        if self.computed_for.as_ref() == Some(&key) {
+            return;
+        }
+        self.computed_for = Some(key);
+        self.matches.clear();
+        self.error = None;
+        self.query = None;
+        if self.pattern.is_empty() {
+            return;
+        }
+
+        match FindQuery::new(&self.pattern, self.options) {
+            Ok(query) => {
+                self.matches = query
+                    .find_all(&buffer.text())
+                    .into_iter()
+                    .map(|range| buffer.byte_to_char(range.start)..buffer.byte_to_char(range.end))
+                    .collect();
+                self.query = Some(query);
+            }
+            Err(e) => self.error = Some(e),
+        }
+    }
+
+    /// Index of the match that is exactly the primary selection.
+    fn current(&self, buffer: &Buffer) -> Option<usize> {
+        let selection = buffer.selection().range();
+        self.matches
+            .binary_search_by_key(&selection.start, |range| range.start)
+            .ok()
+            .filter(|index| self.matches[*index] == selection)
+    }
+
+    fn select(&self, buffer: &mut Buffer, index: usize) {
+        let range = &self.matches[index];
+        buffer.set_selection(Selection::new(range.start, range.end));
+    }
+
+    /// Selects the first match starting at or after `from`, wrapping around.
+    fn select_from(&mut self, buffer: &mut Buffer, from: usize) {
+        self.update_matches(buffer);
+        if self.matches.is_empty() {
+            return;
+        }
+        let index = self.matches.partition_point(|range| range.start < from);
+        self.select(buffer, if index < self.matches.len() { index } else { 0 });
+    }
+
+    pub fn find_next(&mut self, buffer: &mut Buffer) {
+        let from = buffer.selection().end();
+        self.select_from(buffer, from);
+    }
+
+    pub fn find_previous(&mut self, buffer: &mut Buffer) {
+        self.update_matches(buffer);
+        if self.matches.is_empty() {
+            return;
+        }
+        let before = buffer.selection().start();
+        let index = self.matches.partition_point(|range| range.end <= before);
+        self.select(buffer, index.checked_sub(1).unwrap_or(self.matches.len() - 1));
+    }
+
+    /// Replaces the selected match and moves on to the next one. If no match is
+    /// selected, this only selects the next one.
+    pub fn replace_current(&mut self, buffer: &mut Buffer) {
+        self.update_matches(buffer);
+        let (Some(index), Some(query)) = (self.current(buffer), self.query.as_ref()) else {
+            self.find_next(buffer);
+            return;
+        };
+        let range = self.matches[index].clone();
+        let replacement = self.replacement_for(buffer, query, &buffer.text(), range.clone());
+        let end = range.start + replacement.chars().count();
+        buffer.replace_ranges(vec![(range, replacement)], EditKind::Replace);
+        buffer.set_selection(Selection::caret(end));
+        self.find_next(buffer);
+    }
+
+    /// Replaces every match as a single undo step and returns how many there were.
+    pub fn replace_all(&mut self, buffer: &mut Buffer) -> usize {
+        self.update_matches(buffer);
+        let Some(query) = self.query.as_ref() else {
+            return 0;
+        };
+        let text = buffer.text();
+        let edits: Vec<(Range<usize>, String)> = self.matches
+            .iter()
+            .map(|range| (range.clone(), self.replacement_for(buffer, query, &text, range.clone())))
+            .collect();
+        let count = edits.len();
+        if count > 0 {
+            buffer.replace_ranges(edits, EditKind::Replace);
+        }
+        count
+    }
+
+    fn replacement_for(&self, buffer: &Buffer, query: &FindQuery, text: &str, range: Range<usize>) -> String {
+        let bytes = buffer.char_to_byte(range.start)..buffer.char_to_byte(range.end);
+        query.expand_replacement(text, bytes, &self.replacement)
+    }
+
+    fn status_text(&self, buffer: &Buffer) -> String {
+        if let Some(error) = &self.error {
+            return error.clone();
+        }
+        match (self.current(buffer), self.matches.len()) {
+            _ if self.pattern.is_empty() => String::new(),
+            (_, 0) => "No results".to_string(),
+            (Some(index), count) => format!("{} of {}", index + 1, count),
+            (None, 1) => "1 match".to_string(),
+            (None, count) => format!("{} matches", count),
+        }
+    }
+
+    /// Shows the bar if it is open and keeps `search_hits` in sync with it.
+    pub fn show(&mut self, ui: &mut egui::Ui, buffer: &mut Buffer, search_hits: &mut SearchHits) {
+        if !self.visible {
+            return;
+        }
+        self.update_matches(buffer);
+        let searched_for = (self.pattern.clone(), self.options);
+        let (next, previous) = ui.input_mut(|i| (
+            i.consume_key(Modifiers::NONE, Key::F3),
+            i.consume_key(Modifiers::SHIFT, Key::F3),
+        ));
+        let mut close = false;
+
+        ui.horizontal(|ui| {
+            let response = ui.add(
+                egui::TextEdit::singleline(&mut self.pattern)
+                    .hint_text("Find")
+                    .desired_width(240.0),
+            );
+            if std::mem::take(&mut self.focus_requested) {
+                response.request_focus();
+            }
+            if response.lost_focus() {
+                let (enter, shift, escape) = ui.input(|i| (i.key_pressed(Key::Enter), i.modifiers.shift, i.key_pressed(Key::Escape)));
+                if enter && shift {
+                    self.find_previous(buffer);
+                    response.request_focus();
+                } else if enter {
+                    self.find_next(buffer);
+                    response.request_focus();
+                }
+                close |= escape;
+            }
+
+            ui.toggle_value(&mut self.options.case_sensitive, "Aa").on_hover_text("Match case");
+            ui.toggle_value(&mut self.options.whole_word, "W").on_hover_text("Match whole word");
+            ui.toggle_value(&mut self.options.regex, ".*").on_hover_text("Use regular expression");
+
+            if ui.button("↑").on_hover_text("Previous match (Shift+Enter)").clicked() || previous {
+                self.find_previous(buffer);
+            }
+            if ui.button("↓").on_hover_text("Next match (Enter)").clicked() || next {
+                self.find_next(buffer);
+            }
+            ui.label(self.status_text(buffer));
+
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                if ui.button("✕").on_hover_text("Close (Escape)").clicked() {
+                    close = true;
+                }
+                ui.toggle_value(&mut self.show_replace, "Replace");
+            });
+        });
+
+        if self.show_replace {
+            ui.horizontal(|ui| {
+                let response = ui.add(
+                    egui::TextEdit::singleline(&mut self.replacement)
+                        .hint_text("Replace")
+                        .desired_width(240.0),
+                );
+                let enter = response.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter));
+                if enter {
+                    response.request_focus();
+                }
+                if ui.button("Replace").clicked() || enter {
+                    self.replace_current(buffer);
+                }
+                if ui.button("Replace All").clicked() {
+                    self.replace_all(buffer);
+                }
+            });
+        }
+
+        if close {
+            self.close();
+            search_hits.set_query(None);
+            return;
+        }
+        // Jump to the first match from the cursor while the pattern is typed
+        if (self.pattern.clone(), self.options) != searched_for {
+            let from = buffer.selection().start();
+            self.select_from(buffer, from);
+        }
+        search_hits.set_query(self.query.clone());
+    }
+}
@@ -0,0 +1,602 @@
+use eframe::egui::{self, Align2, Color32, PointerButton, Pos2, Rect, Stroke, Vec2};
+use similar::{DiffOp, TextDiff};
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use crossbeam_channel::{Receiver, TryRecvError};
+use crate::components::code_editor::Buffer;
+use crate::core::find::FindQuery;
+use crate::core::git_manager::GitManager;
+
+const NUMBER_PADDING: f32 = 8.0;
+const STRIPE_WIDTH: f32 = 3.0;
+const STRIPE_GAP: f32 = 3.0;
+/// Width of the fold marker lane relative to the row height.
+const FOLD_LANE_SCALE: f32 = 0.8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DiagnosticSeverity {
+    Info,
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GutterMarkerKind {
+    Diagnostic(DiagnosticSeverity),
+    Bookmark,
+    SearchHit,
+    GitAdded,
+    GitModified,
+    /// Lines were removed right above this line.
+    GitDeleted,
+}
+
+impl GutterMarkerKind {
+    /// Git markers are drawn as a stripe next to the text; everything else
+    /// competes for the icon column.
+    fn is_git(self) -> bool {
+        matches!(self, Self::GitAdded | Self::GitModified | Self::GitDeleted)
+    }
+
+    /// When several icons land on the same line, the highest one is drawn.
+    fn priority(self) -> u8 {
+        match self {
+            Self::Diagnostic(DiagnosticSeverity::Error) => 5,
+            Self::Diagnostic(DiagnosticSeverity::Warning) => 4,
+            Self::Bookmark => 3,
+            Self::Diagnostic(DiagnosticSeverity::Info) => 2,
+            Self::SearchHit => 1,
+            Self::GitAdded | Self::GitModified | Self::GitDeleted => 0,
+        }
+    }
+
+    fn color(self) -> Color32 {
+        match self {
+            Self::Diagnostic(DiagnosticSeverity::Error) => Color32::from_rgb(229, 72, 77),
+            Self::Diagnostic(DiagnosticSeverity::Warning) => Color32::from_rgb(230, 180, 60),
+            Self::Diagnostic(DiagnosticSeverity::Info) => Color32::from_rgb(90, 150, 230),
+            Self::Bookmark => Color32::from_rgb(86, 156, 214),
+            Self::SearchHit => Color32::from_rgb(255, 215, 0),
+            Self::GitAdded => Color32::from_rgb(87, 171, 90),
+            Self::GitModified => Color32::from_rgb(70, 130, 200),
+            Self::GitDeleted => Color32::from_rgb(220, 80, 80),
+        }
+    }
+}
+
+/// A marker attached to a 0-based line of the buffer.
+#[derive(Debug, Clone)]
+pub struct GutterMarker {
+    pub line: usize,
+    pub kind: GutterMarkerKind,
+    pub tooltip: Option<String>,
+}
+
+/// Source of gutter markers. Providers are asked for markers on the visible
+/// lines every frame and are told about clicks on the gutter.
+pub trait GutterMarkerProvider {
+    /// Adds the markers for `lines` to `markers`.
+    fn markers(&mut self, buffer: &Buffer, lines: Range<usize>, markers: &mut Vec<GutterMarker>);
+
+    /// Called when a gutter line is clicked. Returning `true` stops the click from
+    /// reaching the remaining providers.
+    fn on_click(&mut self, _buffer: &Buffer, _line: usize, _button: PointerButton) -> bool {
+        false
+    }
+}
+
+/// Line numbers plus the markers of a set of providers, drawn to the left of
+/// the text by [`crate::components::editor_view::EditorView`].
+#[derive(Default)]
+pub struct Gutter<'a> {
+    providers: Vec<&'a mut dyn GutterMarkerProvider>,
+}
+
+impl<'a> Gutter<'a> {
+    pub fn new() -> Self {
+        Self { providers: Vec::new() }
+    }
+
+    pub fn with_provider(mut self, provider: &'a mut dyn GutterMarkerProvider) -> Self {
+        self.providers.push(provider);
+        self
+    }
+
+    pub fn add_provider(&mut self, provider: &'a mut dyn GutterMarkerProvider) {
+        self.providers.push(provider);
+    }
+
+    pub(crate) fn width(&self, line_count: usize, char_width: f32, row_height: f32) -> f32 {
+        let digits = line_count.max(1).to_string().len().max(3);
+        row_height + digits as f32 * char_width + NUMBER_PADDING + row_height * FOLD_LANE_SCALE + STRIPE_WIDTH + STRIPE_GAP
+    }
+
+    /// Horizontal extent of the fold marker lane, between the line numbers and
+    /// the git stripe.
+    pub(crate) fn fold_lane(&self, rect: Rect, row_height: f32) -> Range<f32> {
+        let right = rect.right() - STRIPE_GAP - STRIPE_WIDTH;
+        right - row_height * FOLD_LANE_SCALE..right
+    }
+
+    pub(crate) fn click(&mut self, buffer: &Buffer, line: usize, button: PointerButton) {
+        for provider in self.providers.iter_mut() {
+            if provider.on_click(buffer, line, button) {
+                break;
+            }
+        }
+    }
+
+    /// Paints the gutter for the visible `lines` into `rect`, which is fixed to the
+    /// left edge of the viewport so it stays put while the text scrolls
+    /// horizontally. Each line comes with its fold state: `None` if no region
+    /// starts there, otherwise whether the region is folded.
+    pub(crate) fn paint(
+        &mut self,
+        ui: &egui::Ui,
+        buffer: &Buffer,
+        rect: Rect,
+        lines: &[(usize, Option<bool>)],
+        line_top: &dyn Fn(usize) -> f32,
+        row_height: f32,
+    ) {
+        let (Some(&(first_line, _)), Some(&(last_line, _))) = (lines.first(), lines.last()) else {
+            return;
+        };
+        let mut markers = Vec::new();
+        for provider in self.providers.iter_mut() {
+            provider.markers(buffer, first_line..last_line + 1, &mut markers);
+        }
+        // Markers on folded away lines are not shown
+        markers.retain(|marker| lines.binary_search_by_key(&marker.line, |(line, _)| *line).is_ok());
+
+        let painter = ui.painter().with_clip_rect(rect.intersect(ui.clip_rect()));
+        let visuals = ui.visuals();
+        painter.rect_filled(rect, 0.0, visuals.extreme_bg_color);
+        painter.line_segment(
+            [rect.right_top(), rect.right_bottom()],
+            Stroke::new(1.0, visuals.widgets.noninteractive.bg_stroke.color),
+        );
+
+        let font_id = egui::TextStyle::Monospace.resolve(ui.style());
+        let cursor_line = buffer.char_to_line(buffer.selection().head);
+        let stripe_left = rect.right() - STRIPE_GAP - STRIPE_WIDTH;
+        let fold_lane = self.fold_lane(rect, row_height);
+        let number_right = fold_lane.start - NUMBER_PADDING / 2.0;
+
+        for &(line, _) in lines {
+            let top = line_top(line);
+            let color = if line == cursor_line { visuals.text_color() } else { visuals.weak_text_color() };
+            painter.text(
+                Pos2::new(number_right, top),
+                Align2::RIGHT_TOP,
+                (line + 1).to_string(),
+                font_id.clone(),
+                color,
+            );
+        }
+
+        let mut icons: HashMap<usize, GutterMarkerKind> = HashMap::new();
+        for marker in &markers {
+            let top = line_top(marker.line);
+            if marker.kind.is_git() {
+                paint_git_marker(&painter, marker.kind, stripe_left, top, row_height);
+            } else {
+                let icon = icons.entry(marker.line).or_insert(marker.kind);
+                if marker.kind.priority() > icon.priority() {
+                    *icon = marker.kind;
+                }
+            }
+        }
+        for (line, kind) in icons {
+            let center = Pos2::new(rect.left() + row_height / 2.0, line_top(line) + row_height / 2.0);
+            paint_icon(&painter, kind, center, row_height);
+        }
+        for &(line, folded) in lines {
+            let Some(folded) = folded else {
+                continue;
+            };
+            let center = Pos2::new((fold_lane.start + fold_lane.end) / 2.0, line_top(line) + row_height / 2.0);
+            paint_fold_marker(&painter, folded, center, row_height, visuals.weak_text_color());
+        }
+
+        let hovered_line = ui
+            .input(|i| i.pointer.hover_pos())
+            .filter(|pos| rect.contains(*pos))
+            .and_then(|pos| lines.iter().map(|(line, _)| *line).find(|line| pos.y >= line_top(*line) && pos.y < line_top(*line) + row_height));
+        if let Some(line) = hovered_line {
+            let tooltips: Vec<&str> = markers
+                .iter()
+                .filter(|marker| marker.line == line)
+                .filter_map(|marker| marker.tooltip.as_deref())
+                .collect();
+            if !tooltips.is_empty() {
+                egui::show_tooltip_at_pointer(ui.ctx(), ui.layer_id(), ui.id().with("gutter_tooltip"), |ui| {
+                    for tooltip in tooltips {
+                        ui.label(tooltip);
+                    }
+                });
+            }
+        }
+    }
+}
+
+fn paint_icon(painter: &egui::Painter, kind: GutterMarkerKind, center: Pos2, row_height: f32) {
+    match kind {
+        GutterMarkerKind::Bookmark => {
+            let size = Vec2::new(row_height * 0.45, row_height * 0.6);
+            painter.rect_filled(Rect::from_center_size(center, size), 1.0, kind.color());
+        }
+        GutterMarkerKind::SearchHit => {
+            painter.circle_filled(center, row_height * 0.15, kind.color());
+        }
+        _ => {
+            painter.circle_filled(center, row_height * 0.28, kind.color());
+        }
+    }
+}
+
+/// A triangle pointing right for a folded region and down for an open one.
+fn paint_fold_marker(painter: &egui::Painter, folded: bool, center: Pos2, row_height: f32, color: Color32) {
+    let half = row_height * 0.2;
+    let points = if folded {
+        vec![
+            Pos2::new(center.x - half * 0.6, center.y - half),
+            Pos2::new(center.x + half * 0.8, center.y),
+            Pos2::new(center.x - half * 0.6, center.y + half),
+        ]
+    } else {
+        vec![
+            Pos2::new(center.x - half, center.y - half * 0.6),
+            Pos2::new(center.x + half, center.y - half * 0.6),
+            Pos2::new(center.x, center.y + half * 0.8),
+        ]
+    };
+    painter.add(egui::Shape::convex_polygon(points, color, Stroke::NONE));
+}
+
+fn paint_git_marker(painter: &egui::Painter, kind: GutterMarkerKind, left: f32, top: f32, row_height: f32) {
+    if kind == GutterMarkerKind::GitDeleted {
+        let size = STRIPE_WIDTH + 2.0;
+        let points = vec![
+            Pos2::new(left, top - size),
+            Pos2::new(left + size, top),
+            Pos2::new(left, top + size),
+        ];
+        painter.add(egui::Shape::convex_polygon(points, kind.color(), Stroke::NONE));
+    } else {
+        let stripe = Rect::from_min_size(Pos2::new(left, top), Vec2::new(STRIPE_WIDTH, row_height));
+        painter.rect_filled(stripe, 0.0, kind.color());
+    }
+}
+
+/// Line numbers that follow the text as lines are inserted or removed above them.
+#[derive(Debug, Clone, Default)]
+struct TrackedLines {
+    revision: u64,
+    lines: BTreeSet<usize>,
+}
+
+impl TrackedLines {
+    fn sync(&mut self, buffer: &Buffer) {
+        if let Some(changes) = buffer.line_changes_since(self.revision) {
+            for change in changes {
+                self.lines = self.lines.iter().map(|line| change.map_line(*line)).collect();
+            }
+        }
+        let last_line = buffer.line_count().saturating_sub(1);
+        self.lines = self.lines.iter().map(|line| (*line).min(last_line)).collect();
+        self.revision = buffer.revision();
+    }
+}
+
+/// Per-buffer bookmarks, toggled by clicking the gutter.
+#[derive(Default)]
+pub struct Bookmarks {
+    buffers: HashMap<u64, TrackedLines>,
+}
+
+impl Bookmarks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lines(&mut self, buffer: &Buffer) -> &mut BTreeSet<usize> {
+        let tracked = self.buffers.entry(buffer.id()).or_insert_with(|| TrackedLines {
+            revision: buffer.revision(),
+            lines: BTreeSet::new(),
+        });
+        tracked.sync(buffer);
+        &mut tracked.lines
+    }
+
+    pub fn toggle(&mut self, buffer: &Buffer, line: usize) {
+        let lines = self.lines(buffer);
+        if !lines.remove(&line) {
+            lines.insert(line);
+        }
+    }
+
+    /// Next bookmarked line after `line`, wrapping around to the first one.
+    pub fn next(&mut self, buffer: &Buffer, line: usize) -> Option<usize> {
+        let lines = self.lines(buffer);
+        lines.range(line + 1..).next().or_else(|| lines.iter().next()).copied()
+    }
+
+    /// Previous bookmarked line before `line`, wrapping around to the last one.
+    pub fn previous(&mut self, buffer: &Buffer, line: usize) -> Option<usize> {
+        let lines = self.lines(buffer);
+        lines.range(..line).next_back().or_else(|| lines.iter().next_back()).copied()
+    }
+
+    pub fn remove_buffer(&mut self, buffer_id: u64) {
+        self.buffers.remove(&buffer_id);
+    }
+}
+
+impl GutterMarkerProvider for Bookmarks {
+    fn markers(&mut self, buffer: &Buffer, lines: Range<usize>, markers: &mut Vec<GutterMarker>) {
+        markers.extend(self.lines(buffer).range(lines).map(|line| GutterMarker {
+            line: *line,
+            kind: GutterMarkerKind::Bookmark,
+            tooltip: Some("Bookmark".to_string()),
+        }));
+    }
+
+    fn on_click(&mut self, buffer: &Buffer, line: usize, button: PointerButton) -> bool {
+        if button != PointerButton::Primary {
+            return false;
+        }
+        self.toggle(buffer, line);
+        true
+    }
+}
+
+/// Marks the lines that contain the current search term.
+#[derive(Default)]
+pub struct SearchHits {
+    query: Option<FindQuery>,
+}
+
+impl SearchHits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Marks lines containing `pattern` literally.
+    pub fn set_pattern(&mut self, pattern: Option<String>) {
+        self.query = pattern.and_then(|p| FindQuery::literal(&p).ok());
+    }
+
+    /// Marks lines with a match of `query`, e.g. the find bar's regex.
+    pub fn set_query(&mut self, query: Option<FindQuery>) {
+        self.query = query;
+    }
+}
+
+impl GutterMarkerProvider for SearchHits {
+    fn markers(&mut self, buffer: &Buffer, lines: Range<usize>, markers: &mut Vec<GutterMarker>) {
+        let Some(query) = &self.query else {
+            return;
+        };
+        for line in lines {
+            if query.is_match(&buffer.line(line)) {
+                markers.push(GutterMarker {
+                    line,
+                    kind: GutterMarkerKind::SearchHit,
+                    tooltip: None,
+                });
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    /// 0-based line the diagnostic refers to.
+    pub line: usize,
+    pub severity: DiagnosticSeverity,
+    pub message: String,
+}
+
+/// Diagnostics per file path, filled in by whatever produces them (build
+/// output, language servers, ...).
+#[derive(Default)]
+pub struct Diagnostics {
+    files: HashMap<String, Vec<Diagnostic>>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces all diagnostics for `file_path`.
+    pub fn set(&mut self, file_path: &str, diagnostics: Vec<Diagnostic>) {
+        if diagnostics.is_empty() {
+            self.files.remove(file_path);
+        } else {
+            self.files.insert(file_path.to_string(), diagnostics);
+        }
+    }
+
+    pub fn get(&self, file_path: &str) -> &[Diagnostic] {
+        self.files.get(file_path).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    pub fn clear(&mut self) {
+        self.files.clear();
+    }
+}
+
+impl GutterMarkerProvider for Diagnostics {
+    fn markers(&mut self, buffer: &Buffer, lines: Range<usize>, markers: &mut Vec<GutterMarker>) {
+        let Some(file_path) = &buffer.file_path else {
+            return;
+        };
+        markers.extend(
+            self.get(file_path)
+                .iter()
+                .filter(|diagnostic| lines.contains(&diagnostic.line))
+                .map(|diagnostic| GutterMarker {
+                    line: diagnostic.line,
+                    kind: GutterMarkerKind::Diagnostic(diagnostic.severity),
+                    tooltip: Some(diagnostic.message.clone()),
+                }),
+        );
+    }
+}
+
+#[derive(Debug, Clone)]
+struct GitHunk {
+    kind: GutterMarkerKind,
+    lines: Range<usize>,
+}
+
+/// Added, modified and deleted lines compared to the committed version of the
+/// file. Reading the committed text and diffing against it happen on a
+/// background thread, one diff per buffer at a time, and the last hunks stay
+/// on screen until the diff for the current revision arrives.
+#[derive(Default)]
+pub struct GitHunks {
+    /// The committed text per file path, `None` for untracked files.
+    committed: HashMap<String, Option<Arc<String>>>,
+    buffers: HashMap<u64, BufferHunks>,
+}
+
+#[derive(Default)]
+struct BufferHunks {
+    /// The revision `hunks` belong to, `None` when they need computing again.
+    revision: Option<u64>,
+    hunks: Vec<GitHunk>,
+    running: Option<Receiver<DiffResult>>,
+}
+
+struct DiffResult {
+    revision: u64,
+    file_path: String,
+    committed: Option<Arc<String>>,
+    hunks: Vec<GitHunk>,
+}
+
+impl GitHunks {
+    const DIFF_TIMEOUT: Duration = Duration::from_millis(50);
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forgets the committed versions, e.g. after a commit or checkout. The
+    /// old hunks are shown until the new ones are ready.
+    pub fn invalidate(&mut self) {
+        self.committed.clear();
+        for hunks in self.buffers.values_mut() {
+            hunks.revision = None;
+            hunks.running = None;
+        }
+    }
+
+    /// Picks up a finished diff of `buffer` and starts the next one if the
+    /// buffer changed since. Call before drawing its markers.
+    pub fn update(&mut self, ctx: &egui::Context, buffer: &Buffer) {
+        let Some(file_path) = buffer.file_path.clone() else {
+            return;
+        };
+        let hunks = self.buffers.entry(buffer.id()).or_default();
+        if let Some(receiver) = &hunks.running {
+            match receiver.try_recv() {
+                Ok(result) => {
+                    hunks.revision = Some(result.revision);
+                    hunks.hunks = result.hunks;
+                    self.committed.insert(result.file_path, result.committed);
+                }
+                Err(TryRecvError::Empty) => return,
+                Err(TryRecvError::Disconnected) => {}
+            }
+            hunks.running = None;
+        }
+        if hunks.revision == Some(buffer.revision()) {
+            return;
+        }
+
+        let committed = self.committed.get(&file_path).cloned();
+        let revision = buffer.revision();
+        let text = buffer.snapshot();
+        let (sender, receiver) = crossbeam_channel::bounded(1);
+        let ctx = ctx.clone();
+        std::thread::spawn(move || {
+            let committed = committed.unwrap_or_else(|| {
+                GitManager::file_at_head(Path::new(&file_path)).ok().flatten().map(Arc::new)
+            });
+            let hunks = committed
+                .as_deref()
+                .map(|committed| diff_hunks(committed, &text.to_string()))
+                .unwrap_or_default();
+            let result = DiffResult {
+                revision,
+                file_path,
+                committed,
+                hunks,
+            };
+            if sender.send(result).is_ok() {
+                ctx.request_repaint();
+            }
+        });
+        hunks.running = Some(receiver);
+    }
+}
+
+impl GutterMarkerProvider for GitHunks {
+    fn markers(&mut self, buffer: &Buffer, lines: Range<usize>, markers: &mut Vec<GutterMarker>) {
+        let Some(hunks) = self.buffers.get(&buffer.id()) else {
+            return;
+        };
+        for hunk in &hunks.hunks {
+            let tooltip = match hunk.kind {
+                GutterMarkerKind::GitAdded => "Added lines",
+                GutterMarkerKind::GitModified => "Modified lines",
+                _ => "Deleted lines",
+            };
+            let start = hunk.lines.start.max(lines.start);
+            let end = hunk.lines.end.min(lines.end);
+            markers.extend((start..end).map(|line| GutterMarker {
+                line,
+                kind: hunk.kind,
+                tooltip: Some(tooltip.to_string()),
+            }));
+        }
+    }
+}
+
+fn diff_hunks(committed: &str, current: &str) -> Vec<GitHunk> {
+    let committed = committed.replace("\r\n", "\n");
+    let current = current.replace("\r\n", "\n");
+    let last_line = current.split('\n').count().saturating_sub(1);
+    let diff = TextDiff::configure()
+        .timeout(GitHunks::DIFF_TIMEOUT)
+        .diff_lines(&committed, &current);
+
+    diff.ops()
+        .iter()
+        .filter_map(|op| match *op {
+            DiffOp::Equal { .. } => None,
+            DiffOp::Insert { new_index, new_len, .. } => Some(GitHunk {
+                kind: GutterMarkerKind::GitAdded,
+                lines: new_index..new_index + new_len,
+            }),
+            DiffOp::Replace { new_index, new_len, .. } => Some(GitHunk {
+                kind: GutterMarkerKind::GitModified,
+                lines: new_index..new_index + new_len,
+            }),
+            DiffOp::Delete { new_index, .. } => {
+                let line = new_index.min(last_line);
+                Some(GitHunk {
+                    kind: GutterMarkerKind::GitDeleted,
+                    lines: line..line + 1,
+                })
+            }
+        })
+        .collect()
+}
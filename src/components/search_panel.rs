@@ -0,0 +1,331 @@
+use eframe::egui::{self, Key, Modifiers, ScrollArea, TextFormat};
+use eframe::egui::text::LayoutJob;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use crate::core::find::FindOptions;
+use crate::core::search::SearchResult;
+
+/// Which edge of the window the search results panel is docked to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PanelDock {
+    #[default]
+    Bottom,
+    Right,
+}
+
+/// A search that was run, kept so it can be run again.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SearchHistoryEntry {
+    pub query: String,
+    pub options: FindOptions,
+    /// Searched the whole project rather than the current file.
+    pub project: bool,
+}
+
+impl SearchHistoryEntry {
+    fn label(&self) -> String {
+        let flags: Vec<&str> = [
+            (self.options.case_sensitive, "Aa"),
+            (self.options.whole_word, "W"),
+            (self.options.regex, ".*"),
+        ]
+        .iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, flag)| *flag)
+        .collect();
+        let scope = if self.project { "" } else { " in file" };
+        if flags.is_empty() {
+            format!("{}{}", self.query, scope)
+        } else {
+            format!("{}{}  [{}]", self.query, scope, flags.join(" "))
+        }
+    }
+}
+
+/// The hits of one search in one file.
+struct ResultGroup {
+    display_path: String,
+    hits: Vec<SearchResult>,
+    collapsed: bool,
+}
+
+/// What the IDE should do after the panel was shown.
+pub enum SearchPanelAction {
+    /// Open the file of a hit and select its match.
+    Open(SearchResult),
+    Rerun(SearchHistoryEntry),
+}
+
+/// Results of the last search, grouped by file, in a panel that stays open
+/// while the hits are visited. Keeps the history of searches shown in it.
+pub struct SearchPanel {
+    pub visible: bool,
+    pub dock: PanelDock,
+    search: Option<SearchHistoryEntry>,
+    groups: Vec<ResultGroup>,
+    /// Group and hit index of the hit that was opened last.
+    current: Option<(usize, usize)>,
+    scroll_to_current: bool,
+    /// Most recent first.
+    history: Vec<SearchHistoryEntry>,
+}
+
+impl SearchPanel {
+    const MAX_HISTORY: usize = 30;
+
+    pub fn new() -> Self {
+        Self {
+            visible: false,
+            dock: PanelDock::default(),
+            search: None,
+            groups: Vec::new(),
+            current: None,
+            scroll_to_current: false,
+            history: Vec::new(),
+        }
+    }
+
+    pub fn history(&self) -> &[SearchHistoryEntry] {
+        &self.history
+    }
+
+    pub fn set_history(&mut self, mut history: Vec<SearchHistoryEntry>) {
+        history.truncate(Self::MAX_HISTORY);
+        self.history = history;
+    }
+
+    /// Shows the results of `search`, replacing the previous ones, and adds the
+    /// search to the history.
+    pub fn set_results(&mut self, search: SearchHistoryEntry, results: &[SearchResult], project_root: Option<&Path>) {
+        let mut results = results.to_vec();
+        // Project search finds files in parallel, so put them in a stable order
+        results.sort_by(|a, b| a.file_path.cmp(&b.file_path).then(a.line_number.cmp(&b.line_number)));
+
+        self.groups.clear();
+        for result in results {
+            match self.groups.last_mut() {
+                Some(group) if group.hits[0].file_path == result.file_path => group.hits.push(result),
+                _ => {
+                    let display_path = match (&result.file_path, project_root) {
+                        (Some(path), Some(root)) => Path::new(path)
+                            .strip_prefix(root)
+                            .map_or(path.clone(), |relative| relative.display().to_string()),
+                        (Some(path), None) => path.clone(),
+                        (None, _) => "Untitled".to_string(),
+                    };
+                    self.groups.push(ResultGroup { display_path, hits: vec![result], collapsed: false });
+                }
+            }
+        }
+
+        self.history.retain(|entry| *entry != search);
+        self.history.insert(0, search.clone());
+        self.history.truncate(Self::MAX_HISTORY);
+        self.search = Some(search);
+        self.current = None;
+        self.visible = true;
+    }
+
+    /// Makes the hit for the same file, line and match as `result` the current one.
+    pub fn select(&mut self, result: &SearchResult) {
+        self.current = self.groups.iter().enumerate().find_map(|(group_index, group)| {
+            group.hits
+                .iter()
+                .position(|hit| {
+                    hit.file_path == result.file_path
+                        && hit.line_number == result.line_number
+                        && hit.match_range == result.match_range
+                })
+                .map(|hit_index| (group_index, hit_index))
+        });
+        self.scroll_to_current = true;
+    }
+
+    /// Moves to the next hit, wrapping around at the end, and returns it.
+    pub fn next_hit(&mut self) -> Option<SearchResult> {
+        let (group, hit) = match self.current {
+            Some((group, hit)) if hit + 1 < self.groups[group].hits.len() => (group, hit + 1),
+            Some((group, _)) => ((group + 1) % self.groups.len(), 0),
+            None if !self.groups.is_empty() => (0, 0),
+            None => return None,
+        };
+        self.go_to(group, hit)
+    }
+
+    /// Moves to the previous hit, wrapping around at the start, and returns it.
+    pub fn previous_hit(&mut self) -> Option<SearchResult> {
+        let group = match self.current {
+            Some((group, hit)) if hit > 0 => return self.go_to(group, hit - 1),
+            Some((group, _)) => group.checked_sub(1).unwrap_or(self.groups.len() - 1),
+            None => self.groups.len().checked_sub(1)?,
+        };
+        let last_hit = self.groups[group].hits.len() - 1;
+        self.go_to(group, last_hit)
+    }
+
+    fn go_to(&mut self, group: usize, hit: usize) -> Option<SearchResult> {
+        self.groups[group].collapsed = false;
+        self.current = Some((group, hit));
+        self.scroll_to_current = true;
+        self.visible = true;
+        Some(self.groups[group].hits[hit].clone())
+    }
+
+    fn summary(&self) -> String {
+        let hits: usize = self.groups.iter().map(|group| group.hits.len()).sum();
+        match &self.search {
+            Some(search) => format!(
+                "{} results in {} files for \"{}\"",
+                hits,
+                self.groups.len(),
+                search.query
+            ),
+            None => "No search results".to_string(),
+        }
+    }
+
+    /// The line of a hit with its match highlighted.
+    fn hit_label(ui: &egui::Ui, hit: &SearchResult) -> LayoutJob {
+        let font_id = egui::TextStyle::Monospace.resolve(ui.style());
+        let format = TextFormat { font_id, color: ui.visuals().text_color(), ..TextFormat::default() };
+        let line = &hit.line_content;
+        let indent = (line.len() - line.trim_start().len()).min(hit.match_range.start);
+        let end = hit.match_range.end.min(line.len());
+
+        let mut job = LayoutJob::default();
+        job.append(
+            &format!("{:>5}  ", hit.line_number),
+            0.0,
+            TextFormat { color: ui.visuals().weak_text_color(), ..format.clone() },
+        );
+        job.append(&line[indent..hit.match_range.start], 0.0, format.clone());
+        job.append(
+            &line[hit.match_range.start..end],
+            0.0,
+            TextFormat {
+                background: ui.visuals().selection.bg_fill,
+                color: ui.visuals().strong_text_color(),
+                ..format.clone()
+            },
+        );
+        job.append(&line[end..], 0.0, format);
+        job
+    }
+
+    /// F4 and Shift+F4 step through the hits from anywhere in the IDE.
+    pub fn handle_shortcuts(&mut self, ctx: &egui::Context) -> Option<SearchPanelAction> {
+        let (next, previous) = ctx.input_mut(|i| (
+            i.consume_key(Modifiers::NONE, Key::F4),
+            i.consume_key(Modifiers::SHIFT, Key::F4),
+        ));
+        if next {
+            self.next_hit().map(SearchPanelAction::Open)
+        } else if previous {
+            self.previous_hit().map(SearchPanelAction::Open)
+        } else {
+            None
+        }
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui) -> Option<SearchPanelAction> {
+        let mut action = None;
+
+        ui.horizontal(|ui| {
+            ui.strong(self.summary());
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                if ui.button("✕").on_hover_text("Close").clicked() {
+                    self.visible = false;
+                }
+                let (dock_icon, dock_hint) = match self.dock {
+                    PanelDock::Bottom => ("◨", "Dock to the right"),
+                    PanelDock::Right => ("⬓", "Dock to the bottom"),
+                };
+                if ui.button(dock_icon).on_hover_text(dock_hint).clicked() {
+                    self.dock = match self.dock {
+                        PanelDock::Bottom => PanelDock::Right,
+                        PanelDock::Right => PanelDock::Bottom,
+                    };
+                }
+                if ui.button("⊞").on_hover_text("Expand all").clicked() {
+                    self.groups.iter_mut().for_each(|group| group.collapsed = false);
+                }
+                if ui.button("⊟").on_hover_text("Collapse all").clicked() {
+                    self.groups.iter_mut().for_each(|group| group.collapsed = true);
+                }
+                ui.add_enabled_ui(!self.history.is_empty(), |ui| {
+                    ui.menu_button("History", |ui| {
+                        for entry in &self.history {
+                            if ui.button(entry.label()).clicked() {
+                                action = Some(SearchPanelAction::Rerun(entry.clone()));
+                                ui.close_menu();
+                            }
+                        }
+                    });
+                });
+            });
+        });
+        ui.separator();
+
+        // Groups and their hits as one list of rows, so only the visible ones are laid out
+        let rows: Vec<(usize, Option<usize>)> = self.groups
+            .iter()
+            .enumerate()
+            .flat_map(|(group_index, group)| {
+                let hits = if group.collapsed { 0 } else { group.hits.len() };
+                std::iter::once((group_index, None)).chain((0..hits).map(move |hit| (group_index, Some(hit))))
+            })
+            .collect();
+        let row_height = ui.spacing().interact_size.y + ui.spacing().item_spacing.y;
+
+        let mut scroll_area = ScrollArea::vertical().id_source("search_panel_results").auto_shrink([false, false]);
+        if std::mem::take(&mut self.scroll_to_current) {
+            let current_row = self.current.and_then(|(group, hit)| rows.iter().position(|row| *row == (group, Some(hit))));
+            if let Some(row) = current_row {
+                let centered = row as f32 * row_height - ui.available_height() / 2.0;
+                scroll_area = scroll_area.vertical_scroll_offset(centered.max(0.0));
+            }
+        }
+
+        let mut toggled = None;
+        scroll_area.show_rows(ui, row_height, rows.len(), |ui, visible_rows| {
+            for &(group_index, hit_index) in &rows[visible_rows] {
+                let group = &self.groups[group_index];
+                match hit_index {
+                    None => {
+                        let arrow = if group.collapsed { "▶" } else { "▼" };
+                        let label = format!("{} {}  ({})", arrow, group.display_path, group.hits.len());
+                        if ui.selectable_label(false, label).clicked() {
+                            toggled = Some(group_index);
+                        }
+                    }
+                    Some(hit_index) => {
+                        let hit = &group.hits[hit_index];
+                        let selected = self.current == Some((group_index, hit_index));
+                        let response = ui
+                            .horizontal(|ui| {
+                                ui.add_space(16.0);
+                                ui.selectable_label(selected, Self::hit_label(ui, hit))
+                            })
+                            .inner;
+                        if response.clicked() {
+                            self.current = Some((group_index, hit_index));
+                            action = Some(SearchPanelAction::Open(hit.clone()));
+                        }
+                    }
+                }
+            }
+        });
+        if let Some(group_index) = toggled {
+            let group = &mut self.groups[group_index];
+            group.collapsed = !group.collapsed;
+        }
+
+        action
+    }
+}
+
+impl Default for SearchPanel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,6 +1,7 @@
 use eframe::egui;
-use crate::utils::themes::{custom_theme, Theme};
+use crate::utils::themes::{self, custom_theme, Theme};
 use crate::core::app_creation::AppCreation;
+use crate::core::smart_edit::{EditorSettings, IndentStyle};
 use crate::plugin_manager::PluginManager;
 use std::sync::{Arc, Mutex};
 use rfd::FileDialog;
@@ -19,6 +20,12 @@ pub struct SettingsModal {
     pub show: bool,
     settings_tab: SettingsTab,
     pub current_theme: Theme,
+    /// Names of the available code color schemes, sorted.
+    color_schemes: Vec<String>,
+    /// A color scheme was imported since the last check.
+    color_schemes_changed: bool,
+    color_scheme_error: Option<String>,
+    pub editor_settings: EditorSettings,
     api_key: String, // Add field for API key
     api_key_changed: bool, // Track if API key has changed
     ai_model: String, // Add field for AI model
@@ -38,6 +45,10 @@ impl SettingsModal {
             show: false,
             settings_tab: SettingsTab::Personalization,
             current_theme: Theme::default(),
+            color_schemes: color_scheme_names(),
+            color_schemes_changed: false,
+            color_scheme_error: None,
+            editor_settings: EditorSettings::default(),
             api_key: String::new(),
             api_key_changed: false,
             ai_model: "Qwen/Qwen2.5-Coder-32B-Instruct".to_string(),
@@ -91,6 +102,14 @@ impl SettingsModal {
         changed
     }
 
+    /// Whether a color scheme was imported since the last call, so the
+    /// editor and terminal should load the color schemes again.
+    pub fn take_color_schemes_changed(&mut self) -> bool {
+        let changed = self.color_schemes_changed;
+        self.color_schemes_changed = false;
+        changed
+    }
+
     pub fn show(&mut self, ctx: &egui::Context) {
         if !self.show {
             return;
@@ -185,6 +204,53 @@ impl SettingsModal {
             self.current_theme = Theme::purple();
             self.apply_theme(ctx);
         }
+
+        ui.add_space(10.0);
+        ui.horizontal(|ui| {
+            ui.label("Code Color Scheme:");
+            let mut selected = self.current_theme.color_scheme_name().to_string();
+            egui::ComboBox::from_id_source("color_scheme")
+                .selected_text(&selected)
+                .show_ui(ui, |ui| {
+                    for name in &self.color_schemes {
+                        ui.selectable_value(&mut selected, name.clone(), name);
+                    }
+                });
+            if selected != self.current_theme.color_scheme_name() {
+                self.current_theme.color_scheme = selected;
+            }
+            if ui.button("Import .tmTheme…").clicked() {
+                if let Some(path) = FileDialog::new().add_filter("Color Scheme", &["tmTheme"]).pick_file() {
+                    match themes::import_color_scheme(&path) {
+                        Ok(name) => {
+                            self.current_theme.color_scheme = name;
+                            self.color_schemes = color_scheme_names();
+                            self.color_schemes_changed = true;
+                            self.color_scheme_error = None;
+                        }
+                        Err(e) => self.color_scheme_error = Some(e),
+                    }
+                }
+            }
+        });
+        if let Some(error) = &self.color_scheme_error {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+
+        ui.add_space(10.0);
+        ui.heading("Editor");
+        ui.add_space(5.0);
+        let settings = &mut self.editor_settings;
+        ui.horizontal(|ui| {
+            ui.label("Indent with:");
+            ui.radio_value(&mut settings.indent_style, IndentStyle::Spaces, "Spaces");
+            ui.radio_value(&mut settings.indent_style, IndentStyle::Tabs, "Tabs");
+        });
+        ui.horizontal(|ui| {
+            ui.label("Indent width:");
+            ui.add(egui::DragValue::new(&mut settings.indent_width).range(1..=8));
+        });
+        ui.checkbox(&mut settings.auto_close, "Close brackets and quotes automatically");
     }
 
     fn show_app_creation_settings(&mut self, ui: &mut egui::Ui) {
@@ -328,4 +394,8 @@ impl SettingsModal {
         // Example validation: API key should not be empty
         !self.api_key.is_empty() && !self.ai_model.is_empty()
     }
-}
\ No newline at end of file
+}
+
+fn color_scheme_names() -> Vec<String> {
+    themes::load_color_schemes().themes.into_keys().collect()
+}
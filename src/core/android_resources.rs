@@ -70,7 +70,7 @@ impl AndroidResources {
 
             // Make gradlew executable on Unix-like systems
             #[cfg(unix)]
-            if dest_path == "gradlew" {
+            if *dest_path == "gradlew" {
                 use std::os::unix::fs::PermissionsExt;
                 let mut perms = fs::metadata(&temp_path)?.permissions();
                 perms.set_mode(0o755);
@@ -1,8 +1,8 @@
-use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::runtime::Runtime;
 use crate::core::file_system::FileSystem;
+use crate::core::vfs::Vfs;
 use crate::core::android_resources::AndroidResources;
 use crate::core::android_sdk_manager::AndroidSdkManager;
 
@@ -59,14 +59,35 @@ impl AppCreation {
             Ok::<_, Box<dyn std::error::Error>>(())
         })?;
 
+        let project_dir = PathBuf::from(&self.app_path).join(&self.app_name);
+        let fs = FileSystem::new(project_dir.to_str().unwrap());
+        self.write_project(&fs, &project_dir, &sdk_manager.get_sdk_path())?;
+        self.install_gradle_wrapper(&fs, &project_dir)?;
+
+        // Save resources state
+        (self.logger)("Finalizing project setup...".to_string());
+        self.resources.save_state()?;
+        (self.progress_callback)(1.0);
+        (self.logger)(format!("App creation completed. Project created at: {}", project_dir.display()));
+
+        Ok(())
+    }
+
+    /// Writes the sources, resources and build files of the new project to
+    /// `fs`. Everything except the Gradle wrapper, which is copied from the
+    /// local Gradle distribution.
+    pub fn write_project<V: Vfs>(
+        &self,
+        fs: &FileSystem<V>,
+        project_dir: &Path,
+        sdk_path: &Path,
+    ) -> Result<(), Box<dyn std::error::Error>> {
         // Create root project directory
         (self.logger)("Creating project structure...".to_string());
-        let project_dir = PathBuf::from(&self.app_path).join(&self.app_name);
         let app_dir = project_dir.join("app"); // Create app subdirectory
-        let fs = Arc::new(FileSystem::new(project_dir.to_str().unwrap()));
-        
+
         // Create root and app directories
-        fs.create_directory(&project_dir)?;
+        fs.create_directory(project_dir)?;
         fs.create_directory(&app_dir)?;
         (self.logger)("Created root directories".to_string());
         (self.progress_callback)(0.5);
@@ -129,59 +150,21 @@ impl AppCreation {
             );
 
             // Save both regular and round icons
-            fs::write(
-                res_dir.join(dir_name).join("ic_launcher.xml"),
-                &icon_content
-            )?;
-            fs::write(
-                res_dir.join(dir_name).join("ic_launcher_round.xml"),
-                &icon_content
-            )?;
+            fs.save_file(&res_dir.join(dir_name).join("ic_launcher.xml"), &icon_content)?;
+            fs.save_file(&res_dir.join(dir_name).join("ic_launcher_round.xml"), &icon_content)?;
         }
 
         // Create the base icon drawable
         let drawable_dir = res_dir.join("drawable");
-        fs::create_dir_all(&drawable_dir)?;
+        fs.create_directory(&drawable_dir)?;
         let base_icon = r#"<?xml version="1.0" encoding="utf-8"?>
 <shape xmlns:android="http://schemas.android.com/apk/res/android"
     android:shape="rectangle">
     <solid android:color="@android:color/holo_blue_dark"/>
     <corners android:radius="8dp"/>
 </shape>"#;
-        fs::write(drawable_dir.join("ic_launcher_foreground.xml"), base_icon)?;
-
-        // Ensure Gradle files exist before copying
-        (self.logger)("Setting up Gradle build system...".to_string());
-        self.resources.ensure_gradle_files()?;
-        
-        let gradle_source = self.resources.get_gradle_path();
-        let gradle_wrapper_dir = project_dir.join("gradle").join("wrapper");
-        fs.create_directory(&gradle_wrapper_dir)?;
+        fs.save_file(&drawable_dir.join("ic_launcher_foreground.xml"), base_icon)?;
 
-        // Copy all Gradle files
-        let gradle_files = [
-            (gradle_source.join("gradlew"), project_dir.join("gradlew")),
-            (gradle_source.join("gradlew.bat"), project_dir.join("gradlew.bat")),
-            (gradle_source.join("wrapper").join("gradle-wrapper.jar"), 
-             gradle_wrapper_dir.join("gradle-wrapper.jar")),
-            (gradle_source.join("wrapper").join("gradle-wrapper.properties"), 
-             gradle_wrapper_dir.join("gradle-wrapper.properties")),
-        ];
-
-        for (source, dest) in gradle_files.iter() {
-            if !source.exists() {
-                return Err(format!("Gradle file not found: {}", source.display()).into());
-            }
-            fs::copy(source, dest)?;
-            
-            #[cfg(unix)]
-            if dest.file_name().map_or(false, |f| f == "gradlew") {
-                use std::os::unix::fs::PermissionsExt;
-                let mut perms = fs::metadata(dest)?.permissions();
-                perms.set_mode(0o755);
-                fs::set_permissions(dest, perms)?;
-            }
-        }
         (self.progress_callback)(0.6);
     
         // Create root build.gradle.kts
@@ -196,7 +179,7 @@ impl AppCreation {
         classpath("org.jetbrains.kotlin:kotlin-gradle-plugin:1.9.0")
     }
 }"#;
-        fs::write(project_dir.join("build.gradle.kts"), root_build_gradle)?;
+        fs.save_file(&project_dir.join("build.gradle.kts"), root_build_gradle)?;
 
         // Move build.gradle.kts content to app/build.gradle.kts
         let app_build_gradle = format!(
@@ -213,7 +196,7 @@ android {{
 "#,
             api_level = self.api_level
         );
-        fs::write(app_dir.join("build.gradle.kts"), app_build_gradle)?;
+        fs.save_file(&app_dir.join("build.gradle.kts"), &app_build_gradle)?;
 
         // Update settings.gradle.kts
         let settings_gradle = format!(r#"pluginManagement {{
@@ -234,12 +217,11 @@ dependencyResolutionManagement {{
 rootProject.name = "{}"
 include(":app")
 "#, self.app_name);
-        fs::write(project_dir.join("settings.gradle.kts"), settings_gradle)?;
+        fs.save_file(&project_dir.join("settings.gradle.kts"), &settings_gradle)?;
 
         // Create local.properties with SDK path
-        let sdk_path = sdk_manager.get_sdk_path();
         let local_properties = format!("sdk.dir={}", sdk_path.to_str().unwrap().replace("\\", "\\\\"));
-        fs::write(project_dir.join("local.properties"), local_properties)?;
+        fs.save_file(&project_dir.join("local.properties"), &local_properties)?;
 
         // Create build.gradle.kts
         let build_gradle_content = format!(
@@ -319,7 +301,7 @@ include(":app")
     "#,
             api_level = self.api_level
         );
-        fs::write(app_dir.join("build.gradle.kts"), build_gradle_content)?;
+        fs.save_file(&app_dir.join("build.gradle.kts"), &build_gradle_content)?;
         (self.progress_callback)(0.8);
     
         // Create settings.gradle.kts
@@ -341,7 +323,7 @@ include(":app")
     rootProject.name = "MyApplication"
     include(":app")
     "#;
-        fs::write(project_dir.join("settings.gradle.kts"), settings_gradle_content)?;
+        fs.save_file(&project_dir.join("settings.gradle.kts"), settings_gradle_content)?;
     
         // Create gradle.properties with AndroidX configuration
         (self.logger)("Creating Gradle configuration...".to_string());
@@ -352,7 +334,7 @@ kotlin.code.style=official
 org.gradle.parallel=true
 org.gradle.caching=true
 "#;
-        fs::write(project_dir.join("gradle.properties"), gradle_properties)?;
+        fs.save_file(&project_dir.join("gradle.properties"), gradle_properties)?;
 
         // Create MainActivity.kt
         (self.logger)("Creating Android source files...".to_string());
@@ -401,7 +383,7 @@ org.gradle.caching=true
         }
     }
     "#;
-        fs::write(kotlin_dir.join("MainActivity.kt"), main_activity_content)?;
+        fs.save_file(&kotlin_dir.join("MainActivity.kt"), main_activity_content)?;
     
         // Create Theme.kt
         let theme_content = r#"package com.example.app
@@ -453,7 +435,7 @@ org.gradle.caching=true
         )
     }
     "#;
-        fs::write(kotlin_dir.join("Theme.kt"), theme_content)?;
+        fs.save_file(&kotlin_dir.join("Theme.kt"), theme_content)?;
     
         // Create AndroidManifest.xml
         (self.logger)("Creating Android resource files...".to_string());
@@ -482,21 +464,21 @@ org.gradle.caching=true
             </activity>
         </application>
     </manifest>"#;
-        fs::write(src_main_dir.join("AndroidManifest.xml"), manifest_content)?;
+        fs.save_file(&src_main_dir.join("AndroidManifest.xml"), manifest_content)?;
     
         // Create strings.xml
         let strings_content = format!(r#"<?xml version="1.0" encoding="utf-8"?>
     <resources>
         <string name="app_name">{}</string>
     </resources>"#, self.app_name);
-        fs::write(res_dir.join("values").join("strings.xml"), strings_content)?;
+        fs.save_file(&res_dir.join("values").join("strings.xml"), &strings_content)?;
     
         // Create themes.xml
         let themes_content = r#"<?xml version="1.0" encoding="utf-8"?>
     <resources>
         <style name="Theme.App" parent="android:Theme.Material.Light.NoActionBar" />
     </resources>"#;
-        fs::write(res_dir.join("values").join("themes.xml"), themes_content)?;
+        fs.save_file(&res_dir.join("values").join("themes.xml"), themes_content)?;
     
         // Create backup_rules.xml
         let backup_rules_content = r#"<?xml version="1.0" encoding="utf-8"?>
@@ -504,7 +486,7 @@ org.gradle.caching=true
         <include domain="sharedpref" path="."/>
         <exclude domain="sharedpref" path="device.xml"/>
     </full-backup-content>"#;
-        fs::write(res_dir.join("xml").join("backup_rules.xml"), backup_rules_content)?;
+        fs.save_file(&res_dir.join("xml").join("backup_rules.xml"), backup_rules_content)?;
     
         // Create data_extraction_rules.xml
         let data_extraction_content = r#"<?xml version="1.0" encoding="utf-8"?>
@@ -518,7 +500,7 @@ org.gradle.caching=true
             <exclude domain="sharedpref" path="device.xml"/>
         </device-transfer>
     </data-extraction-rules>"#;
-        fs::write(res_dir.join("xml").join("data_extraction_rules.xml"), data_extraction_content)?;
+        fs.save_file(&res_dir.join("xml").join("data_extraction_rules.xml"), data_extraction_content)?;
     
         // Create ExampleUnitTest.kt
         (self.logger)("Creating test files...".to_string());
@@ -533,7 +515,7 @@ org.gradle.caching=true
             assertEquals(4, 2 + 2)
         }
     }"#;
-        fs::write(java_test_dir.join("ExampleUnitTest.kt"), unit_test_content)?;
+        fs.save_file(&java_test_dir.join("ExampleUnitTest.kt"), unit_test_content)?;
     
         // Create ExampleInstrumentedTest.kt
         let instrumented_test_content = r#"package com.example.app
@@ -552,7 +534,7 @@ org.gradle.caching=true
             assertEquals("com.example.app", appContext.packageName)
         }
     }"#;
-        fs::write(java_android_test_dir.join("ExampleInstrumentedTest.kt"), instrumented_test_content)?;
+        fs.save_file(&java_android_test_dir.join("ExampleInstrumentedTest.kt"), instrumented_test_content)?;
     
         // Create .gitignore
         let gitignore_content = r#"*.iml
@@ -565,16 +547,86 @@ org.gradle.caching=true
     .externalNativeBuild
     .cxx
     local.properties"#;
-        fs::write(project_dir.join(".gitignore"), gitignore_content)?;
-    
-        // Save resources state
-        (self.logger)("Finalizing project setup...".to_string());
-        self.resources.save_state()?;
-        (self.progress_callback)(1.0);
-        (self.logger)(format!("App creation completed. Project created at: {}", project_dir.display()));
+        fs.save_file(&project_dir.join(".gitignore"), gitignore_content)?;
     
         Ok(())
     }
 
+    /// Copies the Gradle wrapper scripts and jar into the project.
+    fn install_gradle_wrapper<V: Vfs>(
+        &self,
+        fs: &FileSystem<V>,
+        project_dir: &Path,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // Ensure Gradle files exist before copying
+        (self.logger)("Setting up Gradle build system...".to_string());
+        self.resources.ensure_gradle_files()?;
+        
+        let gradle_source = self.resources.get_gradle_path();
+        let gradle_wrapper_dir = project_dir.join("gradle").join("wrapper");
+        fs.create_directory(&gradle_wrapper_dir)?;
+
+        // Copy all Gradle files
+        let gradle_files = [
+            (gradle_source.join("gradlew"), project_dir.join("gradlew")),
+            (gradle_source.join("gradlew.bat"), project_dir.join("gradlew.bat")),
+            (gradle_source.join("wrapper").join("gradle-wrapper.jar"), 
+             gradle_wrapper_dir.join("gradle-wrapper.jar")),
+            (gradle_source.join("wrapper").join("gradle-wrapper.properties"), 
+             gradle_wrapper_dir.join("gradle-wrapper.properties")),
+        ];
+
+        for (source, dest) in gradle_files.iter() {
+            if !source.exists() {
+                return Err(format!("Gradle file not found: {}", source.display()).into());
+            }
+            fs.save_bytes(dest, &std::fs::read(source)?)?;
+            if dest.file_name().is_some_and(|f| f == "gradlew") {
+                fs.set_executable(dest)?;
+            }
+        }
+        Ok(())
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::vfs::MemoryVfs;
+
+    #[test]
+    fn write_project_creates_a_buildable_layout() {
+        let creation = AppCreation::new(
+            "Demo".to_string(),
+            "/projects".to_string(),
+            "34".to_string(),
+            Arc::new(|_| {}),
+            Arc::new(|_| {}),
+        );
+        let project_dir = Path::new("/projects/Demo");
+        let fs = FileSystem::with_vfs("/projects/Demo", MemoryVfs::new());
+        creation.write_project(&fs, project_dir, Path::new("/sdk")).unwrap();
+
+        for file in [
+            "settings.gradle.kts",
+            "build.gradle.kts",
+            "gradle.properties",
+            "local.properties",
+            ".gitignore",
+            "app/build.gradle.kts",
+            "app/src/main/AndroidManifest.xml",
+            "app/src/main/kotlin/com/example/app/MainActivity.kt",
+            "app/src/main/res/values/strings.xml",
+            "app/src/main/res/mipmap-hdpi/ic_launcher.xml",
+        ] {
+            assert!(fs.path_exists(&project_dir.join(file)), "{} is missing", file);
+        }
+        assert_eq!(fs.open_file(&project_dir.join("local.properties")).unwrap(), "sdk.dir=/sdk");
+        let app_build = fs.open_file(&project_dir.join("app/build.gradle.kts")).unwrap();
+        assert!(app_build.contains("34"));
+        let strings = fs.open_file(&project_dir.join("app/src/main/res/values/strings.xml")).unwrap();
+        assert!(strings.contains("Demo"));
+        // Nothing is written outside the project
+        assert!(fs.vfs().files().iter().all(|(path, _)| path.starts_with(project_dir)));
+    }
+}
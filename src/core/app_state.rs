@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::rc::Rc;
 use directories::ProjectDirs;
@@ -6,8 +7,11 @@ use std::fs;
 use crate::utils::themes::Theme;
 use crate::core::ide::IDE;
 use crate::components::code_editor::{Buffer, CursorPosition};
+use crate::components::file_modal::TreeDock;
+use crate::components::search_panel::{PanelDock, SearchHistoryEntry};
 use std::path::Path;
 use crate::core::file_system::FileSystem;
+use crate::core::smart_edit::EditorSettings;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AppState {
@@ -16,19 +20,38 @@ pub struct AppState {
     pub last_project_path: Option<PathBuf>,
     pub open_buffers: Vec<BufferState>,
     pub active_buffer_index: Option<usize>,
+    /// Folders expanded in the project tree, by project.
+    #[serde(default)]
+    pub expanded_folders: HashMap<PathBuf, Vec<PathBuf>>,
     
     // UI state
     pub window_size: (f32, f32),
     pub console_panel_visible: bool,
     pub emulator_panel_visible: bool,
     pub ai_assistant_panel_visible: bool,
+    #[serde(default = "default_project_tree_visible")]
+    pub project_tree_visible: bool,
+    #[serde(default)]
+    pub project_tree_dock: TreeDock,
+    #[serde(default)]
+    pub search_panel_visible: bool,
+    #[serde(default)]
+    pub search_panel_dock: PanelDock,
+    #[serde(default)]
+    pub search_history: Vec<SearchHistoryEntry>,
     
     // Settings
     pub current_theme: Theme,
+    #[serde(default)]
+    pub editor_settings: EditorSettings,
     pub ai_api_key: String,
     pub ai_model: String, // Add this field
 }
 
+fn default_project_tree_visible() -> bool {
+    true
+}
+
 // Use serde_path_buf for PathBuf serialization/deserialization
 mod serde_path_buf {
     use serde::{Deserialize, Deserializer, Serializer};
@@ -57,6 +80,9 @@ mod serde_path_buf {
 pub struct BufferState {
     pub file_path: String,
     pub cursor_position: CursorPosition,
+    /// Start lines of the folded regions.
+    #[serde(default)]
+    pub folded_lines: Vec<usize>,
 }
 
 impl Default for AppState {
@@ -65,11 +91,18 @@ impl Default for AppState {
             last_project_path: None,
             open_buffers: Vec::new(),
             active_buffer_index: None,
+            expanded_folders: HashMap::new(),
             window_size: (800.0, 600.0),
             console_panel_visible: false,
             emulator_panel_visible: false,
             ai_assistant_panel_visible: false,
+            project_tree_visible: true,
+            project_tree_dock: TreeDock::default(),
+            search_panel_visible: false,
+            search_panel_dock: PanelDock::default(),
+            search_history: Vec::new(),
             current_theme: Theme::default(),
+            editor_settings: EditorSettings::default(),
             ai_api_key: String::new(),
             ai_model: "Qwen/Qwen2.5-Coder-32B-Instruct".to_string(), // Default model
         }
@@ -100,10 +133,13 @@ impl AppState {
         Ok(())
     }
 
+    /// Directory for the IDE's own files: the app state and per-project caches.
+    pub fn config_dir() -> Option<PathBuf> {
+        ProjectDirs::from("com", "zzz", "ide").map(|proj_dirs| proj_dirs.config_dir().to_path_buf())
+    }
+
     fn get_config_path() -> Option<PathBuf> {
-        ProjectDirs::from("com", "zzz", "ide").map(|proj_dirs| {
-            proj_dirs.config_dir().join("app_state.json")
-        })
+        Self::config_dir().map(|dir| dir.join("app_state.json"))
     }
 
     pub fn update_from_ide(&mut self, ide: &IDE) {
@@ -111,7 +147,14 @@ impl AppState {
         self.console_panel_visible = ide.show_console_panel;
         self.emulator_panel_visible = ide.show_emulator_panel;
         self.ai_assistant_panel_visible = ide.show_ai_panel;
+        self.project_tree_visible = ide.file_modal.show;
+        self.project_tree_dock = ide.file_modal.dock;
+        self.expanded_folders = ide.file_modal.expanded_by_project();
+        self.search_panel_visible = ide.search_panel.visible;
+        self.search_panel_dock = ide.search_panel.dock;
+        self.search_history = ide.search_panel.history().to_vec();
         self.current_theme = ide.settings_modal.current_theme.clone();
+        self.editor_settings = ide.settings_modal.editor_settings;
         self.ai_api_key = ide.settings_modal.get_api_key();
         self.ai_model = ide.ai_model.clone();
 
@@ -119,6 +162,7 @@ impl AppState {
             BufferState {
                 file_path: buffer.file_path.clone().unwrap_or_default(),
                 cursor_position: buffer.cursor_position.clone(),
+                folded_lines: buffer.folding().folded_lines(),
             }
         }).collect();
 
@@ -131,14 +175,20 @@ impl AppState {
                 let fs = Rc::new(FileSystem::new(project_path.to_str().unwrap()));
                 ide.file_modal.file_system = Some(fs);
                 ide.file_modal.project_path = Some(project_path.clone());
-                ide.file_modal.expanded_folders.insert(project_path.clone());
             }
         }
+        ide.file_modal.set_expanded_by_project(self.expanded_folders.clone());
+        ide.file_modal.show = self.project_tree_visible;
+        ide.file_modal.dock = self.project_tree_dock;
 
         ide.show_console_panel = self.console_panel_visible;
         ide.show_emulator_panel = self.emulator_panel_visible;
         ide.show_ai_panel = self.ai_assistant_panel_visible;
+        ide.search_panel.visible = self.search_panel_visible;
+        ide.search_panel.dock = self.search_panel_dock;
+        ide.search_panel.set_history(self.search_history.clone());
         ide.settings_modal.current_theme = self.current_theme.clone();
+        ide.settings_modal.editor_settings = self.editor_settings;
         ide.settings_modal.set_api_key(self.ai_api_key.clone());
         ide.ai_assistant.update_api_key(self.ai_api_key.clone());
         ide.ai_model = self.ai_model.clone();
@@ -148,10 +198,14 @@ impl AppState {
             if path.exists() {
                 if let Some(fs) = &ide.file_modal.file_system {
                     if let Ok(content) = fs.open_file(path) {
-                        let mut buffer = Buffer::new();
-                        buffer.file_path = Some(buffer_state.file_path.clone());
-                        buffer.content = content;
-                        buffer.cursor_position = buffer_state.cursor_position.clone();
+                        let mut buffer = Buffer::from_file(
+                            content,
+                            buffer_state.file_path.clone(),
+                            ide.code_editor.syntax_for_path(path),
+                        );
+                        let cursor = &buffer_state.cursor_position;
+                        buffer.set_cursor_position(cursor.line + 1, cursor.column);
+                        buffer.folding_mut().set_folded_lines(&buffer_state.folded_lines);
                         ide.code_editor.buffers.push(buffer);
                     }
                 }
@@ -0,0 +1,181 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use zip::ZipArchive;
+use crate::core::binary_xml;
+use crate::core::file_system::DirectoryEntry;
+use crate::core::vfs::{Vfs, VfsMetadata};
+
+enum ArchiveEntry {
+    File { index: usize, size: u64 },
+    Directory,
+}
+
+/// The contents of an APK, AAR or JAR as a read-only directory tree, rooted at
+/// the archive's own path: `app-debug.apk/res/layout/main.xml` is an entry of
+/// `app-debug.apk`. Entries are read straight from the archive without
+/// extracting it, and compiled XML is decoded when read as text.
+pub struct ArchiveVfs {
+    archive_path: PathBuf,
+    /// When the archive was last modified, to notice rebuilds.
+    modified: SystemTime,
+    entries: BTreeMap<PathBuf, ArchiveEntry>,
+    zip: Mutex<ZipArchive<BufReader<File>>>,
+}
+
+impl ArchiveVfs {
+    const EXTENSIONS: [&'static str; 3] = ["apk", "aar", "jar"];
+    const MAX_PREALLOCATION: u64 = 16 << 20;
+
+    /// Whether the file at `path` is an archive that can be browsed.
+    pub fn is_archive(path: &Path) -> bool {
+        path.extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| Self::EXTENSIONS.iter().any(|known| ext.eq_ignore_ascii_case(known)))
+    }
+
+    /// Reads the table of contents of the archive at `path`.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let modified = fs::metadata(path)?.modified().unwrap_or(UNIX_EPOCH);
+        let mut zip = ZipArchive::new(BufReader::new(File::open(path)?))
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))?;
+
+        let mut entries = BTreeMap::new();
+        entries.insert(path.to_path_buf(), ArchiveEntry::Directory);
+        for index in 0..zip.len() {
+            let Ok(file) = zip.by_index_raw(index) else {
+                continue;
+            };
+            // Skips names that would point outside the archive, like `../x`
+            let Some(relative) = file.enclosed_name() else {
+                continue;
+            };
+            let entry_path = path.join(&relative);
+            for directory in entry_path.ancestors().skip(1).take_while(|ancestor| *ancestor != path) {
+                entries.entry(directory.to_path_buf()).or_insert(ArchiveEntry::Directory);
+            }
+            let entry = if file.is_dir() {
+                ArchiveEntry::Directory
+            } else {
+                ArchiveEntry::File { index, size: file.size() }
+            };
+            entries.insert(entry_path, entry);
+        }
+
+        Ok(Self {
+            archive_path: path.to_path_buf(),
+            modified,
+            entries,
+            zip: Mutex::new(zip),
+        })
+    }
+
+    /// Whether the archive changed on disk since it was opened.
+    pub fn is_stale(&self) -> bool {
+        !fs::metadata(&self.archive_path)
+            .and_then(|metadata| metadata.modified())
+            .is_ok_and(|modified| modified == self.modified)
+    }
+
+    fn read_only(path: &Path) -> io::Error {
+        io::Error::new(
+            ErrorKind::PermissionDenied,
+            format!("{} is inside a read-only archive", path.display()),
+        )
+    }
+
+    fn not_found(path: &Path) -> io::Error {
+        io::Error::new(ErrorKind::NotFound, format!("{} not found", path.display()))
+    }
+}
+
+impl Vfs for ArchiveVfs {
+    fn metadata(&self, path: &Path) -> io::Result<VfsMetadata> {
+        match self.entries.get(path) {
+            Some(ArchiveEntry::File { size, .. }) => Ok(VfsMetadata { is_dir: false, len: *size, modified: self.modified }),
+            Some(ArchiveEntry::Directory) => Ok(VfsMetadata { is_dir: true, len: 0, modified: self.modified }),
+            None => Err(Self::not_found(path)),
+        }
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let index = match self.entries.get(path) {
+            Some(ArchiveEntry::File { index, .. }) => *index,
+            Some(ArchiveEntry::Directory) => return Err(io::Error::other(format!("{} is a directory", path.display()))),
+            None => return Err(Self::not_found(path)),
+        };
+        let mut zip = self.zip.lock().unwrap();
+        let mut file = zip.by_index(index).map_err(|e| io::Error::new(ErrorKind::InvalidData, e.to_string()))?;
+        // The size comes from the archive, which may lie about it
+        let mut contents = Vec::with_capacity(file.size().min(Self::MAX_PREALLOCATION) as usize);
+        file.read_to_end(&mut contents)?;
+        Ok(contents)
+    }
+
+    fn write(&self, path: &Path, _contents: &[u8]) -> io::Result<()> {
+        Err(Self::read_only(path))
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<DirectoryEntry>> {
+        match self.entries.get(path) {
+            Some(ArchiveEntry::Directory) => {}
+            Some(ArchiveEntry::File { .. }) => {
+                return Err(io::Error::other(format!("{} is not a directory", path.display())));
+            }
+            None => return Err(Self::not_found(path)),
+        }
+        Ok(self.entries
+            .range(path.to_path_buf()..)
+            .skip(1)
+            .take_while(|(child, _)| child.starts_with(path))
+            .filter(|(child, _)| child.parent() == Some(path))
+            .filter_map(|(child, entry)| {
+                let name = child.file_name()?.to_str()?.to_string();
+                let (is_dir, size) = match entry {
+                    ArchiveEntry::File { size, .. } => (false, *size),
+                    ArchiveEntry::Directory => (true, 0),
+                };
+                Some(DirectoryEntry { name, is_dir, size, modified: self.modified })
+            })
+            .collect())
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        Err(Self::read_only(path))
+    }
+
+    fn rename(&self, from: &Path, _to: &Path) -> io::Result<()> {
+        Err(Self::read_only(from))
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        Err(Self::read_only(path))
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        Err(Self::read_only(path))
+    }
+
+    fn set_executable(&self, path: &Path) -> io::Result<()> {
+        Err(Self::read_only(path))
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
+    /// Text entries as they are, compiled XML decoded, and an error for
+    /// anything else, like `.dex` and `.class` files.
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        let contents = self.read(path)?;
+        if binary_xml::is_binary_xml(&contents) {
+            return binary_xml::decode(&contents)
+                .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, format!("{} is not valid compiled XML", path.display())));
+        }
+        String::from_utf8(contents)
+            .map_err(|_| io::Error::new(ErrorKind::InvalidData, format!("{} is a binary file", path.display())))
+    }
+}
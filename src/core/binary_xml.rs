@@ -0,0 +1,338 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+const RES_XML_TYPE: u16 = 0x0003;
+const RES_STRING_POOL_TYPE: u16 = 0x0001;
+const RES_XML_RESOURCE_MAP_TYPE: u16 = 0x0180;
+const RES_XML_START_NAMESPACE_TYPE: u16 = 0x0100;
+const RES_XML_START_ELEMENT_TYPE: u16 = 0x0102;
+const RES_XML_END_ELEMENT_TYPE: u16 = 0x0103;
+const RES_XML_CDATA_TYPE: u16 = 0x0104;
+
+const UTF8_FLAG: u32 = 1 << 8;
+const NO_STRING: u32 = 0xFFFF_FFFF;
+
+/// Whether `data` starts like a compiled XML document.
+pub fn is_binary_xml(data: &[u8]) -> bool {
+    u16_at(data, 0) == Some(RES_XML_TYPE) && u16_at(data, 2) == Some(8)
+}
+
+/// Turns the compiled XML that `aapt` writes into APKs, e.g.
+/// `AndroidManifest.xml` and layouts below `res/`, back into indented XML text.
+/// Returns `None` when the document is malformed.
+///
+/// Resource references can't be resolved without `resources.arsc`, so they are
+/// shown as ids like `@0x7f0a0001`.
+pub fn decode(data: &[u8]) -> Option<String> {
+    if !is_binary_xml(data) {
+        return None;
+    }
+    let end = (u32_at(data, 4)? as usize).min(data.len());
+    let mut strings = Vec::new();
+    let mut resource_ids = Vec::new();
+    // Namespaces declared since the last start tag, as (prefix, uri)
+    let mut pending_namespaces = Vec::new();
+    let mut prefixes = HashMap::new();
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    let mut depth = 0usize;
+    // The last start tag is left open until we know whether it has content
+    let mut tag_open = false;
+
+    let mut pos = 8;
+    while pos + 8 <= end {
+        let chunk_type = u16_at(data, pos)?;
+        let header_size = u16_at(data, pos + 2)? as usize;
+        let chunk_size = u32_at(data, pos + 4)? as usize;
+        if chunk_size < 8 || pos + chunk_size > end {
+            return None;
+        }
+        let chunk = &data[pos..pos + chunk_size];
+        // Node chunks have a 16 byte header (line number and comment), then their data
+        let body = chunk.get(header_size..).unwrap_or_default();
+
+        match chunk_type {
+            RES_STRING_POOL_TYPE => strings = read_string_pool(chunk)?,
+            RES_XML_RESOURCE_MAP_TYPE => {
+                resource_ids = body.chunks_exact(4).map(|id| u32::from_le_bytes([id[0], id[1], id[2], id[3]])).collect();
+            }
+            RES_XML_START_NAMESPACE_TYPE => {
+                let prefix = string(&strings, u32_at(body, 0)?);
+                let uri = string(&strings, u32_at(body, 4)?);
+                prefixes.insert(uri.clone(), prefix.clone());
+                pending_namespaces.push((prefix, uri));
+            }
+            RES_XML_START_ELEMENT_TYPE => {
+                if tag_open {
+                    out.push_str(">\n");
+                }
+                let name = string(&strings, u32_at(body, 4)?);
+                let attribute_start = u16_at(body, 8)? as usize;
+                let attribute_size = u16_at(body, 10)? as usize;
+                let attribute_count = u16_at(body, 12)? as usize;
+
+                indent(&mut out, depth);
+                out.push('<');
+                out.push_str(&name);
+                for (prefix, uri) in pending_namespaces.drain(..) {
+                    let _ = write!(out, "\n{}xmlns:{}=\"{}\"", "    ".repeat(depth + 1), prefix, escape(&uri));
+                }
+                for index in 0..attribute_count {
+                    let attribute = body.get(attribute_start + index * attribute_size..)?;
+                    let namespace = u32_at(attribute, 0)?;
+                    let name_index = u32_at(attribute, 4)?;
+                    let mut attribute_name = string(&strings, name_index);
+                    if attribute_name.is_empty() {
+                        // Shrunk APKs drop the names of framework attributes
+                        if let Some(id) = resource_ids.get(name_index as usize) {
+                            attribute_name = format!("0x{:08x}", id);
+                        }
+                    }
+                    let prefix = match namespace {
+                        NO_STRING => None,
+                        namespace => prefixes.get(&string(&strings, namespace)),
+                    };
+                    let value = attribute_value(&strings, attribute)?;
+                    let _ = write!(out, "\n{}", "    ".repeat(depth + 1));
+                    if let Some(prefix) = prefix {
+                        let _ = write!(out, "{}:", prefix);
+                    }
+                    let _ = write!(out, "{}=\"{}\"", attribute_name, escape(&value));
+                }
+                tag_open = true;
+                depth += 1;
+            }
+            RES_XML_END_ELEMENT_TYPE => {
+                depth = depth.saturating_sub(1);
+                if tag_open {
+                    out.push_str(" />\n");
+                    tag_open = false;
+                } else {
+                    indent(&mut out, depth);
+                    let _ = writeln!(out, "</{}>", string(&strings, u32_at(body, 4)?));
+                }
+            }
+            RES_XML_CDATA_TYPE => {
+                if tag_open {
+                    out.push_str(">\n");
+                    tag_open = false;
+                }
+                indent(&mut out, depth);
+                out.push_str(&escape(string(&strings, u32_at(body, 0)?).trim()));
+                out.push('\n');
+            }
+            // End of namespace and anything unknown
+            _ => {}
+        }
+        pos += chunk_size;
+    }
+    Some(out)
+}
+
+fn indent(out: &mut String, depth: usize) {
+    out.push_str(&"    ".repeat(depth));
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn string(strings: &[String], index: u32) -> String {
+    strings.get(index as usize).cloned().unwrap_or_default()
+}
+
+/// An attribute's value, formatted like `aapt dump xmltree` does.
+fn attribute_value(strings: &[String], attribute: &[u8]) -> Option<String> {
+    let raw_value = u32_at(attribute, 8)?;
+    let data_type = *attribute.get(15)?;
+    let data = u32_at(attribute, 16)?;
+    if raw_value != NO_STRING {
+        return Some(string(strings, raw_value));
+    }
+    Some(match data_type {
+        0x00 => String::new(),
+        0x01 => format!("@0x{:08x}", data),
+        0x02 => format!("?0x{:08x}", data),
+        0x03 => string(strings, data),
+        0x04 => f32::from_bits(data).to_string(),
+        0x05 => {
+            let unit = ["px", "dp", "sp", "pt", "in", "mm"].get((data & 0xf) as usize).copied().unwrap_or("");
+            format!("{}{}", complex_to_float(data), unit)
+        }
+        0x06 => {
+            let unit = if data & 0xf == 0 { "%" } else { "%p" };
+            format!("{}{}", complex_to_float(data) * 100.0, unit)
+        }
+        0x10 => (data as i32).to_string(),
+        0x11 => format!("0x{:x}", data),
+        0x12 => (data != 0).to_string(),
+        0x1c..=0x1f => format!("#{:08x}", data),
+        _ => format!("0x{:08x}", data),
+    })
+}
+
+/// Value of a dimension or fraction: a 24 bit mantissa with one of four radixes.
+fn complex_to_float(data: u32) -> f32 {
+    let radix_shift = [0, 7, 15, 23][((data >> 4) & 0x3) as usize];
+    let mantissa = (data & 0xFFFF_FF00) as i32 as f32 / 256.0;
+    mantissa / (1u32 << radix_shift) as f32
+}
+
+fn read_string_pool(chunk: &[u8]) -> Option<Vec<String>> {
+    let header_size = u16_at(chunk, 2)? as usize;
+    let string_count = u32_at(chunk, 8)? as usize;
+    let flags = u32_at(chunk, 16)?;
+    let strings_start = u32_at(chunk, 20)? as usize;
+    (0..string_count)
+        .map(|index| {
+            let offset = u32_at(chunk, header_size + index * 4)? as usize;
+            let start = strings_start + offset;
+            if flags & UTF8_FLAG != 0 {
+                // Length in UTF-16 units, which we don't need, then in bytes
+                let (_, skip) = utf8_length(chunk, start)?;
+                let (length, skip_length) = utf8_length(chunk, start + skip)?;
+                let bytes_start = start + skip + skip_length;
+                let bytes = chunk.get(bytes_start..bytes_start + length)?;
+                Some(String::from_utf8_lossy(bytes).into_owned())
+            } else {
+                let (length, skip) = utf16_length(chunk, start)?;
+                let units = (0..length)
+                    .map(|unit| u16_at(chunk, start + skip + unit * 2))
+                    .collect::<Option<Vec<u16>>>()?;
+                Some(String::from_utf16_lossy(&units))
+            }
+        })
+        .collect()
+}
+
+/// A string length of one byte, or two with the high bit of the first set.
+fn utf8_length(data: &[u8], pos: usize) -> Option<(usize, usize)> {
+    let first = *data.get(pos)? as usize;
+    if first & 0x80 != 0 {
+        Some((((first & 0x7F) << 8) | *data.get(pos + 1)? as usize, 2))
+    } else {
+        Some((first, 1))
+    }
+}
+
+/// A string length of one unit, or two with the high bit of the first set.
+fn utf16_length(data: &[u8], pos: usize) -> Option<(usize, usize)> {
+    let first = u16_at(data, pos)? as usize;
+    if first & 0x8000 != 0 {
+        Some((((first & 0x7FFF) << 16) | u16_at(data, pos + 2)? as usize, 4))
+    } else {
+        Some((first, 2))
+    }
+}
+
+fn u16_at(data: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(pos..pos + 2)?.try_into().ok()?))
+}
+
+fn u32_at(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STRINGS: [&str; 7] = [
+        "android",
+        "http://schemas.android.com/apk/res/android",
+        "manifest",
+        "package",
+        "com.example.app",
+        "application",
+        "versionCode",
+    ];
+
+    fn chunk(chunk_type: u16, header_size: u16, body: &[u8]) -> Vec<u8> {
+        let mut chunk = Vec::new();
+        chunk.extend(chunk_type.to_le_bytes());
+        chunk.extend(header_size.to_le_bytes());
+        chunk.extend((8 + body.len() as u32).to_le_bytes());
+        chunk.extend(body);
+        chunk
+    }
+
+    fn words(words: &[u32]) -> Vec<u8> {
+        words.iter().flat_map(|word| word.to_le_bytes()).collect()
+    }
+
+    /// A node chunk with line number 1 and no comment.
+    fn node(chunk_type: u16, body: &[u8]) -> Vec<u8> {
+        chunk(chunk_type, 16, &[words(&[1, NO_STRING]), body.to_vec()].concat())
+    }
+
+    fn string_pool(strings: &[&str]) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut offsets = Vec::new();
+        for string in strings {
+            offsets.push(data.len() as u32);
+            data.extend([string.chars().count() as u8, string.len() as u8]);
+            data.extend(string.as_bytes());
+            data.push(0);
+        }
+        while data.len() % 4 != 0 {
+            data.push(0);
+        }
+        let strings_start = 28 + 4 * strings.len() as u32;
+        let header = words(&[strings.len() as u32, 0, UTF8_FLAG, strings_start, 0]);
+        chunk(RES_STRING_POOL_TYPE, 28, &[header, words(&offsets), data].concat())
+    }
+
+    fn attribute(namespace: u32, name: u32, raw_value: u32, data_type: u8, data: u32) -> Vec<u8> {
+        let mut attribute = words(&[namespace, name, raw_value]);
+        attribute.extend(8u16.to_le_bytes());
+        attribute.extend([0, data_type]);
+        attribute.extend(data.to_le_bytes());
+        attribute
+    }
+
+    fn start_element(name: u32, attributes: &[Vec<u8>]) -> Vec<u8> {
+        let mut body = words(&[NO_STRING, name]);
+        for value in [20, 20, attributes.len() as u16, 0, 0, 0] {
+            body.extend(value.to_le_bytes());
+        }
+        body.extend(attributes.concat());
+        node(RES_XML_START_ELEMENT_TYPE, &body)
+    }
+
+    fn manifest() -> Vec<u8> {
+        let chunks = [
+            string_pool(&STRINGS),
+            node(RES_XML_START_NAMESPACE_TYPE, &words(&[0, 1])),
+            start_element(2, &[attribute(NO_STRING, 3, 4, 0x03, 4), attribute(1, 6, NO_STRING, 0x10, 7)]),
+            start_element(5, &[]),
+            node(RES_XML_END_ELEMENT_TYPE, &words(&[NO_STRING, 5])),
+            node(RES_XML_END_ELEMENT_TYPE, &words(&[NO_STRING, 2])),
+        ]
+        .concat();
+        chunk(RES_XML_TYPE, 8, &chunks)
+    }
+
+    #[test]
+    fn decodes_a_compiled_manifest() {
+        let data = manifest();
+        assert!(is_binary_xml(&data));
+        assert_eq!(
+            decode(&data).unwrap(),
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+             <manifest\n    \
+             xmlns:android=\"http://schemas.android.com/apk/res/android\"\n    \
+             package=\"com.example.app\"\n    \
+             android:versionCode=\"7\">\n    \
+             <application />\n\
+             </manifest>\n"
+        );
+    }
+
+    #[test]
+    fn truncated_documents_are_not_decoded() {
+        let data = manifest();
+        assert_eq!(decode(&data[..data.len() - 3]), None);
+        assert_eq!(decode(&data[..40]), None);
+        assert_eq!(decode(&data[..4]), None);
+        assert!(!is_binary_xml(b"<manifest />"));
+    }
+}
@@ -0,0 +1,265 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// The origin of an edit. Only `Typing` edits are merged into the previous group;
+/// every other kind always starts (and seals) a group of its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditKind {
+    Typing,
+    AiApply,
+    Replace,
+    Reload,
+}
+
+/// A single text change, expressed in character indices.
+#[derive(Debug, Clone)]
+pub struct EditOp {
+    pub char_start: usize,
+    pub removed: String,
+    pub inserted: String,
+}
+
+impl EditOp {
+    fn inserted_end(&self) -> usize {
+        self.char_start + self.inserted.chars().count()
+    }
+
+    fn memory_size(&self) -> usize {
+        self.removed.len() + self.inserted.len() + std::mem::size_of::<Self>()
+    }
+}
+
+/// A set of operations that are undone and redone together.
+#[derive(Debug, Clone)]
+pub struct EditGroup {
+    pub id: u64,
+    pub kind: EditKind,
+    pub ops: Vec<EditOp>,
+    /// Number of cursors the edits were made at. Typing is only merged into
+    /// a group made with the same number of cursors.
+    cursors: usize,
+    sealed: bool,
+    last_edit_at: Instant,
+}
+
+impl EditGroup {
+    fn memory_size(&self) -> usize {
+        self.ops.iter().map(EditOp::memory_size).sum()
+    }
+
+    /// Character index where the cursor should land after the group is undone.
+    pub fn undo_cursor(&self) -> usize {
+        self.ops
+            .first()
+            .map(|op| op.char_start + op.removed.chars().count())
+            .unwrap_or(0)
+    }
+
+    /// Character index where the cursor should land after the group is redone.
+    pub fn redo_cursor(&self) -> usize {
+        self.ops.last().map(EditOp::inserted_end).unwrap_or(0)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EditHistory {
+    undo_stack: VecDeque<EditGroup>,
+    redo_stack: Vec<EditGroup>,
+    memory_used: usize,
+    next_group_id: u64,
+}
+
+impl Default for EditHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EditHistory {
+    const MAX_GROUPS: usize = 1000;
+    const MAX_MEMORY_BYTES: usize = 16 * 1024 * 1024; // 16 MB per buffer
+    const GROUP_TIMEOUT: Duration = Duration::from_millis(1000);
+
+    pub fn new() -> Self {
+        Self {
+            undo_stack: VecDeque::new(),
+            redo_stack: Vec::new(),
+            memory_used: 0,
+            next_group_id: 1,
+        }
+    }
+
+    /// Records an operation that has already been applied to the text.
+    /// Any pending redo history is discarded.
+    pub fn record(&mut self, op: EditOp, kind: EditKind) {
+        if op.removed.is_empty() && op.inserted.is_empty() {
+            return;
+        }
+
+        self.clear_redo();
+        self.memory_used += op.memory_size();
+
+        let now = Instant::now();
+        let breaks_group = op.inserted.contains('\n');
+        if let Some(group) = self.undo_stack.back_mut() {
+            if kind == EditKind::Typing && Self::can_merge(group, &op, now) {
+                group.ops.push(op);
+                group.last_edit_at = now;
+                group.sealed = breaks_group;
+                return;
+            }
+            group.sealed = true;
+        }
+
+        let id = self.next_group_id;
+        self.next_group_id += 1;
+        self.undo_stack.push_back(EditGroup {
+            id,
+            kind,
+            ops: vec![op],
+            cursors: 1,
+            sealed: kind != EditKind::Typing || breaks_group,
+            last_edit_at: now,
+        });
+        self.enforce_limits();
+    }
+
+    /// Records one edit made at several cursors at once, in the order the
+    /// operations were applied. They are undone as a unit, and consecutive
+    /// typing at the same cursors keeps extending the same group.
+    pub fn record_batch(&mut self, ops: Vec<EditOp>, kind: EditKind) {
+        let mut ops: Vec<EditOp> = ops
+            .into_iter()
+            .filter(|op| !op.removed.is_empty() || !op.inserted.is_empty())
+            .collect();
+        if ops.len() <= 1 {
+            if let Some(op) = ops.pop() {
+                self.record(op, kind);
+            }
+            return;
+        }
+
+        self.clear_redo();
+        self.memory_used += ops.iter().map(EditOp::memory_size).sum::<usize>();
+
+        let now = Instant::now();
+        let cursors = ops.len();
+        let breaks_group = ops.iter().any(|op| op.inserted.contains('\n'));
+        let is_insert = ops.iter().all(|op| op.removed.is_empty());
+        if let Some(group) = self.undo_stack.back_mut() {
+            // As with a single cursor, deleting right after typing starts a new step
+            let last_is_insert = group.ops.last().is_some_and(|op| op.removed.is_empty());
+            let can_merge = kind == EditKind::Typing
+                && group.kind == EditKind::Typing
+                && !group.sealed
+                && group.cursors == cursors
+                && (is_insert || !last_is_insert)
+                && now.duration_since(group.last_edit_at) <= Self::GROUP_TIMEOUT;
+            if can_merge {
+                group.ops.extend(ops);
+                group.last_edit_at = now;
+                group.sealed = breaks_group;
+                return;
+            }
+            group.sealed = true;
+        }
+
+        let id = self.next_group_id;
+        self.next_group_id += 1;
+        self.undo_stack.push_back(EditGroup {
+            id,
+            kind,
+            ops,
+            cursors,
+            sealed: kind != EditKind::Typing || breaks_group,
+            last_edit_at: now,
+        });
+        self.enforce_limits();
+    }
+
+    /// Closes the current group so the next edit starts a new undo step.
+    pub fn seal(&mut self) {
+        if let Some(group) = self.undo_stack.back_mut() {
+            group.sealed = true;
+        }
+    }
+
+    /// Pops the most recent group. The caller is responsible for reverting its
+    /// operations (in reverse order) on the text.
+    pub fn undo(&mut self) -> Option<EditGroup> {
+        let mut group = self.undo_stack.pop_back()?;
+        group.sealed = true;
+        self.redo_stack.push(group.clone());
+        Some(group)
+    }
+
+    /// Pops the most recently undone group. The caller re-applies its operations in order.
+    pub fn redo(&mut self) -> Option<EditGroup> {
+        let group = self.redo_stack.pop()?;
+        self.undo_stack.push_back(group.clone());
+        Some(group)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
+    /// Identifier of the group at the top of the undo stack, used to detect
+    /// whether the text matches its last saved state.
+    pub fn current_group_id(&self) -> Option<u64> {
+        self.undo_stack.back().map(|group| group.id)
+    }
+
+    pub fn clear(&mut self) {
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.memory_used = 0;
+    }
+
+    fn can_merge(group: &EditGroup, op: &EditOp, now: Instant) -> bool {
+        if group.sealed || group.kind != EditKind::Typing || group.cursors != 1 {
+            return false;
+        }
+        if now.duration_since(group.last_edit_at) > Self::GROUP_TIMEOUT {
+            return false;
+        }
+        let Some(last) = group.ops.last() else {
+            return false;
+        };
+
+        let is_insert = op.removed.is_empty();
+        let last_is_insert = last.removed.is_empty();
+        match (last_is_insert, is_insert) {
+            // Continues typing right after the previous insertion
+            (true, true) => op.char_start == last.inserted_end(),
+            // Backspace (deleting towards the start) or Delete (same position)
+            (false, false) => {
+                let removed_len = op.removed.chars().count();
+                op.char_start + removed_len == last.char_start || op.char_start == last.char_start
+            }
+            // Typing over a selection deletes it first, then inserts at the same spot
+            (false, true) => op.char_start == last.char_start && last.inserted.is_empty(),
+            (true, false) => false,
+        }
+    }
+
+    fn clear_redo(&mut self) {
+        for group in self.redo_stack.drain(..) {
+            self.memory_used = self.memory_used.saturating_sub(group.memory_size());
+        }
+    }
+
+    fn enforce_limits(&mut self) {
+        while self.undo_stack.len() > 1
+            && (self.undo_stack.len() > Self::MAX_GROUPS || self.memory_used > Self::MAX_MEMORY_BYTES)
+        {
+            if let Some(group) = self.undo_stack.pop_front() {
+                self.memory_used = self.memory_used.saturating_sub(group.memory_size());
+            }
+        }
+    }
+}
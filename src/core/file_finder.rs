@@ -0,0 +1,145 @@
+use nucleo_matcher::pattern::{CaseMatching, Normalization, Pattern};
+use nucleo_matcher::{Config, Matcher, Utf32Str};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// A file matching the finder's query.
+pub struct FileMatch {
+    pub path: PathBuf,
+    /// Path relative to the project root, as shown in the finder.
+    pub relative_path: String,
+    /// Character indices of the matched characters in `relative_path`, sorted.
+    pub matched_chars: Vec<usize>,
+}
+
+/// Fuzzy matching of project files for the Ctrl+P finder.
+///
+/// Queries use fzf syntax: characters match in order with gaps, scoring higher
+/// at path segment and camel-case boundaries, so `mavm` finds
+/// `MainActivityViewModel.kt`. Space separated words must all match, and `'`,
+/// `^`, `$` and `!` work as in fzf. Recently opened files rank above others with
+/// a similar score.
+pub struct FileFinder {
+    matcher: Matcher,
+    /// Project files as absolute and relative paths, and the byte offset of the
+    /// file name in the relative path.
+    files: Vec<(PathBuf, String, usize)>,
+    /// The list `files` was built from.
+    listed: Arc<Vec<PathBuf>>,
+    /// Recently opened files, most recent first.
+    recent: Vec<PathBuf>,
+}
+
+impl FileFinder {
+    pub const MAX_RESULTS: usize = 200;
+    const MAX_RECENT: usize = 50;
+    /// Score added for the most recent file, decreasing with each older one.
+    /// A consecutive matched character is worth about 16.
+    const RECENT_BOOST: u32 = 40;
+    /// Score added when the query also matches the file name on its own, so
+    /// `main` prefers `MainActivity.kt` over everything below `src/main`.
+    const FILE_NAME_BOOST: u32 = 30;
+
+    pub fn new() -> Self {
+        Self {
+            matcher: Matcher::new(Config::DEFAULT.match_paths()),
+            files: Vec::new(),
+            listed: Arc::default(),
+            recent: Vec::new(),
+        }
+    }
+
+    /// Takes the files of the project at `root` from a [`ProjectFiles`] list.
+    /// Returns whether anything changed, in which case results are stale.
+    ///
+    /// [`ProjectFiles`]: crate::core::project_files::ProjectFiles
+    pub fn refresh(&mut self, root: &Path, files: &Arc<Vec<PathBuf>>) -> bool {
+        if Arc::ptr_eq(&self.listed, files) {
+            return false;
+        }
+        self.listed = files.clone();
+        self.files = files
+            .iter()
+            .map(|path| {
+                let relative = path.strip_prefix(root).unwrap_or(path).to_string_lossy().into_owned();
+                let name_start = relative.rfind(std::path::MAIN_SEPARATOR).map_or(0, |index| index + 1);
+                (path.clone(), relative, name_start)
+            })
+            .collect();
+        true
+    }
+
+    /// Moves `path` to the front of the recently opened files.
+    pub fn note_opened(&mut self, path: &Path) {
+        if self.recent.first().is_some_and(|recent| recent == path) {
+            return;
+        }
+        self.recent.retain(|recent| recent != path);
+        self.recent.insert(0, path.to_path_buf());
+        self.recent.truncate(Self::MAX_RECENT);
+    }
+
+    /// The best matches for `query`, best first. An empty query lists the
+    /// recently opened files.
+    pub fn search(&mut self, query: &str) -> Vec<FileMatch> {
+        if query.trim().is_empty() {
+            return self.recent
+                .iter()
+                .filter_map(|path| self.files.iter().find(|(file, ..)| file == path))
+                .map(|(path, relative, _)| FileMatch {
+                    path: path.clone(),
+                    relative_path: relative.clone(),
+                    matched_chars: Vec::new(),
+                })
+                .collect();
+        }
+
+        let pattern = Pattern::parse(query, CaseMatching::Smart, Normalization::Smart);
+        let mut buffer = Vec::new();
+        let matcher = &mut self.matcher;
+        let recent = &self.recent;
+        let mut scored: Vec<(u32, usize)> = self.files
+            .iter()
+            .enumerate()
+            .filter_map(|(index, (path, relative, name_start))| {
+                let score = pattern.score(Utf32Str::new(relative, &mut buffer), matcher)?;
+                let name_boost = pattern
+                    .score(Utf32Str::new(&relative[*name_start..], &mut buffer), matcher)
+                    .map_or(0, |_| Self::FILE_NAME_BOOST);
+                let recent_boost = recent.iter().position(|recent| recent == path).map_or(0, |rank| {
+                    Self::RECENT_BOOST * (Self::MAX_RECENT - rank) as u32 / Self::MAX_RECENT as u32
+                });
+                Some((score + name_boost + recent_boost, index))
+            })
+            .collect();
+        // Ties go to the shorter path, which is usually the closer match
+        scored.sort_by(|(a_score, a), (b_score, b)| {
+            b_score.cmp(a_score).then_with(|| self.files[*a].1.len().cmp(&self.files[*b].1.len()))
+        });
+        scored.truncate(Self::MAX_RESULTS);
+
+        // Only the shown results need their match positions
+        let mut indices = Vec::new();
+        scored
+            .into_iter()
+            .map(|(_, index)| {
+                let (path, relative, _) = &self.files[index];
+                indices.clear();
+                pattern.indices(Utf32Str::new(relative, &mut buffer), &mut self.matcher, &mut indices);
+                indices.sort_unstable();
+                indices.dedup();
+                FileMatch {
+                    path: path.clone(),
+                    relative_path: relative.clone(),
+                    matched_chars: indices.iter().map(|index| *index as usize).collect(),
+                }
+            })
+            .collect()
+    }
+}
+
+impl Default for FileFinder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
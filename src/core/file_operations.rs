@@ -0,0 +1,468 @@
+use std::fs::{self, File, TryLockError};
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::core::file_system::FileSystem;
+use crate::core::vfs::Vfs;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileOperationKind {
+    Create,
+    Rename,
+    Delete,
+}
+
+/// A change to the project's files that can be undone. Each one is a move:
+/// redoing moves `from` to `to` and undoing moves it back. Deleting moves into
+/// the trash, and creating counts as moving out of a slot in the trash, so an
+/// undone creation keeps whatever was written to the file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileOperation {
+    pub kind: FileOperationKind,
+    pub from: PathBuf,
+    pub to: PathBuf,
+    /// Other files changed along with the move, undone and redone with it.
+    pub edits: Vec<ContentEdit>,
+}
+
+/// A file whose content changed as part of an operation, e.g. the manifest
+/// an activity created from a template was registered in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentEdit {
+    pub path: PathBuf,
+    pub before: String,
+    pub after: String,
+}
+
+impl FileOperation {
+    /// The affected path as the user sees it, outside the trash.
+    pub fn path(&self) -> &Path {
+        match self.kind {
+            FileOperationKind::Create => &self.to,
+            FileOperationKind::Rename | FileOperationKind::Delete => &self.from,
+        }
+    }
+
+    pub fn description(&self) -> String {
+        let name = |path: &Path| path.file_name().map_or_else(
+            || path.display().to_string(),
+            |name| name.to_string_lossy().into_owned(),
+        );
+        match self.kind {
+            FileOperationKind::Create => format!("create {}", name(&self.to)),
+            FileOperationKind::Rename if self.from.parent() == self.to.parent() => {
+                format!("rename {} to {}", name(&self.from), name(&self.to))
+            }
+            FileOperationKind::Rename => format!("move {} to {}", name(&self.from), self.to.display()),
+            FileOperationKind::Delete => format!("delete {}", name(&self.from)),
+        }
+    }
+}
+
+/// Creates, renames, moves and deletes files for the file browser, keeping
+/// undo and redo stacks of what was done. Deleted files go to an IDE-managed
+/// trash directory instead of being removed, so a delete can be undone too.
+pub struct FileOperations {
+    trash_dir: PathBuf,
+    undo_stack: Vec<FileOperation>,
+    redo_stack: Vec<FileOperation>,
+    next_slot: u64,
+}
+
+impl FileOperations {
+    const MAX_UNDO: usize = 100;
+
+    pub fn new(trash_dir: PathBuf) -> Self {
+        Self {
+            trash_dir,
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+            next_slot: 0,
+        }
+    }
+
+    pub fn trash_dir(&self) -> &Path {
+        &self.trash_dir
+    }
+
+    /// The operation the next [`FileOperations::undo`] reverts.
+    pub fn next_undo(&self) -> Option<&FileOperation> {
+        self.undo_stack.last()
+    }
+
+    /// The operation the next [`FileOperations::redo`] repeats.
+    pub fn next_redo(&self) -> Option<&FileOperation> {
+        self.redo_stack.last()
+    }
+
+    pub fn create_file<V: Vfs>(&mut self, fs: &FileSystem<V>, directory: &Path, name: &str) -> io::Result<PathBuf> {
+        let path = directory.join(name);
+        if fs.path_exists(&path) {
+            return Err(io::Error::new(ErrorKind::AlreadyExists, format!("{} already exists", path.display())));
+        }
+        fs.create_new_file(directory, name)?;
+        let slot = self.trash_slot(&path);
+        self.record(FileOperationKind::Create, slot, path.clone());
+        Ok(path)
+    }
+
+    pub fn create_directory<V: Vfs>(&mut self, fs: &FileSystem<V>, path: &Path) -> io::Result<()> {
+        if fs.path_exists(path) {
+            return Err(io::Error::new(ErrorKind::AlreadyExists, format!("{} already exists", path.display())));
+        }
+        fs.create_directory(path)?;
+        let slot = self.trash_slot(path);
+        self.record(FileOperationKind::Create, slot, path.to_path_buf());
+        Ok(())
+    }
+
+    /// Renames or moves `from` to `to`, which must not exist yet.
+    pub fn rename<V: Vfs>(&mut self, fs: &FileSystem<V>, from: &Path, to: &Path) -> io::Result<()> {
+        check_not_into_itself(from, to)?;
+        move_path(fs, from, to)?;
+        self.record(FileOperationKind::Rename, from.to_path_buf(), to.to_path_buf());
+        Ok(())
+    }
+
+    /// Copies a file or folder to `to`, which must not exist yet. `from` may be
+    /// outside the project, e.g. a file dropped from the desktop. Undoing moves
+    /// the copy to the trash.
+    pub fn copy<V: Vfs>(&mut self, fs: &FileSystem<V>, from: &Path, to: &Path) -> io::Result<()> {
+        check_not_into_itself(from, to)?;
+        if !fs.path_exists(from) {
+            return Err(io::Error::new(ErrorKind::NotFound, format!("{} no longer exists", from.display())));
+        }
+        if fs.path_exists(to) {
+            return Err(io::Error::new(ErrorKind::AlreadyExists, format!("{} already exists", to.display())));
+        }
+        copy_path(fs, from, to)?;
+        let slot = self.trash_slot(to);
+        self.record(FileOperationKind::Create, slot, to.to_path_buf());
+        Ok(())
+    }
+
+    /// Moves `path` to the trash.
+    pub fn delete<V: Vfs>(&mut self, fs: &FileSystem<V>, path: &Path) -> io::Result<()> {
+        let slot = self.trash_slot(path);
+        move_path(fs, path, &slot)?;
+        self.record(FileOperationKind::Delete, path.to_path_buf(), slot);
+        Ok(())
+    }
+
+    /// Adds `edit` to the most recent operation, so that undoing the operation
+    /// puts the file back the way it was before.
+    pub fn record_edit(&mut self, edit: ContentEdit) {
+        if let Some(operation) = self.undo_stack.last_mut() {
+            operation.edits.push(edit);
+        }
+    }
+
+    /// Reverts the most recent operation and returns it. Nothing changes when
+    /// it fails, e.g. because something else was created at the old path or a
+    /// file it edited changed since.
+    pub fn undo<V: Vfs>(&mut self, fs: &FileSystem<V>) -> io::Result<Option<FileOperation>> {
+        let Some(operation) = self.undo_stack.last().cloned() else {
+            return Ok(None);
+        };
+        check_contents(fs, operation.edits.iter().map(|edit| (&edit.path, &edit.after)))?;
+        move_path(fs, &operation.to, &operation.from)?;
+        for edit in operation.edits.iter().rev() {
+            fs.save_file(&edit.path, &edit.before)?;
+        }
+        self.undo_stack.pop();
+        self.redo_stack.push(operation.clone());
+        Ok(Some(operation))
+    }
+
+    /// Repeats the most recently undone operation and returns it.
+    pub fn redo<V: Vfs>(&mut self, fs: &FileSystem<V>) -> io::Result<Option<FileOperation>> {
+        let Some(operation) = self.redo_stack.last().cloned() else {
+            return Ok(None);
+        };
+        check_contents(fs, operation.edits.iter().map(|edit| (&edit.path, &edit.before)))?;
+        move_path(fs, &operation.from, &operation.to)?;
+        for edit in &operation.edits {
+            fs.save_file(&edit.path, &edit.after)?;
+        }
+        self.redo_stack.pop();
+        self.undo_stack.push(operation.clone());
+        Ok(Some(operation))
+    }
+
+    /// Deletes everything in the trash for good. Nothing that went through it
+    /// can be undone afterwards.
+    pub fn empty_trash<V: Vfs>(&mut self, fs: &FileSystem<V>) -> io::Result<()> {
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        if fs.path_exists(&self.trash_dir) {
+            fs.delete_file(&self.trash_dir)?;
+        }
+        Ok(())
+    }
+
+    fn record(&mut self, kind: FileOperationKind, from: PathBuf, to: PathBuf) {
+        self.redo_stack.clear();
+        self.undo_stack.push(FileOperation { kind, from, to, edits: Vec::new() });
+        if self.undo_stack.len() > Self::MAX_UNDO {
+            self.undo_stack.remove(0);
+        }
+    }
+
+    /// A fresh place in the trash for `path`, keeping its name.
+    fn trash_slot(&mut self, path: &Path) -> PathBuf {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_nanos());
+        self.next_slot += 1;
+        let name = path.file_name().map_or_else(|| "unnamed".into(), |name| name.to_os_string());
+        self.trash_dir.join(format!("{}-{}", nanos, self.next_slot)).join(name)
+    }
+}
+
+/// This run's folder in the trash that every IDE window shares. A lock on the
+/// file next to the folder is held while the window is open, so windows
+/// started later only clear the folders of windows that closed or crashed.
+pub struct TrashSession {
+    dir: PathBuf,
+    // The operating system releases the lock however the process ends
+    _lock: File,
+}
+
+impl TrashSession {
+    const LOCK_EXTENSION: &'static str = "lock";
+
+    /// Claims a new folder in `trash_root` after deleting the abandoned ones,
+    /// whose deletions can't be undone by anyone any more.
+    pub fn start(trash_root: &Path) -> io::Result<Self> {
+        fs::create_dir_all(trash_root)?;
+        Self::clear_abandoned(trash_root);
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_nanos());
+        let dir = trash_root.join(format!("{}-{}", std::process::id(), nanos));
+        let lock = File::create_new(dir.with_extension(Self::LOCK_EXTENSION))?;
+        lock.try_lock().map_err(|e| match e {
+            TryLockError::Error(e) => e,
+            TryLockError::WouldBlock => io::Error::new(ErrorKind::WouldBlock, "the trash is locked"),
+        })?;
+        fs::create_dir(&dir)?;
+        Ok(Self { dir, _lock: lock })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Deletes the folders whose lock nobody holds, and any without a lock,
+    /// which only an older version of the IDE leaves behind.
+    fn clear_abandoned(trash_root: &Path) {
+        let Ok(entries) = fs::read_dir(trash_root) else {
+            return;
+        };
+        for path in entries.flatten().map(|entry| entry.path()) {
+            if path.extension().is_some_and(|extension| extension == Self::LOCK_EXTENSION) {
+                let Ok(lock) = File::open(&path) else {
+                    continue;
+                };
+                if lock.try_lock().is_ok() {
+                    let _ = fs::remove_dir_all(path.with_extension(""));
+                    // Windows can't remove a file that is still open
+                    drop(lock);
+                    let _ = fs::remove_file(&path);
+                }
+            } else if !path.with_extension(Self::LOCK_EXTENSION).exists() {
+                let _ = if path.is_dir() { fs::remove_dir_all(&path) } else { fs::remove_file(&path) };
+            }
+        }
+    }
+}
+
+/// Where a copy of `name` goes in `directory`: the name itself if it is free,
+/// otherwise `name copy.ext`, `name copy 2.ext` and so on.
+pub fn free_copy_path<V: Vfs>(fs: &FileSystem<V>, directory: &Path, name: &str) -> PathBuf {
+    let path = directory.join(name);
+    if !fs.path_exists(&path) {
+        return path;
+    }
+    let (stem, extension) = match Path::new(name).extension().and_then(|ext| ext.to_str()) {
+        Some(extension) => (&name[..name.len() - extension.len() - 1], format!(".{}", extension)),
+        None => (name, String::new()),
+    };
+    (1..)
+        .map(|number| match number {
+            1 => directory.join(format!("{} copy{}", stem, extension)),
+            number => directory.join(format!("{} copy {}{}", stem, number, extension)),
+        })
+        .find(|candidate| !fs.path_exists(candidate))
+        .unwrap_or(path)
+}
+
+/// Fails unless every file has the expected content.
+fn check_contents<'a, V: Vfs>(
+    fs: &FileSystem<V>,
+    mut expected: impl Iterator<Item = (&'a PathBuf, &'a String)>,
+) -> io::Result<()> {
+    match expected.find(|(path, content)| fs.open_file(path).ok().as_ref() != Some(*content)) {
+        Some((path, _)) => Err(io::Error::other(format!("{} changed since", path.display()))),
+        None => Ok(()),
+    }
+}
+
+fn check_not_into_itself(from: &Path, to: &Path) -> io::Result<()> {
+    if to.starts_with(from) {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("Can't put {} inside itself", from.display()),
+        ));
+    }
+    Ok(())
+}
+
+/// Moves a file or directory, copying it when a plain rename can't cross
+/// devices, e.g. from a project on another drive into the trash.
+fn move_path<V: Vfs>(fs: &FileSystem<V>, from: &Path, to: &Path) -> io::Result<()> {
+    if !fs.path_exists(from) {
+        return Err(io::Error::new(ErrorKind::NotFound, format!("{} no longer exists", from.display())));
+    }
+    if fs.path_exists(to) {
+        return Err(io::Error::new(ErrorKind::AlreadyExists, format!("{} already exists", to.display())));
+    }
+    match fs.rename_file(from, to) {
+        Err(e) if e.kind() == ErrorKind::CrossesDevices => {
+            copy_path(fs, from, to)?;
+            fs.delete_file(from)
+        }
+        result => result,
+    }
+}
+
+fn copy_path<V: Vfs>(fs: &FileSystem<V>, from: &Path, to: &Path) -> io::Result<()> {
+    let vfs = fs.vfs();
+    if vfs.is_dir(from) {
+        fs.create_directory(to)?;
+        for entry in vfs.read_dir(from)? {
+            copy_path(fs, &from.join(&entry.name), &to.join(&entry.name))?;
+        }
+        Ok(())
+    } else {
+        fs.save_bytes(to, &vfs.read(from)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::vfs::MemoryVfs;
+
+    fn project() -> (FileSystem<MemoryVfs>, FileOperations) {
+        let fs = FileSystem::with_vfs("/project", MemoryVfs::new());
+        fs.create_directory(Path::new("/project/src")).unwrap();
+        (fs, FileOperations::new(PathBuf::from("/trash")))
+    }
+
+    #[test]
+    fn create_rename_and_delete_can_be_undone_and_redone() {
+        let (fs, mut operations) = project();
+        let src = Path::new("/project/src");
+        let created = operations.create_file(&fs, src, "Main.kt").unwrap();
+        fs.save_file(&created, "fun main() {}").unwrap();
+        let renamed = src.join("App.kt");
+        operations.rename(&fs, &created, &renamed).unwrap();
+        operations.delete(&fs, &renamed).unwrap();
+        assert!(!fs.path_exists(&renamed));
+
+        let undone = operations.undo(&fs).unwrap().unwrap();
+        assert_eq!(undone.kind, FileOperationKind::Delete);
+        assert_eq!(fs.open_file(&renamed).unwrap(), "fun main() {}");
+        operations.undo(&fs).unwrap();
+        assert!(fs.path_exists(&created));
+        assert!(!fs.path_exists(&renamed));
+        // An undone creation keeps what was written, in the trash
+        operations.undo(&fs).unwrap();
+        assert!(!fs.path_exists(&created));
+        assert!(operations.undo(&fs).unwrap().is_none());
+
+        operations.redo(&fs).unwrap();
+        assert_eq!(fs.open_file(&created).unwrap(), "fun main() {}");
+        operations.redo(&fs).unwrap();
+        operations.redo(&fs).unwrap();
+        assert!(!fs.path_exists(&renamed));
+        assert!(operations.next_redo().is_none());
+    }
+
+    #[test]
+    fn copy_duplicates_folders_and_undo_moves_the_copy_to_the_trash() {
+        let (fs, mut operations) = project();
+        fs.save_file(Path::new("/project/src/a.txt"), "a").unwrap();
+        let copy = free_copy_path(&fs, Path::new("/project"), "src");
+        assert_eq!(copy, Path::new("/project/src copy"));
+        operations.copy(&fs, Path::new("/project/src"), &copy).unwrap();
+        assert_eq!(fs.open_file(&copy.join("a.txt")).unwrap(), "a");
+
+        operations.undo(&fs).unwrap();
+        assert!(!fs.path_exists(&copy));
+        assert!(fs.path_exists(Path::new("/project/src/a.txt")));
+    }
+
+    #[test]
+    fn failed_operations_change_nothing() {
+        let (fs, mut operations) = project();
+        let src = Path::new("/project/src");
+        operations.create_file(&fs, src, "a.txt").unwrap();
+        assert_eq!(operations.create_file(&fs, src, "a.txt").unwrap_err().kind(), ErrorKind::AlreadyExists);
+        let into_itself = operations.rename(&fs, src, &src.join("inner"));
+        assert_eq!(into_itself.unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert_eq!(operations.next_undo().map(|operation| operation.kind), Some(FileOperationKind::Create));
+
+        // Something new took the old place, so the undo can't move the file back
+        operations.delete(&fs, &src.join("a.txt")).unwrap();
+        fs.save_file(&src.join("a.txt"), "new").unwrap();
+        assert!(operations.undo(&fs).is_err());
+        assert_eq!(operations.next_undo().map(|operation| operation.kind), Some(FileOperationKind::Delete));
+    }
+
+    #[test]
+    fn trash_sessions_only_clear_the_folders_of_ended_sessions() {
+        let root = std::env::temp_dir().join(format!("zzz-trash-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("1-1")).unwrap();
+        fs::write(root.join("1-1/old.txt"), "left by an older version").unwrap();
+
+        let first = TrashSession::start(&root).unwrap();
+        assert!(!root.join("1-1").exists());
+        fs::write(first.dir().join("deleted.txt"), "can still be undone").unwrap();
+        let second = TrashSession::start(&root).unwrap();
+        assert!(first.dir().join("deleted.txt").exists());
+
+        let first_dir = first.dir().to_path_buf();
+        drop(first);
+        let third = TrashSession::start(&root).unwrap();
+        assert!(!first_dir.exists());
+        assert!(second.dir().exists() && third.dir().exists());
+        drop((second, third));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn edits_recorded_with_an_operation_are_undone_with_it() {
+        let (fs, mut operations) = project();
+        let manifest = Path::new("/project/AndroidManifest.xml");
+        fs.save_file(manifest, "<application />").unwrap();
+        let created = operations.create_file(&fs, Path::new("/project/src"), "MainActivity.kt").unwrap();
+        fs.save_file(manifest, "<application>main</application>").unwrap();
+        operations.record_edit(ContentEdit {
+            path: manifest.to_path_buf(),
+            before: "<application />".to_string(),
+            after: "<application>main</application>".to_string(),
+        });
+
+        operations.undo(&fs).unwrap();
+        assert!(!fs.path_exists(&created));
+        assert_eq!(fs.open_file(manifest).unwrap(), "<application />");
+        operations.redo(&fs).unwrap();
+        assert!(fs.path_exists(&created));
+        assert_eq!(fs.open_file(manifest).unwrap(), "<application>main</application>");
+
+        // Changed by hand since, so the undo leaves everything alone
+        fs.save_file(manifest, "<application>edited</application>").unwrap();
+        assert!(operations.undo(&fs).is_err());
+        assert!(fs.path_exists(&created));
+        assert_eq!(fs.open_file(manifest).unwrap(), "<application>edited</application>");
+    }
+}
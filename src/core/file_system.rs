@@ -1,9 +1,9 @@
-use std::fs;
 use std::io::{self, ErrorKind};
 use std::path::{Path, PathBuf};
-use std::time::{SystemTime, UNIX_EPOCH, Duration};
+use std::time::{SystemTime, Duration};
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
+use crate::core::vfs::{DiskVfs, Vfs};
 
 #[derive(Clone, Debug)]
 pub struct DirectoryEntry {
@@ -13,12 +13,25 @@ pub struct DirectoryEntry {
     pub modified: SystemTime,
 }
 
-#[derive(Clone)]
-pub struct FileSystem {
+/// Cached access to the files of a project. All I/O goes through the [`Vfs`]
+/// it is created with, which is the disk unless a test or another backend
+/// provides its own.
+pub struct FileSystem<V: Vfs = DiskVfs> {
     project_directory: PathBuf,
+    vfs: Arc<V>,
     cache: Arc<Mutex<FileSystemCache>>,
 }
 
+impl<V: Vfs> Clone for FileSystem<V> {
+    fn clone(&self) -> Self {
+        Self {
+            project_directory: self.project_directory.clone(),
+            vfs: self.vfs.clone(),
+            cache: self.cache.clone(),
+        }
+    }
+}
+
 #[derive(Default)]
 struct FileSystemCache {
     directory_contents: HashMap<PathBuf, Vec<DirectoryEntry>>,
@@ -26,18 +39,34 @@ struct FileSystemCache {
     file_contents: HashMap<PathBuf, (String, SystemTime)>,
 }
 
-impl FileSystem {
+impl FileSystem<DiskVfs> {
+    /// Creates a new `FileSystem` instance for the given project directory on disk.
+    pub fn new(project_directory: &str) -> Self {
+        Self::with_vfs(project_directory, DiskVfs)
+    }
+}
+
+impl<V: Vfs> FileSystem<V> {
     const CACHE_TIMEOUT_SECS: u64 = 300; // 5 minutes
     const MAX_FILE_SIZE_BYTES: u64 = 10_000_000; // 10 MB
 
-    /// Creates a new `FileSystem` instance with the given project directory.
-    pub fn new(project_directory: &str) -> Self {
+    /// Creates a new `FileSystem` instance for a project directory in `vfs`.
+    pub fn with_vfs(project_directory: &str, vfs: V) -> Self {
         Self {
             project_directory: PathBuf::from(project_directory),
+            vfs: Arc::new(vfs),
             cache: Arc::new(Mutex::new(FileSystemCache::default())),
         }
     }
 
+    pub fn vfs(&self) -> &V {
+        &self.vfs
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.vfs.is_read_only()
+    }
+
     /// Creates a new file with the specified filename in the given directory.
     pub fn create_new_file(&self, directory: &Path, filename: &str) -> io::Result<PathBuf> {
         let path = directory.join(filename);
@@ -46,7 +75,7 @@ impl FileSystem {
         self.ensure_directory_exists(directory)?;
 
         // Create the file
-        fs::File::create(&path)?;
+        self.vfs.write(&path, b"")?;
 
         // Invalidate cache for the parent directory
         self.invalidate_directory_cache(directory);
@@ -62,16 +91,16 @@ impl FileSystem {
         }
 
         // Check file size before reading
-        let metadata = fs::metadata(path)?;
-        if metadata.len() > Self::MAX_FILE_SIZE_BYTES {
+        let metadata = self.vfs.metadata(path)?;
+        if metadata.len > Self::MAX_FILE_SIZE_BYTES {
             return Err(io::Error::new(
                 ErrorKind::Other,
-                format!("File too large to open ({} bytes)", metadata.len()),
+                format!("File too large to open ({} bytes)", metadata.len),
             ));
         }
 
         // Read file content
-        let content = fs::read_to_string(path)?;
+        let content = self.vfs.read_to_string(path)?;
 
         // Cache the file content
         self.cache_file_content(path, &content);
@@ -85,7 +114,7 @@ impl FileSystem {
         self.ensure_directory_exists(path.parent().unwrap_or(path))?;
 
         // Write content to file
-        fs::write(path, content)?;
+        self.vfs.write(path, content.as_bytes())?;
 
         // Update cache
         self.cache_file_content(path, content);
@@ -96,6 +125,20 @@ impl FileSystem {
         Ok(())
     }
 
+    /// Saves binary content, which is not kept in the content cache.
+    pub fn save_bytes(&self, path: &Path, content: &[u8]) -> io::Result<()> {
+        self.ensure_directory_exists(path.parent().unwrap_or(path))?;
+        self.vfs.write(path, content)?;
+        self.remove_file_content_cache(path);
+        self.invalidate_directory_cache(path.parent().unwrap_or(path));
+        Ok(())
+    }
+
+    /// Makes a file executable, e.g. a wrapper script.
+    pub fn set_executable(&self, path: &Path) -> io::Result<()> {
+        self.vfs.set_executable(path)
+    }
+
     /// Lists the entries in the specified directory with caching.
     pub fn list_directory(&self, dir: &Path) -> io::Result<Vec<DirectoryEntry>> {
         // Check cache first
@@ -104,7 +147,7 @@ impl FileSystem {
         }
 
         // If not in cache, read from file system
-        let mut entries = self.read_directory_entries(dir)?;
+        let mut entries = self.vfs.read_dir(dir)?;
 
         // Sort entries (directories first, then alphabetically)
         entries.sort_by(|a, b| {
@@ -127,7 +170,7 @@ impl FileSystem {
         self.ensure_directory_exists(new_path.parent().unwrap_or(new_path))?;
 
         // Rename the file/directory
-        fs::rename(old_path, new_path)?;
+        self.vfs.rename(old_path, new_path)?;
 
         // Invalidate caches for both old and new parent directories
         self.invalidate_directory_cache(old_path.parent().unwrap_or(old_path));
@@ -136,26 +179,26 @@ impl FileSystem {
         // Update file content cache if applicable
         self.update_file_content_cache(old_path, new_path);
 
+        // Contents cached below a renamed directory are stale
+        self.invalidate(old_path);
+
         Ok(())
     }
 
     /// Deletes a file or directory at the specified path.
     pub fn delete_file(&self, path: &Path) -> io::Result<()> {
         // Determine if it's a directory or file
-        let is_dir = path.is_dir();
+        let is_dir = self.vfs.is_dir(path);
 
         // Delete the file or directory
         if is_dir {
-            fs::remove_dir_all(path)?;
+            self.vfs.remove_dir_all(path)?;
         } else {
-            fs::remove_file(path)?;
+            self.vfs.remove_file(path)?;
         }
 
-        // Invalidate cache for the parent directory
-        self.invalidate_directory_cache(path.parent().unwrap_or(path));
-
-        // Remove from file contents cache
-        self.remove_file_content_cache(path);
+        // Forget the path, anything cached below it and the parent listing
+        self.invalidate(path);
 
         Ok(())
     }
@@ -163,7 +206,7 @@ impl FileSystem {
     /// Creates a new directory at the specified path.
     pub fn create_directory(&self, path: &Path) -> io::Result<()> {
         // Create directory and any necessary parent directories
-        fs::create_dir_all(path)?;
+        self.vfs.create_dir_all(path)?;
 
         // Invalidate cache for the parent directory
         self.invalidate_directory_cache(path.parent().unwrap_or(path));
@@ -171,6 +214,24 @@ impl FileSystem {
         Ok(())
     }
 
+    /// Forgets everything cached about `path` and its parent directory, e.g. after
+    /// it changed on disk. For a directory, everything cached below it goes too.
+    pub fn invalidate(&self, path: &Path) {
+        let mut cache = self.cache.lock().unwrap();
+        cache.file_contents.retain(|cached, _| !cached.starts_with(path));
+        cache.directory_contents.retain(|cached, _| !cached.starts_with(path));
+        cache.last_updated.retain(|cached, _| !cached.starts_with(path));
+        if let Some(parent) = path.parent() {
+            cache.directory_contents.remove(parent);
+            cache.last_updated.remove(parent);
+        }
+    }
+
+    /// Forgets all cached directory listings and file contents.
+    pub fn clear_cache(&self) {
+        *self.cache.lock().unwrap() = FileSystemCache::default();
+    }
+
     /// Returns the project directory.
     pub fn get_project_directory(&self) -> &Path {
         &self.project_directory
@@ -178,14 +239,18 @@ impl FileSystem {
 
     /// Checks if a path exists.
     pub fn path_exists(&self, path: &Path) -> bool {
-        path.exists()
+        self.vfs.exists(path)
+    }
+
+    pub fn is_dir(&self, path: &Path) -> bool {
+        self.vfs.is_dir(path)
     }
 
     // Helper methods
 
     fn ensure_directory_exists(&self, directory: &Path) -> io::Result<()> {
-        if !directory.exists() {
-            fs::create_dir_all(directory)?;
+        if !self.vfs.exists(directory) {
+            self.vfs.create_dir_all(directory)?;
         }
         Ok(())
     }
@@ -242,23 +307,4 @@ impl FileSystem {
         let mut cache = self.cache.lock().unwrap();
         cache.file_contents.remove(path);
     }
-
-    fn read_directory_entries(&self, dir: &Path) -> io::Result<Vec<DirectoryEntry>> {
-        let mut entries = Vec::new();
-        for entry in fs::read_dir(dir)? {
-            let entry = entry?;
-            let path = entry.path();
-            let metadata = entry.metadata()?;
-
-            if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                entries.push(DirectoryEntry {
-                    name: name.to_string(),
-                    is_dir: metadata.is_dir(),
-                    size: metadata.len(),
-                    modified: metadata.modified().unwrap_or(UNIX_EPOCH),
-                });
-            }
-        }
-        Ok(entries)
-    }
 }
\ No newline at end of file
@@ -0,0 +1,302 @@
+use std::io::{self, ErrorKind};
+use std::path::{Component, Path, PathBuf};
+use crate::core::file_operations::ContentEdit;
+use crate::core::file_system::FileSystem;
+use crate::core::vfs::Vfs;
+
+/// Project folder for the team's own templates. Each file in it is a template
+/// named after the file, e.g. `Repository.kt` adds "Repository" to the menu
+/// and creates `.kt` files. Its contents may use the same placeholders as the
+/// built-in templates.
+pub const PROJECT_TEMPLATE_DIR: &str = ".zzztemplates";
+
+/// Replaced by the name of the new file, without its extension.
+const NAME: &str = "${NAME}";
+/// Replaced by the package of the folder the file is created in.
+const PACKAGE_NAME: &str = "${PACKAGE_NAME}";
+
+/// A kind of file that can be created from the "New…" menu.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileTemplate {
+    /// Shown in the menu.
+    pub name: String,
+    /// Suggested name of the new file, without the extension.
+    pub default_name: String,
+    pub extension: String,
+    pub content: String,
+    /// The new class is added to the module's `AndroidManifest.xml`.
+    pub registers_activity: bool,
+    /// Read from the project's [`PROJECT_TEMPLATE_DIR`].
+    pub from_project: bool,
+}
+
+impl FileTemplate {
+    fn built_in(name: &str, default_name: &str, extension: &str, content: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            default_name: default_name.to_string(),
+            extension: extension.to_string(),
+            content: content.to_string(),
+            registers_activity: false,
+            from_project: false,
+        }
+    }
+
+    /// The suggested file name, with the extension.
+    pub fn default_file_name(&self) -> String {
+        format!("{}.{}", self.default_name, self.extension)
+    }
+
+    /// `file_name` with the template's extension, whether it was typed or not.
+    pub fn file_name(&self, file_name: &str) -> String {
+        format!("{}.{}", self.stem(file_name), self.extension)
+    }
+
+    fn stem<'a>(&self, file_name: &'a str) -> &'a str {
+        file_name
+            .strip_suffix(&self.extension)
+            .and_then(|stem| stem.strip_suffix('.'))
+            .unwrap_or(file_name)
+    }
+
+    /// The contents of a file named `file_name` in `package`. Without a
+    /// package the `package` line is left out.
+    pub fn fill(&self, file_name: &str, package: &str) -> String {
+        let mut content = self.content.replace("\r\n", "\n");
+        if package.is_empty() {
+            let package_line = format!("package {}\n", PACKAGE_NAME);
+            content = content
+                .replace(&format!("{}\n", package_line), "")
+                .replace(&package_line, "");
+        }
+        content.replace(NAME, self.stem(file_name)).replace(PACKAGE_NAME, package)
+    }
+}
+
+/// The built-in templates, then those in the project's [`PROJECT_TEMPLATE_DIR`].
+pub fn templates<V: Vfs>(fs: &FileSystem<V>, project_path: &Path) -> Vec<FileTemplate> {
+    let mut templates = built_in_templates();
+    templates.extend(project_templates(fs, project_path));
+    templates
+}
+
+pub fn project_templates<V: Vfs>(fs: &FileSystem<V>, project_path: &Path) -> Vec<FileTemplate> {
+    let template_dir = project_path.join(PROJECT_TEMPLATE_DIR);
+    let Ok(entries) = fs.list_directory(&template_dir) else {
+        return Vec::new();
+    };
+    entries
+        .into_iter()
+        .filter(|entry| !entry.is_dir)
+        .filter_map(|entry| {
+            let path = template_dir.join(&entry.name);
+            let name = path.file_stem()?.to_str()?.to_string();
+            let extension = path.extension()?.to_str()?.to_string();
+            let content = fs.open_file(&path).ok()?;
+            Some(FileTemplate {
+                default_name: name.clone(),
+                name,
+                extension,
+                content,
+                registers_activity: false,
+                from_project: true,
+            })
+        })
+        .collect()
+}
+
+pub fn built_in_templates() -> Vec<FileTemplate> {
+    vec![
+        FileTemplate::built_in("Kotlin Class", "NewClass", "kt", KOTLIN_CLASS),
+        FileTemplate::built_in("Kotlin Interface", "NewInterface", "kt", KOTLIN_INTERFACE),
+        FileTemplate::built_in("Kotlin Object", "NewObject", "kt", KOTLIN_OBJECT),
+        FileTemplate {
+            registers_activity: true,
+            ..FileTemplate::built_in("Activity", "NewActivity", "kt", ACTIVITY)
+        },
+        FileTemplate::built_in("Fragment", "NewFragment", "kt", FRAGMENT),
+        FileTemplate::built_in("Composable Screen", "NewScreen", "kt", COMPOSABLE_SCREEN),
+        FileTemplate::built_in("ViewModel", "NewViewModel", "kt", VIEW_MODEL),
+        FileTemplate::built_in("Layout XML", "new_layout", "xml", LAYOUT_XML),
+        FileTemplate::built_in("Values XML", "new_values", "xml", VALUES_XML),
+    ]
+}
+
+/// The package of sources in `folder`, going by the folders below a source
+/// root like `src/main/kotlin` or `src/test/java`. `None` outside of one.
+pub fn package_for(folder: &Path) -> Option<String> {
+    let components: Vec<&str> = folder
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => name.to_str(),
+            _ => None,
+        })
+        .collect();
+    let source_root = components
+        .windows(3)
+        .rposition(|window| window[0] == "src" && matches!(window[2], "kotlin" | "java"))?;
+    Some(components[source_root + 3..].join("."))
+}
+
+/// The `AndroidManifest.xml` of the source set `folder` is in, or of the
+/// `main` source set if that one has none.
+fn manifest_for<V: Vfs>(fs: &FileSystem<V>, folder: &Path) -> Option<PathBuf> {
+    let source_set = folder
+        .ancestors()
+        .find(|ancestor| ancestor.parent().and_then(Path::file_name).is_some_and(|name| name == "src"))?;
+    [source_set.join("AndroidManifest.xml"), source_set.with_file_name("main").join("AndroidManifest.xml")]
+        .into_iter()
+        .find(|manifest| fs.path_exists(manifest))
+}
+
+/// Adds an `<activity>` element for `class_name` to the manifest of the source
+/// set `folder` is in, and returns how the manifest changed. Does nothing if
+/// the activity is already there.
+pub fn register_activity<V: Vfs>(fs: &FileSystem<V>, folder: &Path, class_name: &str) -> io::Result<ContentEdit> {
+    let manifest = manifest_for(fs, folder).ok_or_else(|| {
+        io::Error::new(ErrorKind::NotFound, format!("No AndroidManifest.xml found for {}", folder.display()))
+    })?;
+    let content = fs.open_file(&manifest)?;
+    if content.contains(&format!("android:name=\"{}\"", class_name)) {
+        return Ok(ContentEdit { path: manifest, before: content.clone(), after: content });
+    }
+    let Some(application_end) = content.rfind("</application>") else {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("{} has no <application> element to add the activity to", manifest.display()),
+        ));
+    };
+    let line_start = content[..application_end].rfind('\n').map_or(0, |newline| newline + 1);
+    let indent = &content[line_start..application_end];
+    let indent = if indent.trim().is_empty() { indent } else { "" };
+    let activity = format!(
+        "{indent}    <activity\n{indent}        android:name=\"{class_name}\"\n{indent}        android:exported=\"false\" />\n",
+    );
+    let mut updated = content.clone();
+    updated.insert_str(line_start, &activity);
+    fs.save_file(&manifest, &updated)?;
+    Ok(ContentEdit { path: manifest, before: content, after: updated })
+}
+
+const KOTLIN_CLASS: &str = r#"package ${PACKAGE_NAME}
+
+class ${NAME} {
+}
+"#;
+
+const KOTLIN_INTERFACE: &str = r#"package ${PACKAGE_NAME}
+
+interface ${NAME} {
+}
+"#;
+
+const KOTLIN_OBJECT: &str = r#"package ${PACKAGE_NAME}
+
+object ${NAME} {
+}
+"#;
+
+const ACTIVITY: &str = r#"package ${PACKAGE_NAME}
+
+import android.os.Bundle
+import androidx.activity.ComponentActivity
+import androidx.activity.compose.setContent
+import androidx.compose.material3.MaterialTheme
+import androidx.compose.material3.Text
+
+class ${NAME} : ComponentActivity() {
+    override fun onCreate(savedInstanceState: Bundle?) {
+        super.onCreate(savedInstanceState)
+        setContent {
+            MaterialTheme {
+                Text(text = "${NAME}")
+            }
+        }
+    }
+}
+"#;
+
+const FRAGMENT: &str = r#"package ${PACKAGE_NAME}
+
+import android.os.Bundle
+import android.view.LayoutInflater
+import android.view.View
+import android.view.ViewGroup
+import androidx.compose.material3.MaterialTheme
+import androidx.compose.material3.Text
+import androidx.compose.ui.platform.ComposeView
+import androidx.fragment.app.Fragment
+
+class ${NAME} : Fragment() {
+    override fun onCreateView(
+        inflater: LayoutInflater,
+        container: ViewGroup?,
+        savedInstanceState: Bundle?
+    ): View = ComposeView(requireContext()).apply {
+        setContent {
+            MaterialTheme {
+                Text(text = "${NAME}")
+            }
+        }
+    }
+}
+"#;
+
+const COMPOSABLE_SCREEN: &str = r#"package ${PACKAGE_NAME}
+
+import androidx.compose.foundation.layout.Box
+import androidx.compose.foundation.layout.fillMaxSize
+import androidx.compose.material3.MaterialTheme
+import androidx.compose.material3.Text
+import androidx.compose.runtime.Composable
+import androidx.compose.ui.Alignment
+import androidx.compose.ui.Modifier
+import androidx.compose.ui.tooling.preview.Preview
+
+@Composable
+fun ${NAME}(modifier: Modifier = Modifier) {
+    Box(modifier = modifier.fillMaxSize(), contentAlignment = Alignment.Center) {
+        Text(text = "${NAME}")
+    }
+}
+
+@Preview(showBackground = true)
+@Composable
+private fun ${NAME}Preview() {
+    MaterialTheme {
+        ${NAME}()
+    }
+}
+"#;
+
+const VIEW_MODEL: &str = r#"package ${PACKAGE_NAME}
+
+import androidx.lifecycle.ViewModel
+import kotlinx.coroutines.flow.MutableStateFlow
+import kotlinx.coroutines.flow.StateFlow
+import kotlinx.coroutines.flow.asStateFlow
+
+class ${NAME} : ViewModel() {
+    private val _uiState = MutableStateFlow(UiState())
+    val uiState: StateFlow<UiState> = _uiState.asStateFlow()
+
+    data class UiState(
+        val isLoading: Boolean = false,
+    )
+}
+"#;
+
+const LAYOUT_XML: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<LinearLayout xmlns:android="http://schemas.android.com/apk/res/android"
+    android:layout_width="match_parent"
+    android:layout_height="match_parent"
+    android:orientation="vertical">
+
+</LinearLayout>
+"#;
+
+const VALUES_XML: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<resources>
+
+</resources>
+"#;
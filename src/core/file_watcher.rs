@@ -0,0 +1,89 @@
+use crossbeam_channel::Receiver;
+use eframe::egui;
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::BTreeSet;
+use std::path::{Component, Path, PathBuf};
+
+/// Changes on disk since the last [`FileWatcher::poll`].
+#[derive(Default)]
+pub struct FileChanges {
+    /// Files and directories that were created, modified or removed, outside
+    /// version control metadata. Sorted, without duplicates.
+    pub paths: Vec<PathBuf>,
+    /// Something below `.git` changed, e.g. after a commit or checkout.
+    pub git_changed: bool,
+    /// Events were dropped, so anything may have changed.
+    pub rescan: bool,
+}
+
+impl FileChanges {
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty() && !self.git_changed && !self.rescan
+    }
+}
+
+/// Watches a project directory recursively, so changes made by Gradle, git or
+/// the terminal show up without waiting for a cache to expire. Events are
+/// queued and picked up on the UI thread with [`FileWatcher::poll`]; each one
+/// also requests a repaint so idle windows notice them.
+pub struct FileWatcher {
+    root: PathBuf,
+    receiver: Receiver<notify::Result<Event>>,
+    // Stops watching when dropped
+    _watcher: RecommendedWatcher,
+}
+
+impl FileWatcher {
+    pub fn new(root: &Path, ctx: egui::Context) -> Result<Self, String> {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            if sender.send(event).is_ok() {
+                ctx.request_repaint();
+            }
+        })
+        .map_err(|e| format!("Failed to create file watcher: {}", e))?;
+        watcher
+            .watch(root, RecursiveMode::Recursive)
+            .map_err(|e| format!("Failed to watch {}: {}", root.display(), e))?;
+        Ok(Self {
+            root: root.to_path_buf(),
+            receiver,
+            _watcher: watcher,
+        })
+    }
+
+    /// Takes all changes reported since the last call.
+    pub fn poll(&self) -> FileChanges {
+        let mut changes = FileChanges::default();
+        let mut paths = BTreeSet::new();
+        for event in self.receiver.try_iter() {
+            let event = match event {
+                Ok(event) => event,
+                Err(_) => {
+                    changes.rescan = true;
+                    continue;
+                }
+            };
+            changes.rescan |= event.need_rescan();
+            if event.kind.is_access() {
+                continue;
+            }
+            for path in event.paths {
+                if self.is_vcs_path(&path) {
+                    changes.git_changed = true;
+                } else {
+                    paths.insert(path);
+                }
+            }
+        }
+        changes.paths = paths.into_iter().collect();
+        changes
+    }
+
+    fn is_vcs_path(&self, path: &Path) -> bool {
+        path.strip_prefix(&self.root)
+            .unwrap_or(path)
+            .components()
+            .any(|component| matches!(component, Component::Normal(name) if name == ".git"))
+    }
+}
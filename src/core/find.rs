@@ -0,0 +1,103 @@
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// How a search pattern is interpreted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FindOptions {
+    /// Treat the pattern as a regular expression instead of literal text.
+    pub regex: bool,
+    pub case_sensitive: bool,
+    /// Only match whole words.
+    pub whole_word: bool,
+}
+
+/// A compiled search pattern. Literal patterns are escaped and compiled too, so
+/// every option combination goes through the same matcher.
+#[derive(Debug, Clone)]
+pub struct FindQuery {
+    pattern: String,
+    options: FindOptions,
+    regex: Regex,
+}
+
+impl FindQuery {
+    pub fn new(pattern: &str, options: FindOptions) -> Result<Self, String> {
+        if pattern.is_empty() {
+            return Err("Empty search pattern".to_string());
+        }
+        let mut source = if options.regex { pattern.to_string() } else { regex::escape(pattern) };
+        if options.whole_word {
+            source = format!(r"\b(?:{})\b", source);
+        }
+        let regex = RegexBuilder::new(&source)
+            .case_insensitive(!options.case_sensitive)
+            .multi_line(true)
+            .build()
+            .map_err(|e| {
+                // Syntax errors span several lines; the last one says what is wrong
+                let message = e.to_string();
+                let reason = message.lines().last().unwrap_or_default().trim_start_matches("error: ").to_string();
+                format!("Invalid regex: {}", reason)
+            })?;
+        Ok(Self {
+            pattern: pattern.to_string(),
+            options,
+            regex,
+        })
+    }
+
+    /// A case-sensitive literal search for `pattern`.
+    pub fn literal(pattern: &str) -> Result<Self, String> {
+        Self::new(pattern, FindOptions { case_sensitive: true, ..FindOptions::default() })
+    }
+
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    pub fn options(&self) -> FindOptions {
+        self.options
+    }
+
+    /// The regular expression the pattern compiled to, with the options that
+    /// are part of the source (escaping, word boundaries) applied.
+    pub fn regex_source(&self) -> &str {
+        self.regex.as_str()
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.regex.is_match(text)
+    }
+
+    /// Byte range of the first non-empty match in `text`.
+    pub fn find(&self, text: &str) -> Option<Range<usize>> {
+        self.regex.find_iter(text).find(|m| !m.is_empty()).map(|m| m.range())
+    }
+
+    /// Byte ranges of all non-empty matches in `text`.
+    pub fn find_all(&self, text: &str) -> Vec<Range<usize>> {
+        self.regex
+            .find_iter(text)
+            .filter(|m| !m.is_empty())
+            .map(|m| m.range())
+            .collect()
+    }
+
+    /// The text that replaces the match at `range` in `text`. In regex mode `$1`,
+    /// `${name}` and friends refer to the match's capture groups; otherwise the
+    /// replacement is used as is.
+    pub fn expand_replacement(&self, text: &str, range: Range<usize>, replacement: &str) -> String {
+        if !self.options.regex {
+            return replacement.to_string();
+        }
+        match self.regex.captures_at(text, range.start).filter(|c| c.get(0).map(|m| m.range()) == Some(range)) {
+            Some(captures) => {
+                let mut expanded = String::new();
+                captures.expand(replacement, &mut expanded);
+                expanded
+            }
+            None => replacement.to_string(),
+        }
+    }
+}
@@ -0,0 +1,391 @@
+use std::collections::BTreeSet;
+use std::ops::Range;
+use std::path::Path;
+use crate::components::code_editor::{Buffer, LineChange};
+
+/// A foldable block of lines. Folding it hides `start_line + 1..=end_line`; the
+/// start line stays visible with a placeholder after it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FoldRegion {
+    pub start_line: usize,
+    pub end_line: usize,
+}
+
+impl FoldRegion {
+    pub fn hidden_lines(&self) -> Range<usize> {
+        self.start_line + 1..self.end_line + 1
+    }
+
+    pub fn contains_line(&self, line: usize) -> bool {
+        (self.start_line..=self.end_line).contains(&line)
+    }
+}
+
+/// How fold regions are derived from the text of a buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FoldStrategy {
+    /// `{}`, `()` and `[]` blocks plus multi-line block comments.
+    Brackets,
+    /// Blocks of lines indented deeper than the line before them.
+    Indentation,
+    /// Element nesting and multi-line comments.
+    Xml,
+}
+
+impl FoldStrategy {
+    pub fn for_buffer(buffer: &Buffer) -> Self {
+        let extension = buffer.file_path
+            .as_deref()
+            .and_then(|path| Path::new(path).extension())
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+        match (extension.as_deref(), buffer.syntax.as_str()) {
+            (Some("xml" | "svg" | "html" | "htm" | "plist"), _) | (_, "XML" | "HTML") => Self::Xml,
+            (Some("py" | "yml" | "yaml"), _) | (_, "Python" | "YAML") => Self::Indentation,
+            _ => Self::Brackets,
+        }
+    }
+}
+
+/// The fold regions of a buffer and which of them are currently folded.
+/// Regions are recomputed by the buffer whenever its text changed; folded
+/// regions are remembered by their start line.
+#[derive(Debug, Clone, Default)]
+pub struct Folding {
+    /// Sorted by start line, at most one region per line.
+    regions: Vec<FoldRegion>,
+    /// Revision and strategy the regions were computed for.
+    computed_for: Option<(u64, FoldStrategy)>,
+    folded: BTreeSet<usize>,
+}
+
+impl Folding {
+    pub fn is_stale(&self, revision: u64, strategy: FoldStrategy) -> bool {
+        self.computed_for != Some((revision, strategy))
+    }
+
+    /// Replaces the regions, forgetting folds whose region no longer exists.
+    pub fn set_regions(&mut self, regions: Vec<FoldRegion>, revision: u64, strategy: FoldStrategy) {
+        self.regions = regions;
+        self.computed_for = Some((revision, strategy));
+        let regions = &self.regions;
+        self.folded.retain(|line| regions.binary_search_by_key(line, |r| r.start_line).is_ok());
+    }
+
+    pub fn regions(&self) -> &[FoldRegion] {
+        &self.regions
+    }
+
+    /// The region that starts on `line`, if any.
+    pub fn region_at(&self, line: usize) -> Option<FoldRegion> {
+        self.regions
+            .binary_search_by_key(&line, |region| region.start_line)
+            .ok()
+            .map(|index| self.regions[index])
+    }
+
+    /// The innermost region that contains `line`.
+    pub fn innermost_containing(&self, line: usize) -> Option<FoldRegion> {
+        let candidates = self.regions.partition_point(|region| region.start_line <= line);
+        self.regions[..candidates]
+            .iter()
+            .rev()
+            .find(|region| region.contains_line(line))
+            .copied()
+    }
+
+    pub fn is_folded(&self, start_line: usize) -> bool {
+        self.folded.contains(&start_line)
+    }
+
+    pub fn fold(&mut self, start_line: usize) {
+        if self.region_at(start_line).is_some() {
+            self.folded.insert(start_line);
+        }
+    }
+
+    pub fn unfold(&mut self, start_line: usize) {
+        self.folded.remove(&start_line);
+    }
+
+    pub fn toggle(&mut self, start_line: usize) {
+        if self.is_folded(start_line) {
+            self.unfold(start_line);
+        } else {
+            self.fold(start_line);
+        }
+    }
+
+    pub fn fold_all(&mut self) {
+        self.folded = self.regions.iter().map(|region| region.start_line).collect();
+    }
+
+    pub fn unfold_all(&mut self) {
+        self.folded.clear();
+    }
+
+    /// Start lines of the folded regions, e.g. for saving them.
+    pub fn folded_lines(&self) -> Vec<usize> {
+        self.folded.iter().copied().collect()
+    }
+
+    /// Restores folds saved with [`Folding::folded_lines`]. Lines without a
+    /// region are dropped the next time the regions are computed.
+    pub fn set_folded_lines(&mut self, lines: &[usize]) {
+        self.folded = lines.iter().copied().collect();
+    }
+
+    /// Keeps folds attached to their blocks as lines are inserted or removed.
+    pub fn apply_change(&mut self, change: &LineChange) {
+        let removed = change.start_line + 1..change.start_line + 1 + change.removed_lines;
+        self.folded = self.folded
+            .iter()
+            .filter(|line| !removed.contains(line))
+            .map(|line| change.map_line(*line))
+            .collect();
+    }
+
+    /// Lines hidden by folded regions, sorted and merged.
+    pub fn hidden_ranges(&self) -> Vec<Range<usize>> {
+        let mut hidden: Vec<Range<usize>> = Vec::new();
+        for region in self.folded.iter().filter_map(|line| self.region_at(*line)) {
+            let lines = region.hidden_lines();
+            match hidden.last_mut() {
+                Some(last) if lines.start <= last.end => last.end = last.end.max(lines.end),
+                _ => hidden.push(lines),
+            }
+        }
+        hidden
+    }
+
+    /// Unfolds every region that hides `line`. Returns `true` if anything changed.
+    pub fn reveal_line(&mut self, line: usize) -> bool {
+        let hiding: Vec<usize> = self.folded
+            .iter()
+            .copied()
+            .filter(|start| self.region_at(*start).is_some_and(|region| region.hidden_lines().contains(&line)))
+            .collect();
+        for start in &hiding {
+            self.folded.remove(start);
+        }
+        !hiding.is_empty()
+    }
+}
+
+/// Computes the fold regions of `buffer`, sorted by start line.
+pub fn compute_regions(buffer: &Buffer, strategy: FoldStrategy) -> Vec<FoldRegion> {
+    let mut regions = match strategy {
+        FoldStrategy::Brackets => bracket_regions(buffer),
+        FoldStrategy::Indentation => indentation_regions(buffer),
+        FoldStrategy::Xml => xml_regions(buffer),
+    };
+    // Keep the largest region per start line, e.g. for `foo({` only the outer block
+    regions.retain(|region| region.end_line > region.start_line);
+    regions.sort_by_key(|region| (region.start_line, std::cmp::Reverse(region.end_line)));
+    regions.dedup_by_key(|region| region.start_line);
+    regions
+}
+
+fn bracket_regions(buffer: &Buffer) -> Vec<FoldRegion> {
+    let mut regions = Vec::new();
+    let mut open: Vec<(char, usize)> = Vec::new();
+    let mut block_comment_start: Option<usize> = None;
+    let mut in_raw_string = false;
+
+    for (line_index, line) in buffer.lines().enumerate() {
+        let chars: Vec<char> = line.chars().collect();
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            let next = chars.get(i + 1).copied();
+
+            if let Some(start) = block_comment_start {
+                if c == '*' && next == Some('/') {
+                    block_comment_start = None;
+                    // The closing line stays hidden with the rest of the comment
+                    regions.push(FoldRegion { start_line: start, end_line: line_index });
+                    i += 2;
+                } else {
+                    i += 1;
+                }
+                continue;
+            }
+            if in_raw_string {
+                if chars[i..].starts_with(&['"', '"', '"']) {
+                    in_raw_string = false;
+                    i += 3;
+                } else {
+                    i += 1;
+                }
+                continue;
+            }
+
+            match c {
+                '/' if next == Some('/') => break,
+                '/' if next == Some('*') => {
+                    block_comment_start = Some(line_index);
+                    i += 2;
+                    continue;
+                }
+                '"' if chars[i..].starts_with(&['"', '"', '"']) => {
+                    in_raw_string = true;
+                    i += 3;
+                    continue;
+                }
+                '"' => i = skip_quoted(&chars, i, '"'),
+                // Only treat quotes as char literals when they look like one, so
+                // apostrophes such as Rust lifetimes don't swallow the line
+                '\'' if next == Some('\\') => i = skip_quoted(&chars, i, '\''),
+                '\'' if chars.get(i + 2) == Some(&'\'') => i += 2,
+                '{' | '(' | '[' => open.push((c, line_index)),
+                '}' | ')' | ']' => {
+                    let opener = match c {
+                        '}' => '{',
+                        ')' => '(',
+                        _ => '[',
+                    };
+                    if let Some(position) = open.iter().rposition(|(kind, _)| *kind == opener) {
+                        let (_, start_line) = open[position];
+                        open.truncate(position);
+                        if line_index > start_line + 1 {
+                            regions.push(FoldRegion { start_line, end_line: line_index - 1 });
+                        }
+                    }
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+    }
+    regions
+}
+
+/// Index of the closing quote of the literal that starts at `start`, or the
+/// last index of the line if it is unterminated.
+fn skip_quoted(chars: &[char], start: usize, quote: char) -> usize {
+    let mut i = start + 1;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 2,
+            c if c == quote => return i,
+            _ => i += 1,
+        }
+    }
+    chars.len().saturating_sub(1)
+}
+
+fn indentation_regions(buffer: &Buffer) -> Vec<FoldRegion> {
+    let mut regions = Vec::new();
+    let mut open: Vec<(usize, usize)> = Vec::new();
+    let mut last_content_line = 0;
+
+    for (line_index, line) in buffer.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let indent = line
+            .chars()
+            .take_while(|c| c.is_whitespace())
+            .map(|c| if c == '\t' { 4 } else { 1 })
+            .sum::<usize>();
+        while let Some(&(start_line, start_indent)) = open.last() {
+            if start_indent < indent {
+                break;
+            }
+            open.pop();
+            regions.push(FoldRegion { start_line, end_line: last_content_line });
+        }
+        open.push((line_index, indent));
+        last_content_line = line_index;
+    }
+    for (start_line, _) in open {
+        regions.push(FoldRegion { start_line, end_line: last_content_line });
+    }
+    regions
+}
+
+enum XmlState {
+    Text,
+    Comment { start_line: usize },
+    /// Inside `<...>`. Declarations and processing instructions have no name.
+    Tag {
+        start_line: usize,
+        name: Option<String>,
+        closing: bool,
+        quote: Option<char>,
+    },
+}
+
+fn xml_regions(buffer: &Buffer) -> Vec<FoldRegion> {
+    let mut regions = Vec::new();
+    let mut open: Vec<(String, usize)> = Vec::new();
+    let mut state = XmlState::Text;
+
+    for (line_index, line) in buffer.lines().enumerate() {
+        let chars: Vec<char> = line.chars().collect();
+        let mut i = 0;
+        while i < chars.len() {
+            match &mut state {
+                XmlState::Text => {
+                    if chars[i..].starts_with(&['<', '!', '-', '-']) {
+                        state = XmlState::Comment { start_line: line_index };
+                        i += 4;
+                        continue;
+                    }
+                    if chars[i] == '<' {
+                        let closing = chars.get(i + 1) == Some(&'/');
+                        let name_start = if closing { i + 2 } else { i + 1 };
+                        let name: String = chars[name_start.min(chars.len())..]
+                            .iter()
+                            .take_while(|c| c.is_alphanumeric() || matches!(c, ':' | '-' | '_' | '.'))
+                            .collect();
+                        state = XmlState::Tag {
+                            start_line: line_index,
+                            name: Some(name).filter(|name| !name.is_empty()),
+                            closing,
+                            quote: None,
+                        };
+                        i = name_start;
+                        continue;
+                    }
+                }
+                XmlState::Comment { start_line } => {
+                    if chars[i..].starts_with(&['-', '-', '>']) {
+                        regions.push(FoldRegion { start_line: *start_line, end_line: line_index });
+                        state = XmlState::Text;
+                        i += 3;
+                        continue;
+                    }
+                }
+                XmlState::Tag { start_line, name, closing, quote } => match (chars[i], *quote) {
+                    (c, Some(q)) if c == q => *quote = None,
+                    (_, Some(_)) => {}
+                    ('"' | '\'', None) => *quote = Some(chars[i]),
+                    ('>', None) => {
+                        let self_closing = i > 0 && chars[i - 1] == '/';
+                        match (name.take(), *closing) {
+                            (Some(name), true) => {
+                                if let Some(position) = open.iter().rposition(|(open_name, _)| *open_name == name) {
+                                    let (_, open_line) = open[position];
+                                    open.truncate(position);
+                                    if line_index > open_line + 1 {
+                                        regions.push(FoldRegion { start_line: open_line, end_line: line_index - 1 });
+                                    }
+                                }
+                            }
+                            (Some(_), false) if self_closing => {
+                                regions.push(FoldRegion { start_line: *start_line, end_line: line_index });
+                            }
+                            (Some(name), false) => open.push((name, *start_line)),
+                            (None, _) => {}
+                        }
+                        state = XmlState::Text;
+                    }
+                    _ => {}
+                },
+            }
+            i += 1;
+        }
+    }
+    regions
+}
@@ -1,9 +1,10 @@
 use std::process::Command;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use chrono::{DateTime, Local};
 use serde::{Serialize, Deserialize};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use crate::core::git_status::GitStatus;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GitCommit {
@@ -118,6 +119,41 @@ impl GitManager {
         )
     }
 
+    /// Returns the committed (HEAD) content of a file, or `None` if the file is
+    /// not tracked or not inside a repository.
+    pub fn file_at_head(file_path: &Path) -> Result<Option<String>, String> {
+        let (Some(dir), Some(file_name)) = (file_path.parent(), file_path.file_name()) else {
+            return Ok(None);
+        };
+        let spec = format!("HEAD:./{}", file_name.to_string_lossy());
+        let output = Self::run_git_command(&["show", &spec], &dir.to_path_buf())?;
+        if !output.status.success() {
+            return Ok(None);
+        }
+        Ok(Some(String::from_utf8_lossy(&output.stdout).into_owned()))
+    }
+
+    /// Status of the changed, untracked and ignored files below the repository path.
+    pub fn status(&self) -> Result<GitStatus, String> {
+        let prefix = Self::run_git_command(&["rev-parse", "--show-prefix"], &self.repo_path)?;
+        if !prefix.status.success() {
+            return Err("Not a git repository".to_string());
+        }
+        let prefix = String::from_utf8_lossy(&prefix.stdout).trim_end_matches(['\r', '\n']).to_string();
+
+        // Without optional locks git doesn't rewrite the index, which the file
+        // watcher would report as another git change
+        let output = Self::run_git_command(
+            &["--no-optional-locks", "status", "--porcelain", "-z", "--ignored"],
+            &self.repo_path,
+        )?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(format!("Failed to get git status: {}", stderr));
+        }
+        Ok(GitStatus::parse(&self.repo_path, &prefix, &output.stdout))
+    }
+
     fn run_git_command(args: &[&str], repo_path: &PathBuf) -> Result<std::process::Output, String> {
         Command::new("git")
             .args(args)
@@ -0,0 +1,169 @@
+use crossbeam_channel::{Receiver, TryRecvError};
+use eframe::egui;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use crate::core::git_manager::GitManager;
+
+/// The state of a file in git, ordered from least to most important so a
+/// folder can show the most important state of its contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum GitFileStatus {
+    Ignored,
+    Untracked,
+    Added,
+    Modified,
+    Conflicted,
+}
+
+impl GitFileStatus {
+    /// Reads the two letter `XY` code of a `git status --porcelain` entry.
+    /// Deleted and type-changed files count as modified.
+    fn from_porcelain(index: u8, worktree: u8) -> Self {
+        match (index, worktree) {
+            (b'?', b'?') => Self::Untracked,
+            (b'!', b'!') => Self::Ignored,
+            (b'D', b'D') | (b'A', b'A') | (b'U', _) | (_, b'U') => Self::Conflicted,
+            (b'A', _) | (b'R', _) | (b'C', _) => Self::Added,
+            _ => Self::Modified,
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::Ignored => "Ignored",
+            Self::Untracked => "Untracked",
+            Self::Added => "Added",
+            Self::Modified => "Modified",
+            Self::Conflicted => "Conflicted",
+        }
+    }
+}
+
+/// Git status of the files of a project, by absolute path.
+#[derive(Debug, Default)]
+pub struct GitStatus {
+    root: PathBuf,
+    /// What `git status` reported. Untracked and ignored folders are listed
+    /// as a whole, without their contents.
+    files: HashMap<PathBuf, GitFileStatus>,
+    /// For each folder with reported files below it, the most important
+    /// state among them. Ignored files don't count.
+    folders: HashMap<PathBuf, GitFileStatus>,
+}
+
+impl GitStatus {
+    /// Reads the output of `git status --porcelain -z`, run in `project_path`.
+    /// `prefix` is where the project is in the repository, as printed by
+    /// `git rev-parse --show-prefix`, since porcelain paths are relative to
+    /// the top of the repository.
+    pub fn parse(project_path: &Path, prefix: &str, output: &[u8]) -> Self {
+        let mut status = Self {
+            root: project_path.to_path_buf(),
+            ..Self::default()
+        };
+        let mut entries = output.split(|&byte| byte == 0);
+        while let Some(entry) = entries.next() {
+            if entry.len() < 4 {
+                continue;
+            }
+            let (index, worktree) = (entry[0], entry[1]);
+            // Renames and copies are followed by the original path
+            if matches!(index, b'R' | b'C') {
+                entries.next();
+            }
+            let path = String::from_utf8_lossy(&entry[3..]);
+            let Some(relative) = path.strip_prefix(prefix).map(|relative| relative.trim_end_matches('/')) else {
+                continue;
+            };
+            if relative.is_empty() {
+                continue;
+            }
+            status.insert(project_path.join(relative), GitFileStatus::from_porcelain(index, worktree));
+        }
+        status
+    }
+
+    fn insert(&mut self, path: PathBuf, file_status: GitFileStatus) {
+        if file_status != GitFileStatus::Ignored {
+            for folder in path.ancestors().skip(1).take_while(|folder| folder.starts_with(&self.root)) {
+                let folder_status = self.folders.entry(folder.to_path_buf()).or_insert(file_status);
+                *folder_status = (*folder_status).max(file_status);
+            }
+        }
+        self.files.insert(path, file_status);
+    }
+
+    /// The state of a file, or for a folder the most important state of
+    /// what is in it. `None` for unchanged files.
+    pub fn get(&self, path: &Path) -> Option<GitFileStatus> {
+        if let Some(status) = self.files.get(path).or_else(|| self.folders.get(path)) {
+            return Some(*status);
+        }
+        // Everything in an untracked or ignored folder is untracked or ignored too
+        path.ancestors()
+            .skip(1)
+            .take_while(|folder| folder.starts_with(&self.root))
+            .find_map(|folder| self.files.get(folder).copied())
+    }
+}
+
+/// Keeps the git status of the open project up to date. `git status` runs on
+/// a background thread so large repositories don't stall the UI, and
+/// refreshes asked for while it runs are folded into one.
+#[derive(Default)]
+pub struct GitStatusTracker {
+    project_path: Option<PathBuf>,
+    status: GitStatus,
+    running: Option<Receiver<GitStatus>>,
+    /// A refresh was asked for and hasn't started yet.
+    stale: bool,
+}
+
+impl GitStatusTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn status(&self) -> &GitStatus {
+        &self.status
+    }
+
+    /// Reads the status again on the next poll, e.g. after files changed.
+    pub fn refresh(&mut self) {
+        self.stale = true;
+    }
+
+    /// Picks up a finished refresh and starts the next one if needed. Starts
+    /// over when another project was opened.
+    pub fn poll(&mut self, ctx: &egui::Context, project_path: Option<&Path>) {
+        if self.project_path.as_deref() != project_path {
+            self.project_path = project_path.map(Path::to_path_buf);
+            self.status = GitStatus::default();
+            // The result for the old project is dropped
+            self.running = None;
+            self.stale = true;
+        }
+        if let Some(receiver) = &self.running {
+            match receiver.try_recv() {
+                Ok(status) => self.status = status,
+                Err(TryRecvError::Empty) => return,
+                Err(TryRecvError::Disconnected) => {}
+            }
+            self.running = None;
+        }
+        let Some(project_path) = self.project_path.clone().filter(|_| self.stale) else {
+            return;
+        };
+        self.stale = false;
+        let (sender, receiver) = crossbeam_channel::bounded(1);
+        let ctx = ctx.clone();
+        std::thread::spawn(move || {
+            // Projects outside a repository have no status
+            let status = GitManager::new(project_path).status().unwrap_or_default();
+            if sender.send(status).is_ok() {
+                ctx.request_repaint();
+            }
+        });
+        self.running = Some(receiver);
+    }
+}
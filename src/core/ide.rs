@@ -1,4 +1,5 @@
 use eframe::egui::{self, Rect, Stroke, Color32, Painter, Vec2, TextEdit, ScrollArea};
+use eframe::egui::text::{LayoutJob, TextFormat};
 use tokio::task::LocalSet;
 use crate::components::{
     file_modal::FileModal,
@@ -8,15 +9,20 @@ use crate::components::{
     settings_modal::SettingsModal,
     ai_assistant::AIAssistant,
     git_modal::GitModal,
+    search_panel::{PanelDock, SearchPanel, SearchPanelAction},
 };
 use crate::core::app_state::AppState;
+use crate::core::line_commands::LineCommand;
 use tokio::sync::oneshot;
 use tokio::runtime::Runtime;
 use std::sync::{Arc, Mutex};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::fs;
+use super::file_finder::{FileFinder, FileMatch};
+use super::file_watcher::FileWatcher;
 use super::git_manager::GitManager;
-use super::search::{show_search_modal, SearchResult};
+use super::find::FindOptions;
+use super::search::{open_search_result, rerun_search, show_search_modal, ProjectReplace, ProjectSearch, SearchResult};
 use crate::plugin_manager::PluginManager;
 
 pub struct IDE {
@@ -35,16 +41,25 @@ pub struct IDE {
     pub ai_assistant: AIAssistant,
     pub show_file_search_modal: bool,
     pub file_search_query: String,
-    pub file_search_results: Vec<String>,
+    pub file_search_results: Vec<FileMatch>,
+    pub file_search_selected: usize,
+    pub file_finder: FileFinder,
     pub show_current_file_search_modal: bool,
     pub show_project_search_modal: bool,
     pub search_query: String,
     pub search_results: Vec<SearchResult>,
+    pub search_options: FindOptions,
+    pub search_panel: SearchPanel,
     pub search_highlight_text: Option<String>,
     pub search_focus_requested: bool,
+    pub project_replace: ProjectReplace,
+    pub project_search: ProjectSearch,
     pub ai_model: String,
     pub git_modal: GitModal,
     pub plugin_manager: Arc<Mutex<PluginManager>>,
+    file_watcher: Option<FileWatcher>,
+    /// Project the watcher was set up for, even if that failed.
+    watched_project: Option<PathBuf>,
     local_pool: LocalSet,
 }
 
@@ -82,15 +97,23 @@ impl IDE {
             show_file_search_modal: false,
             file_search_query: String::new(),
             file_search_results: Vec::new(),
+            file_search_selected: 0,
+            file_finder: FileFinder::new(),
             show_current_file_search_modal: false,
             show_project_search_modal: false,
             search_query: String::new(),
             search_results: Vec::new(),
+            search_options: FindOptions::default(),
+            search_panel: SearchPanel::new(),
             search_highlight_text: None,
             search_focus_requested: false,
+            project_replace: ProjectReplace::default(),
+            project_search: ProjectSearch::new(),
             ai_model: state.ai_model.clone(),
             git_modal: GitModal::new(tokio_runtime.clone()),
             plugin_manager: plugin_manager_arc.clone(),
+            file_watcher: None,
+            watched_project: None,
             local_pool: LocalSet::new(),
         };
 
@@ -158,25 +181,33 @@ impl IDE {
             }
             if i.key_pressed(egui::Key::S) && i.modifiers.ctrl {
                 self.file_modal.save_current_file(&mut self.code_editor, &mut |msg| self.console_panel.log(msg));
+                if let Some(file_path) = self.code_editor.get_active_buffer().and_then(|buffer| buffer.file_path.as_deref()) {
+                    self.project_search.file_changed(Path::new(file_path));
+                }
             }
             if i.key_pressed(egui::Key::P) && i.modifiers.ctrl {
                 self.show_file_search_modal = true;
+                if let Some(root) = &self.file_modal.project_path {
+                    self.file_finder.refresh(root, self.file_modal.project_files.files());
+                }
+                self.file_search_results = self.file_finder.search(&self.file_search_query);
+                self.file_search_selected = 0;
             }
             if i.key_pressed(egui::Key::F) && i.modifiers.ctrl && !i.modifiers.shift {
-                if self.show_current_file_search_modal {
-                    self.show_current_file_search_modal = false;
-                } else if self.code_editor.get_active_buffer().is_some() {
-                    self.show_current_file_search_modal = true;
-                    self.search_query = String::new();
-                    self.search_results = Vec::new();
-                    self.search_focus_requested = true;
-                }
+                self.code_editor.open_find_bar(false);
             }
-            if i.key_pressed(egui::Key::F) && i.modifiers.ctrl && i.modifiers.shift {
-                if self.show_project_search_modal {
+            if i.key_pressed(egui::Key::H) && i.modifiers.ctrl && !i.modifiers.shift {
+                self.code_editor.open_find_bar(true);
+            }
+            let project_search = i.key_pressed(egui::Key::F) && i.modifiers.ctrl && i.modifiers.shift;
+            let project_replace = i.key_pressed(egui::Key::H) && i.modifiers.ctrl && i.modifiers.shift;
+            if project_search || project_replace {
+                if self.show_project_search_modal && self.project_replace.enabled == project_replace {
                     self.show_project_search_modal = false;
                 } else {
                     self.show_project_search_modal = true;
+                    self.project_replace.enabled = project_replace;
+                    self.project_replace.cancel();
                     self.search_query = String::new();
                     self.search_results = Vec::new();
                     self.search_focus_requested = true;
@@ -189,10 +220,43 @@ impl IDE {
                 }
             }
             if i.key_pressed(egui::Key::Escape) {
+                self.show_file_search_modal = false;
                 self.show_current_file_search_modal = false;
                 self.show_project_search_modal = false;
             }
         });
+
+        // Taken before the editor sees them, where some of these keys mean
+        // something else, e.g. Ctrl+D adds the next occurrence
+        if self.code_editor.has_focus(ctx) {
+            let command = ctx.input_mut(|i| {
+                let command_shift = egui::Modifiers::COMMAND | egui::Modifiers::SHIFT;
+                let alt_shift = egui::Modifiers::ALT | egui::Modifiers::SHIFT;
+                // Shift+/ is reported as `?` on some layouts
+                if i.consume_key(command_shift, egui::Key::Slash) || i.consume_key(command_shift, egui::Key::Questionmark) {
+                    Some(LineCommand::ToggleBlockComment)
+                } else if i.consume_key(egui::Modifiers::COMMAND, egui::Key::Slash) {
+                    Some(LineCommand::ToggleLineComment)
+                } else if i.consume_key(command_shift, egui::Key::D) {
+                    Some(LineCommand::DuplicateLines)
+                } else if i.consume_key(alt_shift, egui::Key::ArrowUp) {
+                    Some(LineCommand::MoveLinesUp)
+                } else if i.consume_key(alt_shift, egui::Key::ArrowDown) {
+                    Some(LineCommand::MoveLinesDown)
+                } else if i.consume_key(command_shift, egui::Key::K) {
+                    Some(LineCommand::DeleteLines)
+                } else if i.consume_key(command_shift, egui::Key::J) {
+                    Some(LineCommand::JoinLines)
+                } else if i.consume_key(egui::Modifiers::NONE, egui::Key::F9) {
+                    Some(LineCommand::SortLines)
+                } else {
+                    None
+                }
+            });
+            if let Some(command) = command {
+                self.code_editor.run_line_command(command);
+            }
+        }
     }
 
     fn custom_title_bar(&mut self, ui: &mut egui::Ui) {
@@ -290,30 +354,118 @@ impl IDE {
                 .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
                 .show(ctx, |ui| {
                     ui.vertical(|ui| {
-                        let text_edit = TextEdit::singleline(&mut self.file_search_query).hint_text("Type to search...");
-
-                        let response = ui.add(text_edit);
-                        if response.gained_focus() {
-                            response.request_focus();
+                        // Taken before the text field sees them, where they would move the cursor
+                        let (up, down, enter) = ui.input_mut(|i| (
+                            i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowUp),
+                            i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowDown),
+                            i.consume_key(egui::Modifiers::NONE, egui::Key::Enter),
+                        ));
+                        let result_count = self.file_search_results.len();
+                        if up {
+                            self.file_search_selected = self.file_search_selected.saturating_sub(1);
+                        }
+                        if down && self.file_search_selected + 1 < result_count {
+                            self.file_search_selected += 1;
                         }
 
-                        if !self.file_search_query.is_empty() {
-                            self.file_search_results = self.file_modal.search_files(&self.file_search_query);
+                        let text_edit = TextEdit::singleline(&mut self.file_search_query)
+                            .hint_text("Type to search...")
+                            .desired_width(480.0);
+                        let response = ui.add(text_edit);
+                        response.request_focus();
+                        // The list is still being read when the finder opens early on
+                        let listed = self.file_modal.project_path.as_deref().is_some_and(|root| {
+                            self.file_finder.refresh(root, self.file_modal.project_files.files())
+                        });
+                        if response.changed() || listed {
+                            self.file_search_results = self.file_finder.search(&self.file_search_query);
+                            self.file_search_selected = 0;
                         }
 
-                        ScrollArea::vertical().show(ui, |ui| {
-                            for result in &self.file_search_results {
-                                if ui.button(result).clicked() {
-                                    self.file_modal.open_file(result, &mut self.code_editor);
-                                    self.show_file_search_modal = false;
+                        let mut chosen = enter.then_some(self.file_search_selected);
+                        ScrollArea::vertical().max_height(400.0).show(ui, |ui| {
+                            for (index, result) in self.file_search_results.iter().enumerate() {
+                                let selected = index == self.file_search_selected;
+                                let response = ui.selectable_label(selected, Self::file_match_label(ui, result));
+                                if selected && (up || down) {
+                                    response.scroll_to_me(None);
+                                }
+                                if response.clicked() {
+                                    chosen = Some(index);
                                 }
                             }
                         });
+
+                        if let Some(result) = chosen.and_then(|index| self.file_search_results.get(index)) {
+                            self.file_modal.open_file(&result.path.to_string_lossy(), &mut self.code_editor);
+                            self.show_file_search_modal = false;
+                        }
                     });
                 });
         }
     }
 
+    /// The relative path of a finder result with the matched characters highlighted.
+    fn file_match_label(ui: &egui::Ui, result: &FileMatch) -> LayoutJob {
+        let font_id = egui::TextStyle::Button.resolve(ui.style());
+        let normal = TextFormat { font_id: font_id.clone(), color: ui.visuals().text_color(), ..TextFormat::default() };
+        let matched = TextFormat { font_id, color: ui.visuals().strong_text_color(), underline: Stroke::new(1.0, ui.visuals().strong_text_color()), ..TextFormat::default() };
+        let mut job = LayoutJob::default();
+        let mut matched_chars = result.matched_chars.iter().peekable();
+        for (index, c) in result.relative_path.chars().enumerate() {
+            let is_match = matched_chars.next_if_eq(&&index).is_some();
+            job.append(c.encode_utf8(&mut [0; 4]), 0.0, if is_match { matched.clone() } else { normal.clone() });
+        }
+        job
+    }
+
+    /// Applies changes made to project files outside the IDE: drops stale cache
+    /// entries, which also refreshes the project tree, updates the search index
+    /// and reloads or flags open buffers.
+    fn sync_with_disk(&mut self, ctx: &egui::Context) {
+        if self.watched_project != self.file_modal.project_path {
+            self.watched_project = self.file_modal.project_path.clone();
+            self.file_watcher = None;
+            if let Some(root) = &self.watched_project {
+                match FileWatcher::new(root, ctx.clone()) {
+                    Ok(watcher) => self.file_watcher = Some(watcher),
+                    Err(e) => self.console_panel.log(&e),
+                }
+            }
+        }
+
+        let (Some(watcher), Some(fs), Some(root)) = (&self.file_watcher, &self.file_modal.file_system, &self.watched_project) else {
+            return;
+        };
+        let changes = watcher.poll();
+        if changes.is_empty() {
+            return;
+        }
+        self.file_modal.git_status.refresh();
+        if changes.rescan {
+            self.file_modal.project_files.refresh();
+        } else {
+            self.file_modal.project_files.files_changed(&changes.paths);
+        }
+        if changes.git_changed {
+            self.code_editor.git_hunks.invalidate();
+        }
+        let paths = if changes.rescan {
+            fs.clear_cache();
+            self.project_search.refresh_index();
+            vec![root.clone()]
+        } else {
+            for path in &changes.paths {
+                fs.invalidate(path);
+            }
+            self.project_search.files_changed(&changes.paths);
+            changes.paths
+        };
+        let fs = fs.clone();
+        self.code_editor.sync_with_disk(&fs, &paths, &mut |msg| self.console_panel.log(msg));
+        self.file_modal.sync_archive_buffers(&mut self.code_editor, &paths, &mut |msg| self.console_panel.log(msg));
+    }
+
     pub fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         // Run any pending async tasks
         let rt = self.tokio_runtime.clone();
@@ -372,12 +524,40 @@ impl IDE {
                 self.console_panel.set_project_path(new_project_path);
             }
         }
+        self.project_search.open_project(self.file_modal.project_path.as_deref());
+        self.sync_with_disk(ctx);
+        if let Some(file_path) = self.code_editor.get_active_buffer().and_then(|buffer| buffer.file_path.as_deref()) {
+            self.file_finder.note_opened(Path::new(file_path));
+        }
 
         show_search_modal(self, ctx);
         self.console_panel.update(ctx);
         self.file_modal.show(ctx, &mut self.code_editor, &mut |msg| self.console_panel.log(msg), &mut self.ai_assistant);
         self.emulator_panel.update_from_file_modal(self.file_modal.project_path.clone());
 
+        let shortcut_action = self.search_panel.handle_shortcuts(ctx);
+        let panel_action = if !self.search_panel.visible {
+            None
+        } else {
+            match self.search_panel.dock {
+                PanelDock::Bottom => egui::TopBottomPanel::bottom("search_panel")
+                    .resizable(true)
+                    .default_height(220.0)
+                    .show(ctx, |ui| self.search_panel.show(ui))
+                    .inner,
+                PanelDock::Right => egui::SidePanel::right("search_panel")
+                    .resizable(true)
+                    .default_width(420.0)
+                    .show(ctx, |ui| self.search_panel.show(ui))
+                    .inner,
+            }
+        };
+        match shortcut_action.or(panel_action) {
+            Some(SearchPanelAction::Open(result)) => open_search_result(self, &result),
+            Some(SearchPanelAction::Rerun(search)) => rerun_search(self, search),
+            None => {}
+        }
+
         if self.show_ai_panel {
             egui::SidePanel::right("ai_panel")
                 .default_width(350.0)
@@ -389,6 +569,11 @@ impl IDE {
                 });
         }
 
+        let color_scheme = self.settings_modal.current_theme.color_scheme_name();
+        self.code_editor.set_color_scheme(color_scheme);
+        self.console_panel.set_color_scheme(color_scheme);
+        self.code_editor.editor_settings = self.settings_modal.editor_settings;
+
         egui::CentralPanel::default().show(ctx, |ui| {
             self.handle_keyboard_shortcuts(ctx, ui);
             let available_space = ui.available_size();
@@ -425,6 +610,11 @@ impl IDE {
             let _ = app_state.save();
         }
 
+        if self.settings_modal.take_color_schemes_changed() {
+            self.code_editor.reload_color_schemes();
+            self.console_panel.reload_color_schemes();
+        }
+
         if self.show_console_panel {
             egui::TopBottomPanel::bottom("console_panel")
                 .resizable(false)
@@ -0,0 +1,558 @@
+use std::ops::Range;
+use crate::components::code_editor::Buffer;
+use crate::core::edit_history::EditKind;
+use crate::core::selection::{Selection, Selections};
+use crate::core::smart_edit::EditorSettings;
+use crate::core::syntaxes::CommentTokens;
+
+/// Editor commands that act on the lines, or the text, of every selection.
+/// Each one is a single undo step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineCommand {
+    /// Comments the lines out with the line comment marker, or back in if
+    /// they all are. Languages without one use block comments.
+    ToggleLineComment,
+    /// Wraps each selection, or the line of a cursor, in a block comment, or
+    /// unwraps it if it already is one.
+    ToggleBlockComment,
+    /// Copies the lines below themselves and moves the selections onto the copy.
+    DuplicateLines,
+    MoveLinesUp,
+    MoveLinesDown,
+    DeleteLines,
+    /// Joins the selected lines, or the cursor's line with the next, with a
+    /// single space.
+    JoinLines,
+    /// Sorts the selected lines, or the whole buffer when no selection spans
+    /// more than one line.
+    SortLines,
+}
+
+pub fn run(buffer: &mut Buffer, command: LineCommand, comments: &CommentTokens) {
+    if buffer.read_only {
+        return;
+    }
+    let line_comment = comments.line.as_deref();
+    let block_comment = comments.block.as_ref().map(|(open, close)| (open.as_str(), close.as_str()));
+    match command {
+        LineCommand::ToggleLineComment => match (line_comment, block_comment) {
+            (Some(marker), _) => toggle_line_comment(buffer, marker),
+            (None, Some((open, close))) => toggle_block_comment(buffer, open, close),
+            (None, None) => {}
+        },
+        LineCommand::ToggleBlockComment => match (block_comment, line_comment) {
+            (Some((open, close)), _) => toggle_block_comment(buffer, open, close),
+            (None, Some(marker)) => toggle_line_comment(buffer, marker),
+            (None, None) => {}
+        },
+        LineCommand::DuplicateLines => duplicate_lines(buffer),
+        LineCommand::MoveLinesUp => move_lines(buffer, true),
+        LineCommand::MoveLinesDown => move_lines(buffer, false),
+        LineCommand::DeleteLines => delete_lines(buffer),
+        LineCommand::JoinLines => join_lines(buffer),
+        LineCommand::SortLines => sort_lines(buffer),
+    }
+}
+
+/// Tab: indents every selected line by one level when a selection spans
+/// several lines, and otherwise inserts one level at each cursor.
+pub fn indent(buffer: &mut Buffer, settings: &EditorSettings) {
+    let unit = settings.indent_unit();
+    let spans_lines = buffer
+        .selections()
+        .iter()
+        .any(|selection| buffer.char_to_line(selection.start()) != buffer.char_to_line(selection.end()));
+    if !spans_lines {
+        buffer.insert_at_cursor(&unit);
+        return;
+    }
+    if buffer.read_only {
+        return;
+    }
+    let edits: Vec<(Range<usize>, String)> = line_blocks(buffer)
+        .iter()
+        .flat_map(|&(first, last)| first..=last)
+        .filter(|&line| !buffer.line(line).is_empty())
+        .map(|line| {
+            let start = buffer.line_to_char(line);
+            (start..start, unit.clone())
+        })
+        .collect();
+    // Selections starting at a line start take in its new indentation
+    let selections = buffer
+        .selections()
+        .iter()
+        .map(|selection| {
+            if selection.is_empty() {
+                let head = map_position(&edits, selection.head, true);
+                return Selection::new(head, head);
+            }
+            let start = map_position(&edits, selection.start(), false);
+            let end = map_position(&edits, selection.end(), true);
+            if selection.head >= selection.anchor {
+                Selection::new(start, end)
+            } else {
+                Selection::new(end, start)
+            }
+        })
+        .collect();
+    apply(buffer, edits, Some(selections));
+}
+
+/// Shift+Tab: removes one level of indentation, a tab or up to the indent
+/// width in spaces, from every line with a selection or cursor on it.
+pub fn dedent(buffer: &mut Buffer, settings: &EditorSettings) {
+    if buffer.read_only {
+        return;
+    }
+    let edits: Vec<(Range<usize>, String)> = line_blocks(buffer)
+        .iter()
+        .flat_map(|&(first, last)| first..=last)
+        .filter_map(|line| {
+            let text = buffer.line(line);
+            let removed = if text.starts_with('\t') {
+                1
+            } else {
+                text.chars().take(settings.indent_width.max(1)).take_while(|&c| c == ' ').count()
+            };
+            let start = buffer.line_to_char(line);
+            (removed > 0).then(|| (start..start + removed, String::new()))
+        })
+        .collect();
+    let selections = buffer
+        .selections()
+        .iter()
+        .map(|selection| {
+            Selection::new(map_position(&edits, selection.anchor, true), map_position(&edits, selection.head, true))
+        })
+        .collect();
+    apply(buffer, edits, Some(selections));
+}
+
+/// The lines the selections are on, as `(first, last)` pairs in document
+/// order. Blocks that overlap or touch are merged, and a selection ending at
+/// the start of a line doesn't include that line.
+fn line_blocks(buffer: &Buffer) -> Vec<(usize, usize)> {
+    let mut blocks: Vec<(usize, usize)> = Vec::new();
+    for selection in buffer.selections().iter() {
+        let first = buffer.char_to_line(selection.start());
+        let mut last = buffer.char_to_line(selection.end());
+        if last > first && buffer.line_to_char(last) == selection.end() {
+            last -= 1;
+        }
+        match blocks.last_mut() {
+            Some(block) if first <= block.1 + 1 => block.1 = block.1.max(last),
+            _ => blocks.push((first, last)),
+        }
+    }
+    blocks
+}
+
+/// Index of the block in `blocks` that `selection` belongs to.
+fn block_of(buffer: &Buffer, blocks: &[(usize, usize)], selection: &Selection) -> usize {
+    let line = buffer.char_to_line(selection.start());
+    blocks.iter().position(|&(_, last)| line <= last).unwrap_or(blocks.len() - 1)
+}
+
+/// End of `line`, before its line ending.
+fn line_end(buffer: &Buffer, line: usize) -> usize {
+    buffer.char_index(line, usize::MAX)
+}
+
+fn indent_len(text: &str) -> usize {
+    text.chars().take_while(|c| c.is_whitespace()).count()
+}
+
+fn char_len(text: &str) -> usize {
+    text.chars().count()
+}
+
+/// Where `position` ends up after `edits`, which are sorted and don't
+/// overlap. A position inside a replaced range moves to its start, and one
+/// right at an insertion stays in front of it unless `after_insertions`.
+fn map_position(edits: &[(Range<usize>, String)], position: usize, after_insertions: bool) -> usize {
+    let mut shift = 0isize;
+    for (range, text) in edits {
+        let passed = range.end < position
+            || (range.end == position && (!range.is_empty() || after_insertions));
+        if passed {
+            shift += char_len(text) as isize - range.len() as isize;
+        } else if range.start < position {
+            return range.start.saturating_add_signed(shift);
+        } else {
+            break;
+        }
+    }
+    position.saturating_add_signed(shift)
+}
+
+/// Applies `edits` as one undo step and selects `selections`, given one per
+/// old selection. Without them the old selections move along with the text.
+fn apply(buffer: &mut Buffer, edits: Vec<(Range<usize>, String)>, selections: Option<Vec<Selection>>) {
+    if edits.is_empty() {
+        return;
+    }
+    let primary = buffer.selections().primary_index();
+    buffer.replace_ranges(edits, EditKind::Replace);
+    if let Some(selections) = selections {
+        buffer.set_selections(Selections::new(selections, primary));
+    }
+}
+
+/// Comments out every non-blank line at the indentation of the least indented
+/// one in its block, unless all of them are comments already, in which case
+/// the markers are removed instead.
+fn toggle_line_comment(buffer: &mut Buffer, marker: &str) {
+    let blocks = line_blocks(buffer);
+    let lines: Vec<usize> = blocks
+        .iter()
+        .flat_map(|&(first, last)| first..=last)
+        .filter(|&line| !buffer.line(line).trim().is_empty())
+        .collect();
+    if lines.is_empty() {
+        return;
+    }
+
+    let mut edits = Vec::new();
+    if lines.iter().all(|&line| buffer.line(line).trim_start().starts_with(marker)) {
+        for &line in &lines {
+            let text = buffer.line(line);
+            let after_marker = &text.trim_start()[marker.len()..];
+            let start = buffer.line_to_char(line) + indent_len(&text);
+            let end = start + char_len(marker) + usize::from(after_marker.starts_with(' '));
+            edits.push((start..end, String::new()));
+        }
+    } else {
+        let comment = format!("{} ", marker);
+        for &(first, last) in &blocks {
+            let block_lines: Vec<usize> = lines.iter().copied().filter(|line| (first..=last).contains(line)).collect();
+            let Some(column) = block_lines.iter().map(|&line| indent_len(&buffer.line(line))).min() else {
+                continue;
+            };
+            for line in block_lines {
+                let position = buffer.line_to_char(line) + column;
+                edits.push((position..position, comment.clone()));
+            }
+        }
+    }
+    apply(buffer, edits, None);
+}
+
+/// Wraps every selection in `open` and `close`, or unwraps it when it already
+/// is a block comment. A cursor stands for the text of its line.
+fn toggle_block_comment(buffer: &mut Buffer, open: &str, close: &str) {
+    let mut edits: Vec<(Range<usize>, String)> = Vec::new();
+    let mut covered_until = 0;
+    for selection in buffer.selections().iter() {
+        let range = if selection.is_empty() {
+            let line = buffer.char_to_line(selection.head);
+            let text = buffer.line(line);
+            let start = buffer.line_to_char(line) + indent_len(&text);
+            start..start + char_len(text.trim())
+        } else {
+            selection.range()
+        };
+        // Two cursors on the same line would wrap it twice
+        if !edits.is_empty() && range.start < covered_until {
+            continue;
+        }
+        covered_until = range.end.max(range.start + 1);
+
+        let text = buffer.slice(range.clone());
+        let trimmed = text.trim();
+        let is_comment =
+            trimmed.len() >= open.len() + close.len() && trimmed.starts_with(open) && trimmed.ends_with(close);
+        // Markers right around the selection, maybe padded with a space
+        let padded_open = format!("{} ", open);
+        let padded_close = format!(" {}", close);
+        let surrounding = [(open, close), (padded_open.as_str(), padded_close.as_str())]
+            .into_iter()
+            .map(|(open, close)| (char_len(open), char_len(close)))
+            .find(|&(open_len, close_len)| {
+                range.start >= open_len
+                    && buffer.slice(range.start - open_len..range.start).trim_end() == open
+                    && buffer.slice(range.end..range.end + close_len).trim_start() == close
+            });
+        if is_comment {
+            let body = &trimmed[open.len()..trimmed.len() - close.len()];
+            let open_start = range.start + indent_len(&text);
+            let open_end = open_start + char_len(open) + usize::from(body.starts_with(' '));
+            let close_end = range.start + char_len(text.trim_end());
+            let close_start = close_end - char_len(close) - usize::from(body.len() > 1 && body.ends_with(' '));
+            edits.push((open_start..open_end, String::new()));
+            edits.push((close_start..close_end, String::new()));
+        } else if let Some((open_len, close_len)) = surrounding {
+            edits.push((range.start - open_len..range.start, String::new()));
+            edits.push((range.end..range.end + close_len, String::new()));
+        } else {
+            edits.push((range.start..range.start, format!("{} ", open)));
+            edits.push((range.end..range.end, format!(" {}", close)));
+        }
+    }
+
+    // The selections keep covering the same text, inside the markers
+    let selections = buffer
+        .selections()
+        .iter()
+        .map(|selection| {
+            if selection.is_empty() {
+                let head = map_position(&edits, selection.head, true);
+                return Selection::new(head, head);
+            }
+            let start = map_position(&edits, selection.start(), true);
+            let end = map_position(&edits, selection.end(), false);
+            if selection.head >= selection.anchor {
+                Selection::new(start, end)
+            } else {
+                Selection::new(end, start)
+            }
+        })
+        .collect();
+    apply(buffer, edits, Some(selections));
+}
+
+fn duplicate_lines(buffer: &mut Buffer) {
+    let blocks = line_blocks(buffer);
+    let newline = buffer.line_ending();
+    let mut edits = Vec::with_capacity(blocks.len());
+    // What the selections in each block move by, including the copies above them
+    let mut offsets = Vec::with_capacity(blocks.len());
+    let mut offset = 0;
+    for &(first, last) in &blocks {
+        let end = line_end(buffer, last);
+        let copy = format!("{}{}", newline, buffer.slice(buffer.line_to_char(first)..end));
+        offset += char_len(&copy);
+        offsets.push(offset);
+        edits.push((end..end, copy));
+    }
+    let selections = buffer
+        .selections()
+        .iter()
+        .map(|selection| {
+            let offset = offsets[block_of(buffer, &blocks, selection)];
+            Selection::new(selection.anchor + offset, selection.head + offset)
+        })
+        .collect();
+    apply(buffer, edits, Some(selections));
+}
+
+/// Swaps every block with the line above or below it. Nothing moves if one of
+/// the blocks is already at the edge of the buffer.
+fn move_lines(buffer: &mut Buffer, up: bool) {
+    let blocks = line_blocks(buffer);
+    let last_line = buffer.line_count().saturating_sub(1);
+    let at_edge = if up {
+        blocks.first().is_some_and(|&(first, _)| first == 0)
+    } else {
+        blocks.last().is_some_and(|&(_, last)| last >= last_line)
+    };
+    if at_edge {
+        return;
+    }
+
+    let newline = buffer.line_ending();
+    let mut edits = Vec::with_capacity(blocks.len());
+    let mut shifts = Vec::with_capacity(blocks.len());
+    for &(first, last) in &blocks {
+        let lines = buffer.slice(buffer.line_to_char(first)..line_end(buffer, last));
+        if up {
+            let above = buffer.line(first - 1);
+            shifts.push(-((char_len(&above) + char_len(newline)) as isize));
+            let range = buffer.line_to_char(first - 1)..line_end(buffer, last);
+            edits.push((range, format!("{}{}{}", lines, newline, above)));
+        } else {
+            let below = buffer.line(last + 1);
+            shifts.push((char_len(&below) + char_len(newline)) as isize);
+            let range = buffer.line_to_char(first)..line_end(buffer, last + 1);
+            edits.push((range, format!("{}{}{}", below, newline, lines)));
+        }
+    }
+    let selections = buffer
+        .selections()
+        .iter()
+        .map(|selection| {
+            let shift = shifts[block_of(buffer, &blocks, selection)];
+            Selection::new(selection.anchor.saturating_add_signed(shift), selection.head.saturating_add_signed(shift))
+        })
+        .collect();
+    apply(buffer, edits, Some(selections));
+}
+
+fn delete_lines(buffer: &mut Buffer) {
+    let blocks = line_blocks(buffer);
+    let edits = blocks
+        .iter()
+        .map(|&(first, last)| {
+            let range = if last + 1 < buffer.line_count() {
+                buffer.line_to_char(first)..buffer.line_to_char(last + 1)
+            } else if first > 0 {
+                // The last line takes the line ending before it along
+                line_end(buffer, first - 1)..buffer.len_chars()
+            } else {
+                0..buffer.len_chars()
+            };
+            (range, String::new())
+        })
+        .collect();
+    apply(buffer, edits, None);
+}
+
+fn join_lines(buffer: &mut Buffer) {
+    let blocks = line_blocks(buffer);
+    let mut edits = Vec::new();
+    for &(first, last) in &blocks {
+        let last = if first == last { first + 1 } else { last };
+        for line in first..last.min(buffer.line_count().saturating_sub(1)) {
+            let text = buffer.line(line);
+            let next = buffer.line(line + 1);
+            let start = buffer.line_to_char(line) + char_len(text.trim_end());
+            let end = buffer.line_to_char(line + 1) + indent_len(&next);
+            let separator = if text.trim().is_empty() || next.trim().is_empty() { "" } else { " " };
+            edits.push((start..end, separator.to_string()));
+        }
+    }
+    apply(buffer, edits, None);
+}
+
+fn sort_lines(buffer: &mut Buffer) {
+    let mut blocks = line_blocks(buffer);
+    if blocks.iter().all(|&(first, last)| first == last) {
+        // A final line ending doesn't count as an empty line to sort
+        let mut last = buffer.line_count().saturating_sub(1);
+        if last > 0 && buffer.line(last).is_empty() {
+            last -= 1;
+        }
+        blocks = vec![(0, last)];
+    }
+
+    let newline = buffer.line_ending();
+    let mut edits = Vec::new();
+    for &(first, last) in &blocks {
+        let lines: Vec<String> = (first..=last).map(|line| buffer.line(line).into_owned()).collect();
+        let mut sorted = lines.clone();
+        sorted.sort();
+        if sorted != lines {
+            edits.push((buffer.line_to_char(first)..line_end(buffer, last), sorted.join(newline)));
+        }
+    }
+    // Sorting keeps the length of every block, so the selections still fit
+    let selections = buffer.selections().iter().copied().collect();
+    apply(buffer, edits, Some(selections));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn editing(text: &str, selections: &[(usize, usize)]) -> Buffer {
+        let mut buffer = Buffer::from_file(text.to_string(), "Main.kt".to_string(), "Kotlin".to_string());
+        let selections = selections.iter().map(|&(anchor, head)| Selection::new(anchor, head)).collect();
+        buffer.set_selections(Selections::new(selections, 0));
+        buffer
+    }
+
+    fn selections(buffer: &Buffer) -> Vec<(usize, usize)> {
+        buffer.selections().iter().map(|selection| (selection.anchor, selection.head)).collect()
+    }
+
+    fn slash_comments() -> CommentTokens {
+        CommentTokens { line: Some("//".to_string()), block: Some(("/*".to_string(), "*/".to_string())) }
+    }
+
+    #[test]
+    fn positions_move_past_earlier_edits_and_into_replaced_ranges() {
+        let edits = vec![(2..2, "ab".to_string()), (5..8, "x".to_string())];
+        assert_eq!(map_position(&edits, 1, false), 1);
+        // Right at an insertion
+        assert_eq!(map_position(&edits, 2, false), 2);
+        assert_eq!(map_position(&edits, 2, true), 4);
+        // Inside a replaced range, and right after it
+        assert_eq!(map_position(&edits, 6, false), 7);
+        assert_eq!(map_position(&edits, 8, false), 8);
+        assert_eq!(map_position(&edits, 10, false), 10);
+    }
+
+    #[test]
+    fn line_comments_toggle_at_the_least_indentation_of_each_block() {
+        let text = "fun main() {\n    if (x) {\n        run()\n    }\n}";
+        // Cursors on touching lines make one block
+        let mut buffer = editing(text, &[(17, 17), (30, 30)]);
+        run(&mut buffer, LineCommand::ToggleLineComment, &slash_comments());
+        assert_eq!(buffer.text(), "fun main() {\n    // if (x) {\n    //     run()\n    }\n}");
+
+        let mut buffer = editing(text, &[(0, 0), (30, 30)]);
+        run(&mut buffer, LineCommand::ToggleLineComment, &slash_comments());
+        assert_eq!(buffer.text(), "// fun main() {\n    if (x) {\n        // run()\n    }\n}");
+
+        run(&mut buffer, LineCommand::ToggleLineComment, &slash_comments());
+        assert_eq!(buffer.text(), text);
+        buffer.undo();
+        buffer.undo();
+        assert_eq!(buffer.text(), text);
+    }
+
+    #[test]
+    fn block_comments_strip_their_markers_and_keep_the_selection_on_the_text() {
+        let mut buffer = editing("val x = 1 + 2", &[(8, 13)]);
+        run(&mut buffer, LineCommand::ToggleBlockComment, &slash_comments());
+        assert_eq!(buffer.text(), "val x = /* 1 + 2 */");
+        assert_eq!(selections(&buffer), [(11, 16)]);
+
+        // The markers around the selection are found and taken out again
+        run(&mut buffer, LineCommand::ToggleBlockComment, &slash_comments());
+        assert_eq!(buffer.text(), "val x = 1 + 2");
+        assert_eq!(selections(&buffer), [(8, 13)]);
+
+        // A selection that is a whole comment loses its markers
+        let mut buffer = editing("a /*b*/ c", &[(2, 7)]);
+        run(&mut buffer, LineCommand::ToggleBlockComment, &slash_comments());
+        assert_eq!(buffer.text(), "a b c");
+    }
+
+    #[test]
+    fn duplicated_lines_take_the_selections_along_in_crlf_files() {
+        let mut buffer = editing("one\r\ntwo\r\nthree", &[(0, 2), (11, 11)]);
+        run(&mut buffer, LineCommand::DuplicateLines, &slash_comments());
+        assert_eq!(buffer.text(), "one\r\none\r\ntwo\r\nthree\r\nthree");
+        // The first copy shifts the second cursor too
+        assert_eq!(selections(&buffer), [(5, 7), (23, 23)]);
+
+        buffer.undo();
+        assert_eq!(buffer.text(), "one\r\ntwo\r\nthree");
+    }
+
+    #[test]
+    fn moved_lines_take_the_selections_along_and_stop_at_the_edges() {
+        let mut buffer = editing("a\nbb\nccc", &[(2, 4)]);
+        run(&mut buffer, LineCommand::MoveLinesUp, &slash_comments());
+        assert_eq!(buffer.text(), "bb\na\nccc");
+        assert_eq!(selections(&buffer), [(0, 2)]);
+        // Already on the first line
+        run(&mut buffer, LineCommand::MoveLinesUp, &slash_comments());
+        assert_eq!(buffer.text(), "bb\na\nccc");
+
+        run(&mut buffer, LineCommand::MoveLinesDown, &slash_comments());
+        run(&mut buffer, LineCommand::MoveLinesDown, &slash_comments());
+        assert_eq!(buffer.text(), "a\nccc\nbb");
+        assert_eq!(selections(&buffer), [(6, 8)]);
+        // Already on the last line
+        run(&mut buffer, LineCommand::MoveLinesDown, &slash_comments());
+        assert_eq!(buffer.text(), "a\nccc\nbb");
+
+        let mut buffer = editing("a\r\nb\r\nc", &[(6, 6)]);
+        run(&mut buffer, LineCommand::MoveLinesUp, &slash_comments());
+        assert_eq!(buffer.text(), "a\r\nc\r\nb");
+        assert_eq!(selections(&buffer), [(3, 3)]);
+        buffer.undo();
+        assert_eq!(buffer.text(), "a\r\nb\r\nc");
+    }
+
+    #[test]
+    fn deleting_the_last_line_takes_the_line_ending_before_it() {
+        let mut buffer = editing("a\nb\nc", &[(0, 0), (4, 4)]);
+        run(&mut buffer, LineCommand::DeleteLines, &slash_comments());
+        assert_eq!(buffer.text(), "b");
+        buffer.undo();
+        assert_eq!(buffer.text(), "a\nb\nc");
+    }
+}
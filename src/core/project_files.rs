@@ -0,0 +1,85 @@
+use crate::core::project_walker::ProjectWalker;
+use crossbeam_channel::{Receiver, TryRecvError};
+use eframe::egui;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// The files of the open project, for filtering the tree and the file finder
+/// without touching the disk on every keystroke. The project is walked on a
+/// background thread, and walked again when the file watcher reports files
+/// that appeared or disappeared. The old list is kept until the new one is
+/// ready.
+#[derive(Default)]
+pub struct ProjectFiles {
+    project_path: Option<PathBuf>,
+    /// Sorted, so lookups can use a binary search.
+    files: Arc<Vec<PathBuf>>,
+    running: Option<Receiver<Vec<PathBuf>>>,
+    /// A walk was asked for and hasn't started yet.
+    stale: bool,
+}
+
+impl ProjectFiles {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The files below the project, as absolute paths.
+    pub fn files(&self) -> &Arc<Vec<PathBuf>> {
+        &self.files
+    }
+
+    /// Walks the project again on the next poll.
+    pub fn refresh(&mut self) {
+        self.stale = true;
+    }
+
+    /// Walks the project again unless every path is a file already listed
+    /// that still exists, i.e. only file contents changed.
+    pub fn files_changed(&mut self, paths: &[PathBuf]) {
+        let listed = |path: &PathBuf| self.files.binary_search(path).is_ok() && path.is_file();
+        if !paths.iter().all(listed) {
+            self.stale = true;
+        }
+    }
+
+    /// Picks up a finished walk and starts the next one if needed. Starts over
+    /// when another project was opened. Returns whether the list changed.
+    pub fn poll(&mut self, ctx: &egui::Context, project_path: Option<&Path>) -> bool {
+        let mut changed = false;
+        if self.project_path.as_deref() != project_path {
+            self.project_path = project_path.map(Path::to_path_buf);
+            self.files = Arc::default();
+            // The result for the old project is dropped
+            self.running = None;
+            self.stale = true;
+            changed = true;
+        }
+        if let Some(receiver) = &self.running {
+            match receiver.try_recv() {
+                Ok(files) => {
+                    changed |= *self.files != files;
+                    self.files = Arc::new(files);
+                }
+                Err(TryRecvError::Empty) => return changed,
+                Err(TryRecvError::Disconnected) => {}
+            }
+            self.running = None;
+        }
+        let Some(project_path) = self.project_path.clone().filter(|_| self.stale) else {
+            return changed;
+        };
+        self.stale = false;
+        let (sender, receiver) = crossbeam_channel::bounded(1);
+        let ctx = ctx.clone();
+        std::thread::spawn(move || {
+            let mut files = ProjectWalker::new(project_path).files();
+            files.sort();
+            if sender.send(files).is_ok() {
+                ctx.request_repaint();
+            }
+        });
+        self.running = Some(receiver);
+        changed
+    }
+}
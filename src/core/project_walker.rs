@@ -0,0 +1,139 @@
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::WalkBuilder;
+use std::path::{Path, PathBuf};
+use crate::core::vfs::Vfs;
+
+/// Per-project ignore file in `.gitignore` syntax, applied on top of the git
+/// rules. Useful for files git tracks but search should not see, e.g. vendored
+/// or generated sources.
+pub const PROJECT_IGNORE_FILE: &str = ".zzzignore";
+
+/// Version control metadata is never part of the project, ignored or not.
+const ALWAYS_SKIPPED: &[&str] = &[".git", ".svn", ".hg"];
+
+/// Walks the files of a project, skipping everything matched by `.gitignore`
+/// files, `.git/info/exclude`, the global git excludes and
+/// [`PROJECT_IGNORE_FILE`]. Hidden files are included unless ignored.
+///
+/// This is the one place that decides which files belong to a project; search,
+/// the file finder and the AI context all go through it.
+pub struct ProjectWalker {
+    root: PathBuf,
+}
+
+impl ProjectWalker {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Calls `visit` for every file, sorted by path within each directory, until
+    /// it returns `false`. Returns `false` if the walk was stopped early.
+    pub fn visit(&self, visit: &mut dyn FnMut(&Path) -> bool) -> bool {
+        for entry in self.builder().build().flatten() {
+            if entry.file_type().is_some_and(|file_type| file_type.is_file()) && !visit(entry.path()) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Like [`ProjectWalker::visit`], but lists directories through `vfs`, so
+    /// projects that are not on disk can be walked too. Off disk only the
+    /// `.gitignore` and [`PROJECT_IGNORE_FILE`] files inside the project apply.
+    pub fn visit_in<V: Vfs>(&self, vfs: &V, visit: &mut dyn FnMut(&Path) -> bool) -> bool {
+        if vfs.is_disk() {
+            return self.visit(visit);
+        }
+        visit_directory(vfs, &self.root, &mut Vec::new(), visit)
+    }
+
+    pub fn files(&self) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        self.visit(&mut |path| {
+            files.push(path.to_path_buf());
+            true
+        });
+        files
+    }
+
+    fn builder(&self) -> WalkBuilder {
+        let mut builder = WalkBuilder::new(&self.root);
+        builder
+            .hidden(false)
+            // Honor .gitignore files even in projects that are not git repositories
+            .require_git(false)
+            .add_custom_ignore_filename(PROJECT_IGNORE_FILE)
+            .sort_by_file_name(|a, b| a.cmp(b))
+            .filter_entry(|entry| {
+                let is_dir = entry.file_type().is_some_and(|file_type| file_type.is_dir());
+                !(is_dir && entry.file_name().to_str().is_some_and(|name| ALWAYS_SKIPPED.contains(&name)))
+            });
+        builder
+    }
+}
+
+/// Walks `directory` the way [`ProjectWalker::visit`] does, with `ignores`
+/// holding the rules of the directories above it, outermost first.
+fn visit_directory<V: Vfs>(
+    vfs: &V,
+    directory: &Path,
+    ignores: &mut Vec<Gitignore>,
+    visit: &mut dyn FnMut(&Path) -> bool,
+) -> bool {
+    let Ok(mut entries) = vfs.read_dir(directory) else {
+        return true;
+    };
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    let rules = ignore_rules(vfs, directory);
+    let has_rules = rules.is_some();
+    ignores.extend(rules);
+
+    let mut finished = true;
+    for entry in entries {
+        if entry.is_dir && ALWAYS_SKIPPED.contains(&entry.name.as_str()) {
+            continue;
+        }
+        let path = directory.join(&entry.name);
+        // The closest rule that matches decides
+        let ignored = ignores
+            .iter()
+            .rev()
+            .map(|rules| rules.matched(&path, entry.is_dir))
+            .find(|matched| !matched.is_none())
+            .is_some_and(|matched| matched.is_ignore());
+        if ignored {
+            continue;
+        }
+        let keep_going = if entry.is_dir {
+            visit_directory(vfs, &path, ignores, visit)
+        } else {
+            visit(&path)
+        };
+        if !keep_going {
+            finished = false;
+            break;
+        }
+    }
+    if has_rules {
+        ignores.pop();
+    }
+    finished
+}
+
+/// The ignore rules of `directory`, with those of [`PROJECT_IGNORE_FILE`]
+/// taking precedence over `.gitignore`.
+fn ignore_rules<V: Vfs>(vfs: &V, directory: &Path) -> Option<Gitignore> {
+    let mut builder = GitignoreBuilder::new(directory);
+    let mut found = false;
+    for name in [".gitignore", PROJECT_IGNORE_FILE] {
+        let Ok(content) = vfs.read_to_string(&directory.join(name)) else {
+            continue;
+        };
+        found = true;
+        for line in content.lines() {
+            // Invalid patterns are skipped, as git does
+            let _ = builder.add_line(None, line);
+        }
+    }
+    found.then(|| builder.build().ok()).flatten()
+}
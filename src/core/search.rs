@@ -1,115 +1,721 @@
+use crate::components::search_panel::SearchHistoryEntry;
+use crate::core::edit_history::EditKind;
 use crate::core::file_system::FileSystem;
-use std::rc::Rc;
-use std::path::Path;
-use egui::{Context, TextEdit, ScrollArea};
+use crate::core::vfs::Vfs;
+use crate::core::find::{FindOptions, FindQuery};
+use crate::core::project_walker::ProjectWalker;
+use crate::core::search_index::ProjectIndex;
+use crate::core::selection::Selection;
+use crossbeam_channel::{Receiver, Sender, TryRecvError};
+use rayon::prelude::*;
+use ropey::Rope;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use egui::{Color32, Context, Stroke, TextEdit, ScrollArea};
+use egui::text::{LayoutJob, TextFormat};
 use super::ide::IDE;
 
 #[derive(Clone)]
 pub struct SearchResult {
     pub line_number: usize,
     pub line_content: String,
+    /// Byte range of the first match in `line_content`.
+    pub match_range: Range<usize>,
     pub file_path: Option<String>,
 }
 
 pub fn perform_current_file_search(ide: &mut IDE) {
+    let Ok(query) = FindQuery::literal(&ide.search_query) else {
+        ide.search_results = Vec::new();
+        return;
+    };
+    ide.code_editor.search_hits.set_query(Some(query.clone()));
     if let Some(buffer) = ide.code_editor.get_active_buffer() {
-        let content = &buffer.content;
-        ide.search_results = content
+        ide.search_results = buffer
             .lines()
             .enumerate()
-            .filter(|(_, line)| line.contains(&ide.search_query))
-            .map(|(line_num, line)| SearchResult {
+            .filter_map(|(line_num, line)| Some((line_num, query.find(&line)?, line)))
+            .map(|(line_num, match_range, line)| SearchResult {
                 line_number: line_num + 1,
                 line_content: line.to_string(),
+                match_range,
                 file_path: buffer.file_path.clone(),
             })
             .collect();
     }
 }
 
-pub fn perform_project_search(ide: &mut IDE) {
-    const MAX_RESULTS: usize = 100;
-    let mut results = Vec::new();
+const SKIPPABLE_EXTENSIONS: &[&str] = &[
+    "png", "jpg", "jpeg", "gif", "svg", "pdf", "zip", "tar", "gz", "class", "jar", "so", "dll", "dylib", "o", "a",
+];
+
+/// Runs project searches on a background thread. A new search starts once the
+/// query has stopped changing for a moment, and cancels the previous one.
+/// Searches only read the files the project's [`ProjectIndex`] names as
+/// candidates, once it has loaded.
+pub struct ProjectSearch {
+    query: String,
+    options: FindOptions,
+    /// When the query last changed, if its search has not started yet.
+    changed_at: Option<Instant>,
+    cancel: Arc<AtomicBool>,
+    receiver: Option<Receiver<Vec<SearchResult>>>,
+    /// Why the last search could not start, e.g. an invalid regex.
+    error: Option<String>,
+    index: Option<ProjectIndex>,
+}
+
+impl ProjectSearch {
+    const DEBOUNCE: Duration = Duration::from_millis(200);
+    /// Keeps a query like `e` from collecting every line of the project.
+    const MIN_QUERY_CHARS: usize = 2;
+
+    pub fn new() -> Self {
+        Self {
+            query: String::new(),
+            options: FindOptions::default(),
+            changed_at: None,
+            cancel: Arc::new(AtomicBool::new(false)),
+            receiver: None,
+            error: None,
+            index: None,
+        }
+    }
+
+    /// Switches the index to the project at `root`, loading it in the background.
+    pub fn open_project(&mut self, root: Option<&Path>) {
+        if self.index.as_ref().map(ProjectIndex::root) == root {
+            return;
+        }
+        if let Some(index) = self.index.take() {
+            index.close();
+        }
+        self.index = root.map(ProjectIndex::open);
+    }
+
+    /// Brings the index up to date with a file that was saved or written.
+    pub fn file_changed(&self, path: &Path) {
+        if let Some(index) = &self.index {
+            index.file_changed(path);
+        }
+    }
 
-    let excluded_dirs = vec![
-        "build", "target", "out", "bin", "node_modules", ".gradle", "gradle", "captures",
-        ".git", ".svn", ".idea", ".vscode", "app/build", "androidTest", "test", "debug",
-        "release", "shared/build", "commonMain", "androidMain", "iosMain", "__MACOSX",
-        ".DS_Store", "*.xcodeproj", "*.iml",
-    ];
+    /// Brings the index up to date with files that changed on disk.
+    pub fn files_changed(&self, paths: &[PathBuf]) {
+        if let Some(index) = &self.index {
+            index.files_changed(paths);
+        }
+    }
 
-    if let Some(fs) = &ide.file_modal.file_system {
-        if let Some(project_path) = &ide.file_modal.project_path {
-            let query = ide.search_query.trim().to_lowercase();
-            if query.len() >= 2 {
-                search_in_directory_with_exclusions(
-                    fs, project_path, &query, &mut results, MAX_RESULTS, &excluded_dirs,
-                );
+    /// Rescans the whole project, e.g. after file system events were lost.
+    pub fn refresh_index(&self) {
+        if let Some(index) = &self.index {
+            index.refresh();
+        }
+    }
+
+    /// The index of the project at `root`, if it is the one that is open.
+    fn index_for(&self, root: &Path) -> Option<ProjectIndex> {
+        self.index.clone().filter(|index| index.root() == root)
+    }
+
+    pub fn is_searching(&self) -> bool {
+        self.changed_at.is_some() || self.receiver.is_some()
+    }
+
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    /// Schedules a search for `query` if it or the options differ from the
+    /// current search.
+    pub fn set_query(&mut self, query: &str, options: FindOptions) {
+        if query != self.query || options != self.options {
+            self.query = query.to_string();
+            self.options = options;
+            self.restart();
+        }
+    }
+
+    /// Schedules the current search to run again, e.g. when it is re-run from
+    /// the search history.
+    pub fn restart(&mut self) {
+        self.cancel();
+        self.changed_at = Some(Instant::now());
+    }
+
+    /// Stops the running search, if any. Results found so far are kept.
+    pub fn cancel(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+        self.receiver = None;
+    }
+
+    /// Starts a pending search once the debounce delay has passed and moves the
+    /// results found since the last call into `results`.
+    pub fn poll(&mut self, fs: Option<&FileSystem>, root: Option<&Path>, results: &mut Vec<SearchResult>) {
+        if let Some(changed_at) = self.changed_at {
+            if changed_at.elapsed() < Self::DEBOUNCE {
+                return;
+            }
+            self.changed_at = None;
+            self.error = None;
+            results.clear();
+            if let (Some(fs), Some(root)) = (fs, root) {
+                self.start(fs.clone(), root.to_path_buf());
             }
         }
+
+        let Some(receiver) = &self.receiver else {
+            return;
+        };
+        loop {
+            match receiver.try_recv() {
+                Ok(batch) => results.extend(batch),
+                Err(TryRecvError::Empty) => return,
+                Err(TryRecvError::Disconnected) => {
+                    self.receiver = None;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn start(&mut self, fs: FileSystem, root: PathBuf) {
+        if self.query.is_empty() {
+            return;
+        }
+        if self.query.chars().count() < Self::MIN_QUERY_CHARS {
+            self.error = Some(format!("Type at least {} characters to search", Self::MIN_QUERY_CHARS));
+            return;
+        }
+        let query = match FindQuery::new(&self.query, self.options) {
+            Ok(query) => query,
+            Err(e) => {
+                self.error = Some(e);
+                return;
+            }
+        };
+        let index = self.index_for(&root);
+        let cancel = Arc::new(AtomicBool::new(false));
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        self.cancel = cancel.clone();
+        self.receiver = Some(receiver);
+        std::thread::spawn(move || search_project(&fs, &root, index.as_ref(), &query, &cancel, sender));
     }
-    ide.search_results = results;
 }
 
-fn search_in_directory_with_exclusions(
-    fs: &Rc<FileSystem>,
-    dir: &Path,
-    query: &str,
-    results: &mut Vec<SearchResult>,
-    max_results: usize,
-    excluded_dirs: &[&str],
+impl Default for ProjectSearch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Collects the files to search first, then searches them in parallel, sending
+/// the results of each file as one batch.
+fn search_project<V: Vfs>(
+    fs: &FileSystem<V>,
+    root: &Path,
+    index: Option<&ProjectIndex>,
+    query: &FindQuery,
+    cancel: &AtomicBool,
+    sender: Sender<Vec<SearchResult>>,
 ) {
-    if results.len() >= max_results {
-        return;
+    let files = candidate_files(fs.vfs(), root, index, query, cancel);
+    files.par_iter().for_each_with(sender, |sender, path| {
+        if cancel.load(Ordering::Relaxed) {
+            return;
+        }
+        let file_results = search_file(fs, path, query);
+        if !file_results.is_empty() {
+            let _ = sender.send(file_results);
+        }
+    });
+}
+
+/// The files that may contain matches of `query`. Without a loaded index
+/// every searchable file is a candidate.
+fn candidate_files<V: Vfs>(
+    vfs: &V,
+    root: &Path,
+    index: Option<&ProjectIndex>,
+    query: &FindQuery,
+    cancel: &AtomicBool,
+) -> Vec<PathBuf> {
+    if let Some(index) = index {
+        // Picks up changes made outside the IDE for the next search
+        index.refresh_if_stale();
     }
+    match index.and_then(|index| index.candidates(query)) {
+        Some(files) => files,
+        None => {
+            let mut files = Vec::new();
+            visit_searchable_files(vfs, root, &mut |path| {
+                files.push(path.to_path_buf());
+                !cancel.load(Ordering::Relaxed)
+            });
+            files
+        }
+    }
+}
 
-    if let Ok(entries) = fs.list_directory(dir) {
-        for entry in entries {
-            if results.len() >= max_results {
-                break;
-            }
+/// The matches of `query` in the file at `path`, one per line. Files that
+/// cannot be read as text have none.
+pub fn search_file<V: Vfs>(fs: &FileSystem<V>, path: &Path, query: &FindQuery) -> Vec<SearchResult> {
+    let Ok(content) = fs.open_file(path) else {
+        return Vec::new();
+    };
+    content
+        .lines()
+        .enumerate()
+        .filter_map(|(line_num, line)| Some((line_num, line, query.find(line)?)))
+        .map(|(line_num, line, match_range)| SearchResult {
+            line_number: line_num + 1,
+            line_content: line.to_string(),
+            match_range,
+            file_path: Some(path.to_string_lossy().into_owned()),
+        })
+        .collect()
+}
+
+/// Feeds the modal's query to the background search and collects its results.
+pub fn perform_project_search(ide: &mut IDE, ctx: &Context) {
+    let search = &mut ide.project_search;
+    search.set_query(ide.search_query.trim(), ide.search_options);
+    search.poll(
+        ide.file_modal.file_system.as_deref(),
+        ide.file_modal.project_path.as_deref(),
+        &mut ide.search_results,
+    );
+    if search.is_searching() {
+        ctx.request_repaint_after(Duration::from_millis(50));
+    }
+}
+
+/// Calls `visit` for every project file below `root` whose content project
+/// search looks at, until it returns `false`.
+pub(crate) fn visit_searchable_files<V: Vfs>(vfs: &V, root: &Path, visit: &mut dyn FnMut(&Path) -> bool) -> bool {
+    ProjectWalker::new(root).visit_in(vfs, &mut |path| {
+        let file_ext = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+        SKIPPABLE_EXTENSIONS.contains(&file_ext) || visit(path)
+    })
+}
+
+/// A single match that "Replace in project" would change.
+#[derive(Clone)]
+pub struct ReplaceHit {
+    pub line_number: usize,
+    /// Byte range of the match in the file content.
+    pub byte_range: Range<usize>,
+    pub replacement: String,
+    /// The line the match starts on.
+    pub line_content: String,
+    /// Byte range of the match within `line_content`, cut off at the line end.
+    line_range: Range<usize>,
+    pub included: bool,
+}
 
-            let path = dir.join(&entry.name);
+impl ReplaceHit {
+    /// The line with the match struck through and the replacement after it.
+    fn preview(&self, ui: &egui::Ui) -> LayoutJob {
+        let font_id = egui::TextStyle::Monospace.resolve(ui.style());
+        let format = |color: Color32| TextFormat { font_id: font_id.clone(), color, ..TextFormat::default() };
+        let removed_color = Color32::from_rgb(220, 80, 80);
+        let inserted_color = Color32::from_rgb(87, 171, 90);
 
-            if entry.is_dir {
-                let dir_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+        let line = &self.line_content;
+        let indent = (line.len() - line.trim_start().len()).min(self.line_range.start);
+        let mut job = LayoutJob::default();
+        job.append(&format!("{}: ", self.line_number), 0.0, format(ui.visuals().weak_text_color()));
+        job.append(&line[indent..self.line_range.start], 0.0, format(ui.visuals().text_color()));
+        job.append(
+            &line[self.line_range.clone()],
+            0.0,
+            TextFormat { strikethrough: Stroke::new(1.0, removed_color), ..format(removed_color) },
+        );
+        job.append(&self.replacement, 0.0, format(inserted_color));
+        job.append(&line[self.line_range.end..], 0.0, format(ui.visuals().text_color()));
+        job
+    }
+}
 
-                if excluded_dirs.iter().any(|&excluded| dir_name == excluded || dir_name.starts_with(excluded) || dir_name.contains(excluded)) {
-                    continue;
+/// The pending replacements in one file.
+#[derive(Clone)]
+pub struct FileReplacement {
+    pub file_path: String,
+    /// Content the hits were computed from. Files that changed since are skipped.
+    original: String,
+    pub hits: Vec<ReplaceHit>,
+}
+
+impl FileReplacement {
+    fn new(file_path: String, content: String, query: &FindQuery, replacement: &str) -> Option<Self> {
+        let mut line_starts = vec![0];
+        line_starts.extend(content.match_indices('\n').map(|(index, _)| index + 1));
+
+        let hits: Vec<ReplaceHit> = query
+            .find_all(&content)
+            .into_iter()
+            .map(|byte_range| {
+                let line = line_starts.partition_point(|start| *start <= byte_range.start) - 1;
+                let line_start = line_starts[line];
+                let line_end = line_starts.get(line + 1).map_or(content.len(), |next| next - 1);
+                let line_content = content[line_start..line_end].trim_end_matches('\r').to_string();
+                // A match may start on the `\r` or `\n` cut off the line
+                let end = (byte_range.end - line_start).min(line_content.len());
+                let line_range = (byte_range.start - line_start).min(end)..end;
+                ReplaceHit {
+                    line_number: line + 1,
+                    replacement: query.expand_replacement(&content, byte_range.clone(), replacement),
+                    byte_range,
+                    line_content,
+                    line_range,
+                    included: true,
                 }
+            })
+            .collect();
 
-                search_in_directory_with_exclusions(fs, &path, query, results, max_results, excluded_dirs);
-            } else {
-                let file_ext = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
-                let skippable_extensions = ["png", "jpg", "jpeg", "gif", "svg", "pdf", "zip", "tar", "gz", "class", "jar", "so", "dll", "dylib", "o", "a"];
+        if hits.is_empty() {
+            return None;
+        }
+        Some(Self { file_path, original: content, hits })
+    }
+
+    pub fn included_count(&self) -> usize {
+        self.hits.iter().filter(|hit| hit.included).count()
+    }
+
+    /// The content with every included hit replaced.
+    pub fn replaced_content(&self) -> String {
+        let mut result = String::with_capacity(self.original.len());
+        let mut copied_up_to = 0;
+        for hit in self.hits.iter().filter(|hit| hit.included) {
+            result.push_str(&self.original[copied_up_to..hit.byte_range.start]);
+            result.push_str(&hit.replacement);
+            copied_up_to = hit.byte_range.end;
+        }
+        result.push_str(&self.original[copied_up_to..]);
+        result
+    }
+}
 
-                if skippable_extensions.contains(&file_ext) {
-                    continue;
+/// State of the project search modal's replace mode.
+#[derive(Default)]
+pub struct ProjectReplace {
+    pub enabled: bool,
+    pub replacement: String,
+    pub options: FindOptions,
+    /// The files with hits, sorted by path.
+    pub files: Vec<FileReplacement>,
+    pub status: Option<String>,
+    cancel: Arc<AtomicBool>,
+    /// Files with hits from the running preview.
+    receiver: Option<Receiver<FileReplacement>>,
+}
+
+impl ProjectReplace {
+    pub fn is_collecting(&self) -> bool {
+        self.receiver.is_some()
+    }
+
+    /// Stops the running preview, if any, and forgets its hits.
+    pub fn cancel(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+        self.receiver = None;
+        self.files.clear();
+        self.status = None;
+    }
+
+    /// Moves the files the preview found since the last call into `files`.
+    fn poll(&mut self) {
+        let Some(receiver) = &self.receiver else {
+            return;
+        };
+        loop {
+            match receiver.try_recv() {
+                Ok(file) => {
+                    let index = self.files.partition_point(|other| other.file_path < file.file_path);
+                    self.files.insert(index, file);
                 }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.receiver = None;
+                    break;
+                }
+            }
+        }
+        let (hits, files) = self.included_count();
+        self.status = Some(if self.receiver.is_some() {
+            format!("Searching... {} matches in {} files", hits, files)
+        } else {
+            format!("{} matches in {} files", hits, files)
+        });
+    }
+
+    /// Number of included hits and the number of files they are in.
+    pub fn included_count(&self) -> (usize, usize) {
+        self.files
+            .iter()
+            .map(FileReplacement::included_count)
+            .filter(|count| *count > 0)
+            .fold((0, 0), |(hits, files), count| (hits + count, files + 1))
+    }
+}
+
+/// Starts finding every match of the search query in the project and
+/// computing its replacement on a background thread, without changing
+/// anything yet. Only the index's candidates and the open files are read.
+pub fn collect_project_replacements(ide: &mut IDE) {
+    let state = &mut ide.project_replace;
+    state.cancel();
+    let query = match FindQuery::new(&ide.search_query, state.options) {
+        Ok(query) => query,
+        Err(e) => {
+            state.status = Some(e);
+            return;
+        }
+    };
+    let (Some(fs), Some(project_path)) = (&ide.file_modal.file_system, &ide.file_modal.project_path) else {
+        state.status = Some("No project is open".to_string());
+        return;
+    };
+
+    // Open files are replaced in what the editor shows, and those with unsaved
+    // changes are looked at even if the index doesn't name them
+    let open_files: HashMap<String, Rope> = ide.code_editor.buffers
+        .iter()
+        .filter_map(|buffer| Some((buffer.file_path.clone()?, buffer.snapshot())))
+        .collect();
+    let modified_files: Vec<PathBuf> = ide.code_editor.buffers
+        .iter()
+        .filter(|buffer| buffer.is_modified)
+        .filter_map(|buffer| buffer.file_path.as_deref().map(PathBuf::from))
+        .filter(|path| path.starts_with(project_path))
+        .collect();
+    let fs = FileSystem::clone(fs);
+    let root = project_path.clone();
+    let index = ide.project_search.index_for(&root);
+    let replacement = state.replacement.clone();
+    let cancel = Arc::new(AtomicBool::new(false));
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    state.cancel = cancel.clone();
+    state.receiver = Some(receiver);
+    state.status = Some("Searching...".to_string());
+
+    std::thread::spawn(move || {
+        let mut files = candidate_files(fs.vfs(), &root, index.as_ref(), &query, &cancel);
+        for path in modified_files {
+            if !files.contains(&path) {
+                files.push(path);
+            }
+        }
+        files.par_iter().for_each_with(sender, |sender, path| {
+            if cancel.load(Ordering::Relaxed) {
+                return;
+            }
+            let Some(file_path) = path.to_str() else {
+                return;
+            };
+            let content = match open_files.get(file_path) {
+                Some(text) => text.to_string(),
+                None => match fs.open_file(path) {
+                    Ok(content) => content,
+                    Err(_) => return,
+                },
+            };
+            if let Some(file) = FileReplacement::new(file_path.to_string(), content, &query, &replacement) {
+                let _ = sender.send(file);
+            }
+        });
+    });
+}
+
+/// Writes every file with included hits and updates the open buffers. Files
+/// that changed since the preview was computed are left alone.
+pub fn apply_project_replacements(ide: &mut IDE) {
+    let Some(fs) = ide.file_modal.file_system.clone() else {
+        return;
+    };
+    let mut replaced_hits = 0;
+    let mut replaced_files = 0;
+    let mut problems = Vec::new();
 
-                if let Ok(content) = fs.open_file(&path) {
-                    let file_results = content
-                        .lines()
-                        .enumerate()
-                        .filter(|(_, line)| line.to_lowercase().contains(query))
-                        .take(10)
-                        .map(|(line_num, line)| SearchResult {
-                            line_number: line_num + 1,
-                            line_content: line.to_string(),
-                            file_path: Some(path.to_str().unwrap().to_string()),
-                        })
-                        .collect::<Vec<_>>();
-
-                    results.extend(file_results);
+    for file in ide.project_replace.files.iter().filter(|file| file.included_count() > 0) {
+        let path = Path::new(&file.file_path);
+        let buffer = ide.code_editor.buffers
+            .iter_mut()
+            .find(|buffer| buffer.file_path.as_deref() == Some(file.file_path.as_str()));
+        let result = match buffer {
+            Some(buffer) if buffer.text() != file.original => Err("changed since the preview".to_string()),
+            Some(buffer) => {
+                // Edit the buffer rather than just the file so the replacement can be undone
+                let edits = file.hits
+                    .iter()
+                    .filter(|hit| hit.included)
+                    .map(|hit| {
+                        let range = buffer.byte_to_char(hit.byte_range.start)..buffer.byte_to_char(hit.byte_range.end);
+                        (range, hit.replacement.clone())
+                    })
+                    .collect();
+                buffer.replace_ranges(edits, EditKind::Replace);
+                fs.save_file(path, &buffer.text())
+                    .map(|_| buffer.mark_saved())
+                    .map_err(|e| e.to_string())
+            }
+            None => match fs.open_file(path) {
+                Ok(content) if content == file.original => {
+                    fs.save_file(path, &file.replaced_content()).map_err(|e| e.to_string())
                 }
+                Ok(_) => Err("changed since the preview".to_string()),
+                Err(e) => Err(e.to_string()),
+            },
+        };
+
+        match result {
+            Ok(()) => {
+                ide.project_search.file_changed(path);
+                replaced_hits += file.included_count();
+                replaced_files += 1;
             }
+            Err(e) => problems.push(format!("{}: {}", file.file_path, e)),
         }
     }
+
+    for problem in &problems {
+        ide.console_panel.log(&format!("Replace in project skipped {}", problem));
+    }
+    let summary = format!("Replaced {} occurrences in {} files", replaced_hits, replaced_files);
+    ide.console_panel.log(&summary);
+    ide.project_replace.status = Some(if problems.is_empty() {
+        summary
+    } else {
+        format!("{}, {} files skipped (see console)", summary, problems.len())
+    });
+    ide.project_replace.files.clear();
+}
+
+fn show_project_replace(ide: &mut IDE, ui: &mut egui::Ui) {
+    let mut preview = false;
+    ui.horizontal(|ui| {
+        let response = ui.add(TextEdit::singleline(&mut ide.project_replace.replacement).hint_text("Replace with..."));
+        preview |= response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+        let options = &mut ide.project_replace.options;
+        ui.toggle_value(&mut options.case_sensitive, "Aa").on_hover_text("Match case");
+        ui.toggle_value(&mut options.whole_word, "W").on_hover_text("Match whole word");
+        ui.toggle_value(&mut options.regex, ".*").on_hover_text("Use regular expression");
+        preview |= ui.button("Preview").clicked();
+    });
+    if preview {
+        collect_project_replacements(ide);
+    }
+    ide.project_replace.poll();
+    if ide.project_replace.is_collecting() {
+        ui.ctx().request_repaint_after(Duration::from_millis(50));
+    }
+    if let Some(status) = &ide.project_replace.status {
+        ui.label(status);
+    }
+
+    let project_path = ide.file_modal.project_path.clone().unwrap_or_default();
+    ScrollArea::vertical().max_height(400.0).show(ui, |ui| {
+        for file in ide.project_replace.files.iter_mut() {
+            let display_path = Path::new(&file.file_path)
+                .strip_prefix(&project_path)
+                .map_or(file.file_path.clone(), |p| p.display().to_string());
+            ui.horizontal(|ui| {
+                let mut all_included = file.hits.iter().all(|hit| hit.included);
+                if ui.checkbox(&mut all_included, "").changed() {
+                    for hit in file.hits.iter_mut() {
+                        hit.included = all_included;
+                    }
+                }
+                ui.strong(format!("{} ({})", display_path, file.hits.len()));
+            });
+            ui.indent(&file.file_path, |ui| {
+                for hit in file.hits.iter_mut() {
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut hit.included, "");
+                        ui.label(hit.preview(ui));
+                    });
+                }
+            });
+        }
+    });
+
+    let (hits, files) = ide.project_replace.included_count();
+    let button = egui::Button::new(format!("Replace {} occurrences in {} files", hits, files));
+    if ui.add_enabled(hits > 0 && !ide.project_replace.is_collecting(), button).clicked() {
+        apply_project_replacements(ide);
+    }
+}
+
+/// Opens the file of a search result and selects its match.
+pub fn open_search_result(ide: &mut IDE, result: &SearchResult) {
+    if let Some(file_path) = &result.file_path {
+        ide.file_modal.open_file(file_path, &mut ide.code_editor);
+    }
+    let editor = &mut ide.code_editor;
+    let Some(buffer) = editor.get_active_buffer_mut() else {
+        return;
+    };
+    let line_start = buffer.line_to_byte(result.line_number.saturating_sub(1));
+    let match_start = line_start + result.match_range.start;
+    let match_end = line_start + result.match_range.end;
+    buffer.set_selection(Selection::new(buffer.byte_to_char(match_start), buffer.byte_to_char(match_end)));
+
+    // Flash the line and the match
+    editor.selected_match_position = Some((match_start, match_end));
+    editor.search_selected_line = Some(result.line_number);
+    editor.search_highlight_text = result.line_content.get(result.match_range.clone()).map(str::to_string);
+    editor.search_highlight_expires_at = Some(Instant::now() + Duration::from_secs_f64(0.5));
+}
+
+/// Moves the modal's results into the search panel, which keeps them around
+/// while they are visited.
+fn show_results_in_panel(ide: &mut IDE, is_project_search: bool) {
+    let search = SearchHistoryEntry {
+        query: ide.search_query.trim().to_string(),
+        options: if is_project_search { ide.search_options } else { FindOptions { case_sensitive: true, ..FindOptions::default() } },
+        project: is_project_search,
+    };
+    ide.search_panel.set_results(search, &ide.search_results, ide.file_modal.project_path.as_deref());
+}
+
+/// Opens the search modal with a search from the history and runs it again.
+pub fn rerun_search(ide: &mut IDE, search: SearchHistoryEntry) {
+    ide.search_query = search.query;
+    ide.search_results.clear();
+    ide.search_focus_requested = true;
+    if search.project {
+        ide.search_options = search.options;
+        ide.project_replace.enabled = false;
+        ide.show_project_search_modal = true;
+        ide.project_search.restart();
+    } else {
+        ide.show_current_file_search_modal = true;
+    }
 }
 
 pub fn show_search_modal(ide: &mut IDE, ctx: &Context) {
     let is_project_search = ide.show_project_search_modal;
-    let modal_title = if is_project_search { "Project Search" } else { "Current File Search" };
+    let is_replace = is_project_search && ide.project_replace.enabled;
+    let modal_title = if is_replace {
+        "Replace in Project"
+    } else if is_project_search {
+        "Project Search"
+    } else {
+        "Current File Search"
+    };
+
+    if !ide.show_project_search_modal {
+        ide.project_search.cancel();
+        if ide.project_replace.is_collecting() {
+            ide.project_replace.cancel();
+        }
+    }
 
     if ide.show_current_file_search_modal || ide.show_project_search_modal {
         egui::Window::new(modal_title)
@@ -120,22 +726,60 @@ pub fn show_search_modal(ide: &mut IDE, ctx: &Context) {
                 ui.vertical(|ui| {
                     let text_edit = TextEdit::singleline(&mut ide.search_query).hint_text("Search...");
 
-                    let response = ui.add(text_edit);
+                    let response = ui.horizontal(|ui| {
+                        let response = ui.add(text_edit);
+                        if is_project_search && !is_replace {
+                            let options = &mut ide.search_options;
+                            ui.toggle_value(&mut options.case_sensitive, "Aa").on_hover_text("Match case");
+                            ui.toggle_value(&mut options.whole_word, "W").on_hover_text("Match whole word");
+                            ui.toggle_value(&mut options.regex, ".*").on_hover_text("Use regular expression");
+                        }
+                        if is_project_search {
+                            ui.toggle_value(&mut ide.project_replace.enabled, "Replace");
+                        }
+                        response
+                    }).inner;
                     if ide.search_focus_requested {
                         response.request_focus();
                         ide.search_focus_requested = false;
                     }
 
-                    if !ide.search_query.is_empty() {
-                        if is_project_search {
-                            perform_project_search(ide);
+                    if is_replace {
+                        show_project_replace(ide, ui);
+                        return;
+                    }
+
+                    let status = if is_project_search {
+                        perform_project_search(ide, ctx);
+                        if let Some(error) = ide.project_search.error() {
+                            error.to_string()
+                        } else if ide.project_search.is_searching() {
+                            format!("Searching... {} results", ide.search_results.len())
                         } else {
+                            format!("{} results", ide.search_results.len())
+                        }
+                    } else {
+                        if !ide.search_query.is_empty() {
                             perform_current_file_search(ide);
                         }
-                    }
+                        format!("{} results", ide.search_results.len())
+                    };
+                    ui.horizontal(|ui| {
+                        ui.weak(status);
+                        let button = egui::Button::new("Show in Panel");
+                        if ui.add_enabled(!ide.search_results.is_empty(), button).clicked() {
+                            show_results_in_panel(ide, is_project_search);
+                            ide.show_current_file_search_modal = false;
+                            ide.show_project_search_modal = false;
+                        }
+                    });
 
-                    ScrollArea::vertical().show(ui, |ui| {
-                        for result in ide.search_results.iter() {
+                    // Enter opens the first result
+                    let enter = ui.input(|i| i.key_pressed(egui::Key::Enter));
+                    let mut chosen = if enter { ide.search_results.first().cloned() } else { None };
+                    let row_height = ui.spacing().interact_size.y + ui.spacing().item_spacing.y;
+                    ScrollArea::vertical().show_rows(ui, row_height, ide.search_results.len(), |ui, rows| {
+                        for result in ide.search_results[rows].iter() {
                             let display_text = if is_project_search {
                                 format!(
                                     "{}:{} - {}",
@@ -146,61 +790,105 @@ pub fn show_search_modal(ide: &mut IDE, ctx: &Context) {
                             } else {
                                 format!("Line {}: {}", result.line_number, result.line_content.trim())
                             };
-
-                            let response = ui.button(display_text);
-                            if response.clicked() || ui.input(|i| i.key_pressed(egui::Key::Enter)) {
-                                // First, clear any existing highlights
-                                ide.code_editor.search_highlight_text = None;
-                                ide.code_editor.search_selected_line = None;
-                                ide.code_editor.selected_match_position = None;
-                                
-                                // Open the file if it's a project search
-                                if is_project_search {
-                                    if let Some(file_path) = &result.file_path {
-                                        ide.file_modal.open_file(file_path, &mut ide.code_editor);
-                                    }
-                                }
-                            
-                                // Calculate absolute position of the match in the file
-                                if let Some(buffer) = ide.code_editor.get_active_buffer() {
-                                    let content = &buffer.content;
-                                    let mut line_start = 0;
-                                    for _ in 0..result.line_number.saturating_sub(1) {
-                                        if let Some(next_line) = content[line_start..].find('\n') {
-                                            line_start += next_line + 1;
-                                        }
-                                    }
-                                    
-                                    if let Some(column_offset) = result.line_content.find(&ide.search_query) {
-                                        let match_start = line_start + column_offset;
-                                        let match_end = match_start + ide.search_query.len();
-                                        ide.code_editor.selected_match_position = Some((match_start, match_end));
-                                        
-                                        // Set cursor position
-                                        if let Some(buffer) = ide.code_editor.get_active_buffer_mut() {
-                                            buffer.set_cursor_position(result.line_number, column_offset);
-                                            buffer.is_modified = false;
-                                        }
-                                    }
-                                }
-                            
-                                // Set up highlighting
-                                ide.code_editor.search_selected_line = Some(result.line_number);
-                                ide.code_editor.search_highlight_text = Some(ide.search_query.clone());
-                                ide.code_editor.search_highlight_expires_at = Some(
-                                    std::time::Instant::now() + std::time::Duration::from_secs_f64(0.5)
-                                );
-                            
-                                // Request a repaint to ensure highlighting is visible
-                                ctx.request_repaint();
-                            
-                                // Close the search modals
-                                ide.show_current_file_search_modal = false;
-                                ide.show_project_search_modal = false;
+                            if ui.button(display_text).clicked() {
+                                chosen = Some(result.clone());
                             }
                         }
                     });
+
+                    if let Some(result) = chosen {
+                        // The other results stay reachable from the panel and with F4
+                        show_results_in_panel(ide, is_project_search);
+                        ide.search_panel.select(&result);
+                        open_search_result(ide, &result);
+                        ctx.request_repaint();
+                        ide.show_current_file_search_modal = false;
+                        ide.show_project_search_modal = false;
+                    }
                 });
             });
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::vfs::MemoryVfs;
+
+    #[test]
+    fn search_file_reports_the_first_match_of_each_line() {
+        let fs = FileSystem::with_vfs("/project", MemoryVfs::new());
+        fs.create_directory(Path::new("/project")).unwrap();
+        let path = Path::new("/project/Main.kt");
+        fs.save_file(path, "val count = 1\nfun main() {\n    println(count + count)\n}\n").unwrap();
+
+        let query = FindQuery::new("count", FindOptions::default()).unwrap();
+        let results = search_file(&fs, path, &query);
+        let found: Vec<(usize, Range<usize>)> = results.iter().map(|r| (r.line_number, r.match_range.clone())).collect();
+        assert_eq!(found, vec![(1, 4..9), (3, 12..17)]);
+        assert_eq!(results[1].line_content, "    println(count + count)");
+        assert_eq!(results[0].file_path.as_deref(), Some("/project/Main.kt"));
+
+        let query = FindQuery::new(r"fun \w+\(", FindOptions { regex: true, ..FindOptions::default() }).unwrap();
+        assert_eq!(search_file(&fs, path, &query).len(), 1);
+    }
+
+    #[test]
+    fn project_search_walks_the_vfs_and_honors_ignore_files() {
+        let fs = FileSystem::with_vfs("/project", MemoryVfs::new());
+        for directory in ["/project/src", "/project/build", "/project/.git", "/project/vendor"] {
+            fs.create_directory(Path::new(directory)).unwrap();
+        }
+        for (path, content) in [
+            ("/project/.gitignore", "build/\n*.log\n"),
+            ("/project/.zzzignore", "vendor/\n"),
+            ("/project/src/Main.kt", "val count = 1\nprintln(count)\n"),
+            ("/project/src/Util.kt", "fun other() {}\n"),
+            ("/project/notes.md", "count things\n"),
+            ("/project/debug.log", "count\n"),
+            ("/project/build/Generated.kt", "val count = 2\n"),
+            ("/project/vendor/Lib.kt", "val count = 3\n"),
+            ("/project/.git/config", "count\n"),
+            ("/project/icon.png", "count\n"),
+        ] {
+            fs.save_file(Path::new(path), content).unwrap();
+        }
+
+        let query = FindQuery::new("count", FindOptions::default()).unwrap();
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        search_project(&fs, Path::new("/project"), None, &query, &AtomicBool::new(false), sender);
+        let mut found: Vec<(String, usize)> = receiver
+            .iter()
+            .flatten()
+            .map(|result| (result.file_path.unwrap(), result.line_number))
+            .collect();
+        found.sort();
+        assert_eq!(found, vec![
+            ("/project/notes.md".to_string(), 1),
+            ("/project/src/Main.kt".to_string(), 1),
+            ("/project/src/Main.kt".to_string(), 2),
+        ]);
+    }
+
+    #[test]
+    fn replace_hits_starting_at_a_crlf_line_end_stay_within_the_line() {
+        let content = "fun main() {\r\n    run()\r\n}\r\n".to_string();
+        let query = FindQuery::new(r"\n\s*\}", FindOptions { regex: true, ..FindOptions::default() }).unwrap();
+        let replacement = FileReplacement::new("/project/Main.kt".to_string(), content, &query, " }").unwrap();
+        let hit = &replacement.hits[0];
+        assert_eq!(hit.line_number, 2);
+        assert_eq!(hit.line_content, "    run()");
+        assert_eq!(hit.line_range, 9..9);
+        assert_eq!(replacement.replaced_content(), "fun main() {\r\n    run()\r }\r\n");
+    }
+
+    #[test]
+    fn search_file_skips_files_that_are_not_text() {
+        let fs = FileSystem::with_vfs("/project", MemoryVfs::new());
+        fs.create_directory(Path::new("/project")).unwrap();
+        let query = FindQuery::new("count", FindOptions::default()).unwrap();
+        fs.save_bytes(Path::new("/project/image.webp"), &[0xff, 0xfe, b'c', b'o', b'u', b'n', b't']).unwrap();
+        assert!(search_file(&fs, Path::new("/project/image.webp"), &query).is_empty());
+        assert!(search_file(&fs, Path::new("/project/missing.kt"), &query).is_empty());
+    }
+}
@@ -0,0 +1,661 @@
+use rayon::prelude::*;
+use regex_syntax::hir::{Hir, HirKind};
+use regex_syntax::ParserBuilder;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use crate::core::app_state::AppState;
+use crate::core::find::FindQuery;
+use crate::core::search::visit_searchable_files;
+use crate::core::vfs::DiskVfs;
+
+/// Three bytes of ASCII-lowercased text packed into the low 24 bits.
+type Trigram = u32;
+
+const MAGIC: &[u8; 8] = b"ZZZIDX02";
+/// Same limit as `FileSystem::open_file`; larger files are never searched.
+const MAX_FILE_SIZE: u64 = 10_000_000;
+
+/// Size and modification time of a file when it was indexed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileStamp {
+    len: u64,
+    modified_secs: u64,
+    modified_nanos: u32,
+}
+
+impl FileStamp {
+    fn of(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        Some(Self {
+            len: metadata.len(),
+            modified_secs: modified.as_secs(),
+            modified_nanos: modified.subsec_nanos(),
+        })
+    }
+}
+
+struct IndexedFile {
+    /// Path relative to the project root.
+    path: PathBuf,
+    stamp: FileStamp,
+    /// Binary and very large files are kept with their stamp, so scans skip
+    /// them while they are unchanged, but are never candidates.
+    searchable: bool,
+}
+
+/// What indexing read from a file.
+struct FileContent {
+    stamp: FileStamp,
+    /// `None` if the file cannot be searched.
+    trigrams: Option<Vec<Trigram>>,
+}
+
+/// Files to add to or remove from an index, computed without holding its lock.
+#[derive(Default)]
+struct IndexChanges {
+    indexed: Vec<(PathBuf, FileContent)>,
+    removed: Vec<PathBuf>,
+}
+
+/// A trigram index over the searchable files of a project.
+///
+/// Every file is listed under each trigram of its ASCII-lowercased content, so
+/// a search only has to read the files that contain all trigrams of the
+/// literal parts of its pattern. Files are identified by ids; a changed file
+/// gets a new id and its old one is dropped from the posting lists lazily, when
+/// enough of them have piled up.
+pub struct SearchIndex {
+    root: PathBuf,
+    /// Indexed files by id. `None` for files that were removed or re-indexed.
+    files: Vec<Option<IndexedFile>>,
+    ids: HashMap<PathBuf, u32>,
+    /// File ids containing each trigram, in ascending order.
+    postings: HashMap<Trigram, Vec<u32>>,
+    removed: usize,
+    dirty: bool,
+}
+
+impl SearchIndex {
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            files: Vec::new(),
+            ids: HashMap::new(),
+            postings: HashMap::new(),
+            removed: 0,
+            dirty: true,
+        }
+    }
+
+    /// Where the index of `root` is kept: one file per project under the
+    /// application's config directory.
+    fn storage_path(root: &Path) -> Option<PathBuf> {
+        let hash = root
+            .to_string_lossy()
+            .bytes()
+            .fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100_0000_01b3));
+        AppState::config_dir().map(|dir| dir.join("search_index").join(format!("{:016x}.idx", hash)))
+    }
+
+    /// Loads the stored index of `root`, or starts an empty one if there is none
+    /// or it cannot be read.
+    pub fn load_or_new(root: &Path) -> Self {
+        Self::storage_path(root)
+            .and_then(|path| fs::File::open(path).ok())
+            .and_then(|file| Self::read_from(root, &mut BufReader::new(file)).ok())
+            .unwrap_or_else(|| Self::new(root))
+    }
+
+    /// Writes the index to disk if it changed since it was loaded or last saved.
+    pub fn save(&mut self) -> io::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let path = Self::storage_path(&self.root)
+            .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "No config directory"))?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        self.compact();
+        // Write next to the target and rename, so a crash never leaves half an index
+        let temp_path = path.with_extension("tmp");
+        let mut writer = BufWriter::new(fs::File::create(&temp_path)?);
+        self.write_to(&mut writer)?;
+        writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        fs::rename(&temp_path, &path)?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    pub fn file_count(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Files that may contain a match of `query`, sorted by path.
+    pub fn candidates(&self, query: &FindQuery) -> Vec<PathBuf> {
+        let mut lists = Vec::new();
+        for trigram in query_trigrams(query) {
+            match self.postings.get(&trigram) {
+                Some(list) => lists.push(list),
+                None => return Vec::new(),
+            }
+        }
+        // Intersecting the shortest lists first keeps the intermediate results small
+        lists.sort_by_key(|list| list.len());
+
+        let mut paths: Vec<PathBuf> = match lists.split_first() {
+            Some((first, rest)) => {
+                let mut ids = (*first).clone();
+                for list in rest {
+                    if ids.is_empty() {
+                        break;
+                    }
+                    ids = intersect(&ids, list);
+                }
+                ids.into_iter()
+                    .filter_map(|id| self.files[id as usize].as_ref())
+                    .map(|file| self.root.join(&file.path))
+                    .collect()
+            }
+            None => self.files
+                .iter()
+                .flatten()
+                .filter(|file| file.searchable)
+                .map(|file| self.root.join(&file.path))
+                .collect(),
+        };
+        paths.sort();
+        paths
+    }
+
+    fn stamps(&self) -> HashMap<PathBuf, FileStamp> {
+        self.files
+            .iter()
+            .flatten()
+            .map(|file| (file.path.clone(), file.stamp))
+            .collect()
+    }
+
+    fn apply(&mut self, changes: IndexChanges) {
+        for path in &changes.removed {
+            self.remove(path);
+        }
+        for (path, content) in changes.indexed {
+            self.remove(&path);
+            let id = self.files.len() as u32;
+            let searchable = content.trigrams.is_some();
+            for trigram in content.trigrams.into_iter().flatten() {
+                self.postings.entry(trigram).or_default().push(id);
+            }
+            self.files.push(Some(IndexedFile { path: path.clone(), stamp: content.stamp, searchable }));
+            self.ids.insert(path, id);
+            self.dirty = true;
+        }
+        if self.removed > self.ids.len() {
+            self.compact();
+        }
+    }
+
+    fn remove(&mut self, path: &Path) {
+        if let Some(id) = self.ids.remove(path) {
+            self.files[id as usize] = None;
+            self.removed += 1;
+            self.dirty = true;
+        }
+    }
+
+    /// Drops removed files from the posting lists and renumbers the rest.
+    fn compact(&mut self) {
+        if self.removed == 0 {
+            return;
+        }
+        let mut new_ids = vec![None; self.files.len()];
+        let mut files = Vec::with_capacity(self.ids.len());
+        for (old_id, file) in std::mem::take(&mut self.files).into_iter().enumerate() {
+            if let Some(file) = file {
+                let id = files.len() as u32;
+                new_ids[old_id] = Some(id);
+                self.ids.insert(file.path.clone(), id);
+                files.push(Some(file));
+            }
+        }
+        // Renumbering keeps the order, so the lists stay sorted
+        for list in self.postings.values_mut() {
+            list.retain_mut(|id| match new_ids[*id as usize] {
+                Some(new_id) => {
+                    *id = new_id;
+                    true
+                }
+                None => false,
+            });
+        }
+        self.postings.retain(|_, list| !list.is_empty());
+        self.files = files;
+        self.removed = 0;
+    }
+
+    fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        write_bytes(writer, self.root.to_string_lossy().as_bytes())?;
+        let files: Vec<&IndexedFile> = self.files.iter().flatten().collect();
+        writer.write_all(&(files.len() as u32).to_le_bytes())?;
+        for file in files {
+            write_bytes(writer, file.path.to_string_lossy().as_bytes())?;
+            writer.write_all(&file.stamp.len.to_le_bytes())?;
+            writer.write_all(&file.stamp.modified_secs.to_le_bytes())?;
+            writer.write_all(&file.stamp.modified_nanos.to_le_bytes())?;
+            writer.write_all(&[file.searchable as u8])?;
+        }
+        writer.write_all(&(self.postings.len() as u32).to_le_bytes())?;
+        let mut encoded = Vec::new();
+        for (trigram, list) in &self.postings {
+            // Ids are ascending, so the gaps between them are small and encode in a byte or two
+            encoded.clear();
+            let mut previous = 0;
+            for id in list {
+                write_varint(&mut encoded, id - previous);
+                previous = *id;
+            }
+            writer.write_all(&trigram.to_le_bytes())?;
+            writer.write_all(&(list.len() as u32).to_le_bytes())?;
+            write_bytes(writer, &encoded)?;
+        }
+        Ok(())
+    }
+
+    fn read_from(root: &Path, reader: &mut impl Read) -> io::Result<Self> {
+        let invalid = |message: &str| io::Error::new(ErrorKind::InvalidData, message.to_string());
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("Unknown index format"));
+        }
+        if read_bytes(reader)? != root.to_string_lossy().as_bytes() {
+            return Err(invalid("Index belongs to another project"));
+        }
+
+        let mut index = Self::new(root);
+        let file_count = read_u32(reader)?;
+        for id in 0..file_count {
+            let path = PathBuf::from(String::from_utf8(read_bytes(reader)?).map_err(|_| invalid("Invalid path"))?);
+            let stamp = FileStamp {
+                len: read_u64(reader)?,
+                modified_secs: read_u64(reader)?,
+                modified_nanos: read_u32(reader)?,
+            };
+            let mut searchable = [0];
+            reader.read_exact(&mut searchable)?;
+            index.ids.insert(path.clone(), id);
+            index.files.push(Some(IndexedFile { path, stamp, searchable: searchable[0] != 0 }));
+        }
+
+        let trigram_count = read_u32(reader)?;
+        for _ in 0..trigram_count {
+            let trigram = read_u32(reader)?;
+            let len = read_u32(reader)? as usize;
+            let encoded = read_bytes(reader)?;
+            // Every id takes at least one byte
+            if len > encoded.len() {
+                return Err(invalid("Truncated posting list"));
+            }
+            let mut list = Vec::with_capacity(len);
+            let mut position = 0;
+            let mut previous = 0;
+            for _ in 0..len {
+                let gap = read_varint(&encoded, &mut position).ok_or_else(|| invalid("Truncated posting list"))?;
+                previous += gap;
+                if previous >= file_count {
+                    return Err(invalid("Posting refers to an unknown file"));
+                }
+                list.push(previous);
+            }
+            index.postings.insert(trigram, list);
+        }
+        index.dirty = false;
+        Ok(index)
+    }
+}
+
+/// Walks the project and reads every file that is new or changed compared to
+/// `known`. Returns `None` if cancelled.
+fn scan_changes(root: &Path, known: &HashMap<PathBuf, FileStamp>, cancel: &AtomicBool) -> Option<IndexChanges> {
+    let mut seen = HashSet::new();
+    let mut changed = Vec::new();
+    let finished = visit_searchable_files(&DiskVfs, root, &mut |path| {
+        if let Ok(relative) = path.strip_prefix(root) {
+            let stamp = FileStamp::of(path);
+            if stamp.is_some() && known.get(relative) != stamp.as_ref() {
+                changed.push(relative.to_path_buf());
+            }
+            seen.insert(relative.to_path_buf());
+        }
+        !cancel.load(Ordering::Relaxed)
+    });
+    if !finished {
+        return None;
+    }
+
+    let indexed = changed
+        .par_iter()
+        .filter_map(|relative| {
+            if cancel.load(Ordering::Relaxed) {
+                return None;
+            }
+            let content = read_file(&root.join(relative))?;
+            Some((relative.clone(), content))
+        })
+        .collect();
+    if cancel.load(Ordering::Relaxed) {
+        return None;
+    }
+    let removed = known.keys().filter(|path| !seen.contains(*path)).cloned().collect();
+    Some(IndexChanges { indexed, removed })
+}
+
+/// Reads a file and collects its distinct trigrams, if it can be searched
+/// (not too large, readable and UTF-8). `None` if the file is gone.
+fn read_file(path: &Path) -> Option<FileContent> {
+    let stamp = FileStamp::of(path)?;
+    Some(FileContent { stamp, trigrams: read_trigrams(path, stamp) })
+}
+
+fn read_trigrams(path: &Path, stamp: FileStamp) -> Option<Vec<Trigram>> {
+    if stamp.len > MAX_FILE_SIZE {
+        return None;
+    }
+    let content = fs::read(path).ok()?;
+    std::str::from_utf8(&content).ok()?;
+    let mut trigrams: Vec<Trigram> = content.windows(3).map(|bytes| trigram(bytes[0], bytes[1], bytes[2])).collect();
+    trigrams.sort_unstable();
+    trigrams.dedup();
+    Some(trigrams)
+}
+
+fn trigram(a: u8, b: u8, c: u8) -> Trigram {
+    (a.to_ascii_lowercase() as u32) << 16 | (b.to_ascii_lowercase() as u32) << 8 | c.to_ascii_lowercase() as u32
+}
+
+/// Trigrams that every file containing a match of `query` must contain.
+fn query_trigrams(query: &FindQuery) -> HashSet<Trigram> {
+    // Case-insensitivity is a matcher option rather than part of the source, so
+    // the literals come out exactly as typed
+    let Ok(hir) = ParserBuilder::new().multi_line(true).build().parse(query.regex_source()) else {
+        return HashSet::new();
+    };
+    let mut literals = Vec::new();
+    required_literals(&hir, &mut literals);
+
+    let case_sensitive = query.options().case_sensitive;
+    literals
+        .iter()
+        .flat_map(|literal| literal.windows(3))
+        // Without case sensitivity `k` and `s` also match the Kelvin and long s
+        // signs, and non-ASCII letters match their other case, none of which
+        // lowercase to the same bytes
+        .filter(|bytes| case_sensitive || bytes.iter().all(|b| b.is_ascii() && !matches!(b.to_ascii_lowercase(), b'k' | b's')))
+        .map(|bytes| trigram(bytes[0], bytes[1], bytes[2]))
+        .collect()
+}
+
+/// Collects byte strings that every match of `hir` contains.
+fn required_literals(hir: &Hir, literals: &mut Vec<Vec<u8>>) {
+    match hir.kind() {
+        HirKind::Literal(literal) => literals.push(literal.0.to_vec()),
+        HirKind::Capture(capture) => required_literals(&capture.sub, literals),
+        HirKind::Repetition(repetition) if repetition.min > 0 => required_literals(&repetition.sub, literals),
+        HirKind::Concat(parts) => {
+            // Adjacent literals form one longer literal
+            let mut run = Vec::new();
+            for part in parts {
+                if let HirKind::Literal(literal) = part.kind() {
+                    run.extend_from_slice(&literal.0);
+                    continue;
+                }
+                if !run.is_empty() {
+                    literals.push(std::mem::take(&mut run));
+                }
+                required_literals(part, literals);
+            }
+            if !run.is_empty() {
+                literals.push(run);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn intersect(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len().min(b.len()));
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                result.push(a[i]);
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    result
+}
+
+fn write_bytes(writer: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    writer.write_all(bytes)
+}
+
+/// Reads a length-prefixed byte string. The length comes from the file, so
+/// the buffer only grows as far as there is data rather than being allocated
+/// up front.
+fn read_bytes(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+    let len = read_u32(reader)? as u64;
+    let mut bytes = Vec::new();
+    reader.take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(io::Error::new(ErrorKind::UnexpectedEof, "Truncated index"));
+    }
+    Ok(bytes)
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(bytes: &[u8], position: &mut usize) -> Option<u32> {
+    let mut value = 0u32;
+    let mut shift = 0;
+    loop {
+        let byte = *bytes.get(*position)?;
+        *position += 1;
+        value |= ((byte & 0x7f) as u32).checked_shl(shift)?;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+        shift += 7;
+    }
+}
+
+/// The search index of the open project, shared with background threads. It is
+/// loaded and refreshed off the UI thread; searches fall back to walking the
+/// project until the first load has finished.
+#[derive(Clone)]
+pub struct ProjectIndex {
+    root: PathBuf,
+    index: Arc<RwLock<Option<SearchIndex>>>,
+    refreshing: Arc<AtomicBool>,
+    /// Another scan was asked for while one was running.
+    refresh_pending: Arc<AtomicBool>,
+    last_refresh: Arc<Mutex<Option<Instant>>>,
+    cancel: Arc<AtomicBool>,
+}
+
+impl ProjectIndex {
+    /// External changes are picked up by rescanning at most this often.
+    const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+    /// Starts loading the stored index of `root` and bringing it up to date.
+    pub fn open(root: &Path) -> Self {
+        let index = Self {
+            root: root.to_path_buf(),
+            index: Arc::new(RwLock::new(None)),
+            refreshing: Arc::new(AtomicBool::new(false)),
+            refresh_pending: Arc::new(AtomicBool::new(false)),
+            last_refresh: Arc::new(Mutex::new(None)),
+            cancel: Arc::new(AtomicBool::new(false)),
+        };
+        index.refresh();
+        index
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.index.read().is_ok_and(|index| index.is_some())
+    }
+
+    /// Stops background work, e.g. when another project is opened.
+    pub fn close(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+
+    /// Candidate files for `query`, or `None` while the index is still loading.
+    pub fn candidates(&self, query: &FindQuery) -> Option<Vec<PathBuf>> {
+        let index = self.index.read().ok()?;
+        index.as_ref().map(|index| index.candidates(query))
+    }
+
+    /// Rescans the project in the background if the last scan is older than
+    /// [`ProjectIndex::REFRESH_INTERVAL`].
+    pub fn refresh_if_stale(&self) {
+        let stale = self.last_refresh
+            .lock()
+            .map(|last| last.is_none_or(|at| at.elapsed() >= Self::REFRESH_INTERVAL))
+            .unwrap_or(true);
+        if stale {
+            self.refresh();
+        }
+    }
+
+    /// Rescans the project in the background, re-indexing new and changed files
+    /// and saving the result. If a scan is already running, another one follows it.
+    pub fn refresh(&self) {
+        if self.refreshing.swap(true, Ordering::AcqRel) {
+            self.refresh_pending.store(true, Ordering::Release);
+            return;
+        }
+        let this = self.clone();
+        std::thread::spawn(move || {
+            let loaded = this.index.read().is_ok_and(|index| index.is_some());
+            if !loaded {
+                let mut index = SearchIndex::load_or_new(&this.root);
+                if let Some(changes) = scan_changes(&this.root, &index.stamps(), &this.cancel) {
+                    index.apply(changes);
+                }
+                if let Ok(mut shared) = this.index.write() {
+                    *shared = Some(index);
+                }
+            } else {
+                // Scan against a snapshot so searches are not blocked meanwhile
+                let known = this.index.read().ok().and_then(|index| index.as_ref().map(SearchIndex::stamps));
+                let changes = known.and_then(|known| scan_changes(&this.root, &known, &this.cancel));
+                if let (Some(changes), Ok(mut index)) = (changes, this.index.write()) {
+                    if let Some(index) = index.as_mut() {
+                        index.apply(changes);
+                    }
+                }
+            }
+
+            if !this.cancel.load(Ordering::Relaxed) {
+                if let Ok(mut index) = this.index.write() {
+                    if let Some(Err(e)) = index.as_mut().map(SearchIndex::save) {
+                        eprintln!("Failed to save search index: {}", e);
+                    }
+                }
+            }
+            if let Ok(mut last) = this.last_refresh.lock() {
+                *last = Some(Instant::now());
+            }
+            this.refreshing.store(false, Ordering::Release);
+            if this.refresh_pending.swap(false, Ordering::AcqRel) && !this.cancel.load(Ordering::Relaxed) {
+                this.refresh();
+            }
+        });
+    }
+
+    /// Re-indexes a file that was written or removed, e.g. on save.
+    pub fn file_changed(&self, path: &Path) {
+        self.files_changed(&[path.to_path_buf()]);
+    }
+
+    /// Re-indexes the indexed files at or below `paths` in the background. Paths
+    /// the index does not know yet may be new files, which only a scan can tell
+    /// apart from ignored ones, so they start one.
+    pub fn files_changed(&self, paths: &[PathBuf]) {
+        let relative_paths: Vec<PathBuf> = paths
+            .iter()
+            .filter_map(|path| path.strip_prefix(&self.root).ok())
+            .map(Path::to_path_buf)
+            .collect();
+        if relative_paths.is_empty() {
+            return;
+        }
+        let this = self.clone();
+        std::thread::spawn(move || {
+            let mut known = Vec::new();
+            let mut unknown = false;
+            if let Ok(index) = this.index.read() {
+                let Some(index) = index.as_ref() else {
+                    // The first scan is still running and will see the changes
+                    return;
+                };
+                for relative in &relative_paths {
+                    let before = known.len();
+                    known.extend(index.ids.keys().filter(|path| path.starts_with(relative)).cloned());
+                    unknown |= known.len() == before && this.root.join(relative).is_file();
+                }
+            }
+
+            let mut changes = IndexChanges::default();
+            for relative in known {
+                match read_file(&this.root.join(&relative)) {
+                    Some(content) => changes.indexed.push((relative, content)),
+                    None => changes.removed.push(relative),
+                }
+            }
+            if let Ok(mut index) = this.index.write() {
+                if let Some(index) = index.as_mut() {
+                    index.apply(changes);
+                }
+            }
+            if unknown {
+                this.refresh();
+            }
+        });
+    }
+}
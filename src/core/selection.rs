@@ -0,0 +1,188 @@
+use std::ops::Range;
+
+/// A selection in a buffer, expressed in character indices. `anchor` stays put
+/// while `head` follows the cursor; both are equal for a plain caret.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Selection {
+    pub anchor: usize,
+    pub head: usize,
+    /// Column the cursor tries to return to when moving up or down.
+    pub preferred_column: Option<usize>,
+}
+
+impl Selection {
+    pub fn caret(position: usize) -> Self {
+        Self {
+            anchor: position,
+            head: position,
+            preferred_column: None,
+        }
+    }
+
+    pub fn new(anchor: usize, head: usize) -> Self {
+        Self {
+            anchor,
+            head,
+            preferred_column: None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.anchor == self.head
+    }
+
+    pub fn start(&self) -> usize {
+        self.anchor.min(self.head)
+    }
+
+    pub fn end(&self) -> usize {
+        self.anchor.max(self.head)
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.start()..self.end()
+    }
+
+    /// Adjusts the selection for an edit that replaced `start..old_end` with
+    /// `new_len` characters.
+    pub fn map_edit(self, start: usize, old_end: usize, new_len: usize) -> Self {
+        let map = |pos: usize| {
+            if pos < start {
+                pos
+            } else if pos >= old_end {
+                pos - (old_end - start) + new_len
+            } else {
+                start + new_len
+            }
+        };
+        Self {
+            anchor: map(self.anchor),
+            head: map(self.head),
+            preferred_column: None,
+        }
+    }
+}
+
+/// The selections of a buffer: never empty, sorted by position and free of
+/// overlaps. One of them is the primary selection, which the view follows and
+/// single-cursor commands act on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selections {
+    ranges: Vec<Selection>,
+    primary: usize,
+}
+
+impl Default for Selections {
+    fn default() -> Self {
+        Self::single(Selection::default())
+    }
+}
+
+impl Selections {
+    pub fn single(selection: Selection) -> Self {
+        Self {
+            ranges: vec![selection],
+            primary: 0,
+        }
+    }
+
+    /// Builds a set from arbitrary selections, merging any that overlap.
+    /// `primary` indexes into `ranges`.
+    pub fn new(ranges: Vec<Selection>, primary: usize) -> Self {
+        if ranges.is_empty() {
+            return Self::default();
+        }
+        let mut selections = Self {
+            primary: primary.min(ranges.len() - 1),
+            ranges,
+        };
+        selections.normalize();
+        selections
+    }
+
+    pub fn primary(&self) -> Selection {
+        self.ranges[self.primary]
+    }
+
+    pub fn primary_index(&self) -> usize {
+        self.primary
+    }
+
+    /// Iterates over the selections in document order.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &Selection> {
+        self.ranges.iter()
+    }
+
+    pub fn count(&self) -> usize {
+        self.ranges.len()
+    }
+
+    pub fn is_multiple(&self) -> bool {
+        self.ranges.len() > 1
+    }
+
+    /// Adds a selection and makes it the primary one.
+    pub fn push(&mut self, selection: Selection) {
+        self.ranges.push(selection);
+        self.primary = self.ranges.len() - 1;
+        self.normalize();
+    }
+
+    /// Index of the first selection that ends at or after `char_index`.
+    pub fn first_ending_at_or_after(&self, char_index: usize) -> usize {
+        self.ranges.partition_point(|selection| selection.end() < char_index)
+    }
+
+    pub fn clamp(&mut self, len: usize) {
+        for selection in &mut self.ranges {
+            selection.anchor = selection.anchor.min(len);
+            selection.head = selection.head.min(len);
+        }
+        self.normalize();
+    }
+
+    /// Adjusts every selection for an edit that replaced `start..old_end` with
+    /// `new_len` characters.
+    pub fn map_edit(&mut self, start: usize, old_end: usize, new_len: usize) {
+        for selection in &mut self.ranges {
+            *selection = selection.map_edit(start, old_end, new_len);
+        }
+        self.normalize();
+    }
+
+    /// Sorts the selections and merges those that overlap, or that touch when one
+    /// of them is a plain caret, keeping track of which one is primary.
+    fn normalize(&mut self) {
+        if self.ranges.len() == 1 {
+            return;
+        }
+        let primary = self.ranges[self.primary];
+        let mut ranges = std::mem::take(&mut self.ranges);
+        ranges.sort_by_key(|selection| (selection.start(), selection.end()));
+
+        let mut merged: Vec<Selection> = Vec::with_capacity(ranges.len());
+        let mut primary_index = 0;
+        for selection in ranges {
+            let is_primary = selection == primary;
+            match merged.last_mut() {
+                Some(last)
+                    if selection.start() < last.end()
+                        || (selection.start() == last.end() && (selection.is_empty() || last.is_empty())) =>
+                {
+                    let end = last.end().max(selection.end());
+                    *last = if last.head >= last.anchor {
+                        Selection::new(last.start(), end)
+                    } else {
+                        Selection::new(end, last.start())
+                    };
+                }
+                _ => merged.push(selection),
+            }
+            if is_primary {
+                primary_index = merged.len() - 1;
+            }
+        }
+        self.ranges = merged;
+        self.primary = primary_index;
+    }
+}
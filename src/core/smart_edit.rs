@@ -0,0 +1,417 @@
+use serde::{Deserialize, Serialize};
+use crate::components::code_editor::{Buffer, SelectionEdit};
+use crate::core::folding::FoldStrategy;
+use crate::core::selection::Selection;
+
+/// How far to look for the bracket matching the one at the cursor, in characters.
+const MAX_BRACKET_SCAN: usize = 20_000;
+
+const BRACKETS: &[(char, char)] = &[('{', '}'), ('(', ')'), ('[', ']')];
+const BRACKETS_AND_QUOTES: &[(char, char)] = &[('{', '}'), ('(', ')'), ('[', ']'), ('"', '"'), ('\'', '\'')];
+const QUOTES: &[(char, char)] = &[('"', '"')];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum IndentStyle {
+    #[default]
+    Spaces,
+    Tabs,
+}
+
+/// How the editor indents and whether it closes pairs while typing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EditorSettings {
+    pub indent_style: IndentStyle,
+    /// Spaces per indentation level.
+    pub indent_width: usize,
+    /// Typing an opening bracket or quote also inserts its closing one.
+    pub auto_close: bool,
+}
+
+impl Default for EditorSettings {
+    fn default() -> Self {
+        Self {
+            indent_style: IndentStyle::Spaces,
+            indent_width: 4,
+            auto_close: true,
+        }
+    }
+}
+
+impl EditorSettings {
+    /// The text of one indentation level.
+    pub fn indent_unit(&self) -> String {
+        match self.indent_style {
+            IndentStyle::Spaces => " ".repeat(self.indent_width.max(1)),
+            IndentStyle::Tabs => "\t".to_string(),
+        }
+    }
+}
+
+/// What smart editing knows about the language of a buffer.
+#[derive(Debug, Clone, Copy)]
+pub struct LanguageRules {
+    /// Pairs that open and close indented blocks, and are highlighted when
+    /// the cursor is next to one of them.
+    pub brackets: &'static [(char, char)],
+    /// Pairs closed automatically when the opener is typed.
+    pub auto_close: &'static [(char, char)],
+    /// A line ending in `:` starts an indented block, as in Python and YAML.
+    pub colon_blocks: bool,
+    /// A line ending in an opening tag starts an indented block.
+    pub tag_blocks: bool,
+}
+
+impl LanguageRules {
+    pub fn for_buffer(buffer: &Buffer) -> Self {
+        match FoldStrategy::for_buffer(buffer) {
+            FoldStrategy::Xml => Self {
+                brackets: &[],
+                auto_close: QUOTES,
+                colon_blocks: false,
+                tag_blocks: true,
+            },
+            FoldStrategy::Indentation => Self {
+                brackets: BRACKETS,
+                auto_close: BRACKETS_AND_QUOTES,
+                colon_blocks: true,
+                tag_blocks: false,
+            },
+            // Quotes are mostly apostrophes in prose
+            FoldStrategy::Brackets if buffer.syntax == "Plain Text" => Self {
+                brackets: BRACKETS,
+                auto_close: &[],
+                colon_blocks: false,
+                tag_blocks: false,
+            },
+            FoldStrategy::Brackets => Self {
+                brackets: BRACKETS,
+                auto_close: BRACKETS_AND_QUOTES,
+                colon_blocks: false,
+                tag_blocks: false,
+            },
+        }
+    }
+
+    /// Whether a line whose text before the cursor is `before` starts an
+    /// indented block.
+    fn opens_block(&self, before: &str) -> bool {
+        let before = before.trim_end();
+        let Some(last) = before.chars().last() else {
+            return false;
+        };
+        self.brackets.iter().any(|&(open, _)| open == last)
+            || (self.colon_blocks && last == ':')
+            || (self.tag_blocks && ends_with_opening_tag(before))
+    }
+
+    /// Whether `after`, the text after the cursor, closes the block opened
+    /// by `before`, so it belongs on a line of its own.
+    fn closes_block(&self, before: &str, after: &str) -> bool {
+        let (Some(last), Some(first)) = (before.trim_end().chars().last(), after.trim_start().chars().next()) else {
+            return false;
+        };
+        self.brackets.contains(&(last, first))
+            || (self.tag_blocks && ends_with_opening_tag(before.trim_end()) && after.trim_start().starts_with("</"))
+    }
+
+    fn closer_for(&self, open: char) -> Option<char> {
+        self.auto_close.iter().find(|&&(o, _)| o == open).map(|&(_, close)| close)
+    }
+
+    fn is_closer(&self, c: char) -> bool {
+        self.auto_close.iter().any(|&(_, close)| close == c)
+    }
+}
+
+/// `<tag ...>`, but not `<tag/>`, `</tag>`, `<?xml ...?>` or `<!-- ... -->`.
+fn ends_with_opening_tag(text: &str) -> bool {
+    if !text.ends_with('>') || text.ends_with("/>") {
+        return false;
+    }
+    let Some(tag_start) = text.rfind('<') else {
+        return false;
+    };
+    let tag = &text[tag_start..];
+    !tag.starts_with("</") && !tag.starts_with("<?") && !tag.starts_with("<!")
+}
+
+fn leading_whitespace(text: &str) -> &str {
+    &text[..text.len() - text.trim_start().len()]
+}
+
+/// Applies `edits` unless two of them overlap, e.g. cursors on the same
+/// whitespace, in which case `fallback` is inserted at every cursor instead.
+fn apply(buffer: &mut Buffer, edits: Vec<SelectionEdit>, fallback: &str) {
+    if edits.windows(2).any(|pair| pair[0].range.end > pair[1].range.start) {
+        buffer.insert_at_cursor(fallback);
+    } else {
+        buffer.edit_selections(edits);
+    }
+}
+
+/// Breaks the line at every cursor. The new line keeps the indentation of
+/// the old one, one level deeper after a block opener, and a closing bracket
+/// right after the cursor moves to a line of its own.
+pub fn insert_newline(buffer: &mut Buffer, settings: &EditorSettings) {
+    let rules = LanguageRules::for_buffer(buffer);
+    let unit = settings.indent_unit();
+    let newline = buffer.line_ending();
+    let edits = buffer
+        .selections()
+        .iter()
+        .map(|selection| {
+            let range = selection.range();
+            let line = buffer.char_to_line(range.start);
+            let line_start = buffer.line_to_char(line);
+            let before = buffer.slice(line_start..range.start);
+            let end_line = buffer.char_to_line(range.end);
+            let after = buffer.slice(range.end..buffer.char_index(end_line, usize::MAX));
+            let indent = leading_whitespace(&before).to_string();
+            // Whitespace-only lines don't keep their indentation
+            let start = if before.trim().is_empty() { line_start } else { range.start };
+            let end = range.end + leading_whitespace(&after).chars().count();
+
+            let mut text = format!("{}{}", newline, indent);
+            if rules.opens_block(&before) {
+                text.push_str(&unit);
+            }
+            let caret = text.chars().count();
+            if rules.closes_block(&before, &after) {
+                text.push_str(newline);
+                text.push_str(&indent);
+            }
+            SelectionEdit {
+                range: start..end,
+                text,
+                selection: caret..caret,
+            }
+        })
+        .collect();
+    apply(buffer, edits, newline);
+}
+
+/// Types `text` at every cursor, closing pairs, typing over closers that are
+/// already there and dedenting closing brackets on otherwise empty lines.
+/// Returns `false` if `text` needs none of that and should be inserted as is.
+pub fn type_text(buffer: &mut Buffer, settings: &EditorSettings, text: &str) -> bool {
+    let mut chars = text.chars();
+    let (Some(c), None) = (chars.next(), chars.next()) else {
+        return false;
+    };
+    let rules = LanguageRules::for_buffer(buffer);
+    let all_carets = buffer.selections().iter().all(|selection| selection.is_empty());
+
+    if settings.auto_close && all_carets && rules.is_closer(c)
+        && buffer.selections().iter().all(|selection| buffer.char_at(selection.head) == Some(c))
+    {
+        buffer.map_selections(|_, selection| Selection::caret(selection.head + 1));
+        return true;
+    }
+    if let Some(close) = rules.closer_for(c).filter(|_| settings.auto_close) {
+        let edits = buffer
+            .selections()
+            .iter()
+            .map(|selection| {
+                let range = selection.range();
+                if !selection.is_empty() {
+                    // Wraps the selection and keeps it selected
+                    let selected = buffer.slice(range.clone());
+                    let length = selected.chars().count();
+                    return SelectionEdit {
+                        range,
+                        text: format!("{}{}{}", c, selected, close),
+                        selection: 1..length + 1,
+                    };
+                }
+                let text = if should_close(buffer, &rules, selection.head, c, close) {
+                    format!("{}{}", c, close)
+                } else {
+                    c.to_string()
+                };
+                SelectionEdit {
+                    range,
+                    text,
+                    selection: 1..1,
+                }
+            })
+            .collect();
+        apply(buffer, edits, text);
+        return true;
+    }
+    if let Some(&(open, _)) = rules.brackets.iter().find(|&&(_, close)| close == c) {
+        if !all_carets {
+            return false;
+        }
+        let edits = buffer
+            .selections()
+            .iter()
+            .map(|selection| dedent_closer(buffer, settings, selection.head, open, c))
+            .collect();
+        apply(buffer, edits, text);
+        return true;
+    }
+    false
+}
+
+/// Whether typing `open` at `position` should insert `close` too: only
+/// before whitespace, punctuation or the end of the line, and for quotes not
+/// right after a word, as in `don't`.
+fn should_close(buffer: &Buffer, rules: &LanguageRules, position: usize, open: char, close: char) -> bool {
+    let next_is_free = buffer
+        .char_at(position)
+        .is_none_or(|next| next.is_whitespace() || rules.is_closer(next) || matches!(next, ',' | ';' | ':' | '.'));
+    if open != close {
+        return next_is_free;
+    }
+    let previous = position.checked_sub(1).and_then(|index| buffer.char_at(index));
+    next_is_free && previous.is_none_or(|previous| !previous.is_alphanumeric() && previous != '\\' && previous != open)
+}
+
+/// Types `close` at `position`. On a line with only whitespace before it the
+/// closer is lined up with the line of its opener.
+fn dedent_closer(buffer: &Buffer, settings: &EditorSettings, position: usize, open: char, close: char) -> SelectionEdit {
+    let line = buffer.char_to_line(position);
+    let line_start = buffer.line_to_char(line);
+    let before = buffer.slice(line_start..position);
+    let typed = SelectionEdit {
+        range: position..position,
+        text: close.to_string(),
+        selection: 1..1,
+    };
+    if !before.trim().is_empty() {
+        return typed;
+    }
+    let indent = match find_opener(buffer, position, open, close) {
+        Some(opener) => leading_whitespace(&buffer.line(buffer.char_to_line(opener))).to_string(),
+        None => {
+            let unit = settings.indent_unit();
+            before.strip_suffix(unit.as_str()).unwrap_or(&before).to_string()
+        }
+    };
+    if indent == before {
+        return typed;
+    }
+    let length = indent.chars().count() + 1;
+    SelectionEdit {
+        range: line_start..position,
+        text: format!("{}{}", indent, close),
+        selection: length..length,
+    }
+}
+
+/// The unmatched `open` before `position`.
+fn find_opener(buffer: &Buffer, position: usize, open: char, close: char) -> Option<usize> {
+    let mut depth = 0usize;
+    let start = position.saturating_sub(MAX_BRACKET_SCAN);
+    for index in (start..position).rev() {
+        match buffer.char_at(index) {
+            Some(c) if c == close => depth += 1,
+            Some(c) if c == open => {
+                if depth == 0 {
+                    return Some(index);
+                }
+                depth -= 1;
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The unmatched `close` after `position`.
+fn find_closer(buffer: &Buffer, position: usize, open: char, close: char) -> Option<usize> {
+    let mut depth = 0usize;
+    let end = (position + MAX_BRACKET_SCAN).min(buffer.len_chars());
+    for index in position..end {
+        match buffer.char_at(index) {
+            Some(c) if c == open => depth += 1,
+            Some(c) if c == close => {
+                if depth == 0 {
+                    return Some(index);
+                }
+                depth -= 1;
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The bracket at the primary cursor and the one matching it, as character
+/// indices. The character after the cursor is checked before the one before
+/// it. `None` if neither is a bracket or the match is too far away.
+pub fn matching_brackets(buffer: &Buffer) -> Option<(usize, usize)> {
+    let rules = LanguageRules::for_buffer(buffer);
+    let head = buffer.selection().head;
+    [Some(head), head.checked_sub(1)].into_iter().flatten().find_map(|index| {
+        let c = buffer.char_at(index)?;
+        rules.brackets.iter().find_map(|&(open, close)| {
+            if c == open {
+                find_closer(buffer, index + 1, open, close).map(|other| (index, other))
+            } else if c == close {
+                find_opener(buffer, index, open, close).map(|other| (index, other))
+            } else {
+                None
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::selection::Selections;
+
+    fn editing(text: &str, carets: &[usize]) -> Buffer {
+        let mut buffer = Buffer::from_file(text.to_string(), "Main.kt".to_string(), "Kotlin".to_string());
+        buffer.set_selections(Selections::new(carets.iter().map(|&caret| Selection::caret(caret)).collect(), 0));
+        buffer
+    }
+
+    fn carets(buffer: &Buffer) -> Vec<usize> {
+        buffer.selections().iter().map(|selection| selection.head).collect()
+    }
+
+    #[test]
+    fn newlines_between_brackets_indent_the_cursor_and_move_the_closer_down() {
+        let settings = EditorSettings::default();
+        let mut buffer = editing("fun main() {}", &[12]);
+        insert_newline(&mut buffer, &settings);
+        assert_eq!(buffer.text(), "fun main() {\n    \n}");
+        assert_eq!(carets(&buffer), [17]);
+
+        buffer.undo();
+        assert_eq!(buffer.text(), "fun main() {}");
+    }
+
+    #[test]
+    fn newlines_keep_the_line_ending_and_indentation_at_every_cursor() {
+        let settings = EditorSettings::default();
+        let mut buffer = editing("  a(\r\n  b", &[4, 9]);
+        insert_newline(&mut buffer, &settings);
+        assert_eq!(buffer.text(), "  a(\r\n      \r\n  b\r\n  ");
+        assert_eq!(carets(&buffer), [12, 21]);
+    }
+
+    #[test]
+    fn closers_line_up_with_their_opener_on_otherwise_empty_lines() {
+        let settings = EditorSettings::default();
+        let mut buffer = editing("if (x) {\n    run()\n        ", &[28]);
+        assert!(type_text(&mut buffer, &settings, "}"));
+        assert_eq!(buffer.text(), "if (x) {\n    run()\n}");
+        assert_eq!(carets(&buffer), [20]);
+        buffer.undo();
+        assert_eq!(buffer.text(), "if (x) {\n    run()\n        ");
+
+        // Without an opener the closer goes back one level
+        let edit = dedent_closer(&editing("        ", &[8]), &settings, 8, '{', '}');
+        assert_eq!(edit.range, 0..8);
+        assert_eq!(edit.text, "    }");
+        assert_eq!(edit.selection, 5..5);
+
+        // After other text it is typed as is
+        let edit = dedent_closer(&editing("    run() ", &[10]), &settings, 10, '(', ')');
+        assert_eq!(edit.range, 10..10);
+        assert_eq!(edit.text, ")");
+    }
+}
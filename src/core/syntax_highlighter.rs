@@ -0,0 +1,180 @@
+use eframe::egui::Color32;
+use std::ops::Range;
+use syntect::highlighting::{HighlightIterator, HighlightState, Highlighter, Style, Theme};
+use syntect::parsing::{ParseState, ScopeStack, SyntaxSet};
+use crate::components::code_editor::Buffer;
+
+/// Colored byte ranges of a single line, excluding its line ending.
+pub type LineSpans = Vec<(Color32, Range<usize>)>;
+
+/// Parser and highlighter state at the start of a line.
+type LineState = (ParseState, HighlightState);
+
+#[derive(Clone, Default)]
+struct HighlightedLine {
+    start_state: Option<LineState>,
+    /// `None` until the line has been highlighted since its last edit.
+    spans: Option<LineSpans>,
+}
+
+/// Syntax highlighting for one buffer that keeps the syntect state at the start
+/// of every line.
+///
+/// After an edit only the lines from the first changed line onwards are
+/// re-highlighted, and only until the state at the start of a line matches the
+/// one stored for it; everything after that point is known to be unchanged.
+/// Lines are highlighted lazily, so nothing below the viewport is processed.
+pub struct IncrementalHighlighter {
+    syntax: String,
+    theme: String,
+    revision: u64,
+    lines: Vec<HighlightedLine>,
+    /// Every line before this index has up-to-date spans.
+    frontier: usize,
+    /// Lines that may still be highlighted in the current frame.
+    budget: usize,
+}
+
+impl IncrementalHighlighter {
+    /// Upper bound on lines highlighted per frame, so opening or jumping deep
+    /// into a large file never blocks the UI.
+    const LINES_PER_FRAME: usize = 5000;
+
+    pub fn new(buffer: &Buffer, theme: &str) -> Self {
+        Self {
+            syntax: buffer.syntax.clone(),
+            theme: theme.to_string(),
+            revision: buffer.revision(),
+            lines: vec![HighlightedLine::default(); buffer.line_count()],
+            frontier: 0,
+            budget: Self::LINES_PER_FRAME,
+        }
+    }
+
+    /// Resets the per-frame work budget.
+    pub fn begin_frame(&mut self) {
+        self.budget = Self::LINES_PER_FRAME;
+    }
+
+    /// Brings the per-line bookkeeping in line with the buffer's edits since the
+    /// last call. Lines touched by an edit lose their spans; all others keep them.
+    pub fn sync(&mut self, buffer: &Buffer, theme: &str) {
+        if buffer.syntax != self.syntax || theme != self.theme {
+            *self = Self::new(buffer, theme);
+            return;
+        }
+        if buffer.revision() == self.revision {
+            return;
+        }
+
+        let Some(changes) = buffer.line_changes_since(self.revision) else {
+            *self = Self::new(buffer, theme);
+            return;
+        };
+        for change in changes {
+            let start = change.start_line.min(self.lines.len().saturating_sub(1));
+            let removed_end = (start + 1 + change.removed_lines).min(self.lines.len());
+            self.lines.splice(
+                (start + 1).min(removed_end)..removed_end,
+                std::iter::repeat_with(HighlightedLine::default).take(change.inserted_lines),
+            );
+            if let Some(line) = self.lines.get_mut(start) {
+                line.spans = None;
+            }
+            self.frontier = self.frontier.min(start);
+        }
+
+        self.lines.resize_with(buffer.line_count(), HighlightedLine::default);
+        self.frontier = self.frontier.min(self.lines.len());
+        self.revision = buffer.revision();
+    }
+
+    /// Highlights every stale line before `end_line`. Returns `false` if the
+    /// frame budget ran out first, in which case the caller should repaint.
+    pub fn highlight_until(&mut self, buffer: &Buffer, end_line: usize, syntax_set: &SyntaxSet, theme: &Theme) -> bool {
+        let end_line = end_line.min(self.lines.len());
+        if self.frontier >= end_line {
+            return true;
+        }
+
+        let syntax = syntax_set
+            .find_syntax_by_name(&self.syntax)
+            .unwrap_or_else(|| syntax_set.find_syntax_plain_text());
+        let highlighter = Highlighter::new(theme);
+        let initial_state = || (ParseState::new(syntax), HighlightState::new(&highlighter, ScopeStack::new()));
+
+        let mut line_index = self.frontier;
+        let mut carried: Option<LineState> = None;
+        while line_index < end_line {
+            if self.budget == 0 {
+                self.stop_at(line_index, carried);
+                return false;
+            }
+            self.budget -= 1;
+
+            let start_state = match carried.take() {
+                Some(state) => state,
+                None if line_index == 0 => initial_state(),
+                None => self.lines[line_index].start_state.clone().unwrap_or_else(initial_state),
+            };
+            let (mut parse_state, mut highlight_state) = start_state.clone();
+            let text = buffer.line_with_ending(line_index);
+            let content_len = text.trim_end_matches(['\n', '\r']).len();
+
+            let ops = parse_state.parse_line(&text, syntax_set).unwrap_or_default();
+            let mut spans = LineSpans::new();
+            let mut offset = 0;
+            for (style, piece) in HighlightIterator::new(&mut highlight_state, &ops, &text, &highlighter) {
+                let end = (offset + piece.len()).min(content_len);
+                if offset < end {
+                    spans.push((style_to_color(style), offset..end));
+                }
+                offset += piece.len();
+            }
+
+            let line = &mut self.lines[line_index];
+            line.start_state = Some(start_state);
+            line.spans = Some(spans);
+            line_index += 1;
+
+            let end_state = (parse_state, highlight_state);
+            match self.lines.get(line_index) {
+                // The next line starts in the same state as before, so it and
+                // everything after it up to the next edited line are still valid.
+                Some(next) if next.spans.is_some() && next.start_state.as_ref() == Some(&end_state) => {
+                    line_index = self.lines[line_index..]
+                        .iter()
+                        .position(|line| line.spans.is_none())
+                        .map_or(self.lines.len(), |offset| line_index + offset);
+                }
+                Some(_) => carried = Some(end_state),
+                None => {}
+            }
+        }
+
+        self.stop_at(line_index, carried);
+        true
+    }
+
+    /// Records where highlighting paused. A line whose start state changed keeps
+    /// the new state but drops its spans, since those were computed from the old one.
+    fn stop_at(&mut self, line_index: usize, carried: Option<LineState>) {
+        if let Some(state) = carried {
+            let line = &mut self.lines[line_index];
+            line.start_state = Some(state);
+            line.spans = None;
+        }
+        self.frontier = line_index;
+    }
+
+    /// Spans for a line, or `None` if it has not been highlighted since it changed.
+    /// Lines past the frontier may return spans from before an earlier edit,
+    /// which is closer to the final result than no colors at all.
+    pub fn spans(&self, line_index: usize) -> Option<&LineSpans> {
+        self.lines.get(line_index).and_then(|line| line.spans.as_ref())
+    }
+}
+
+fn style_to_color(style: Style) -> Color32 {
+    Color32::from_rgb(style.foreground.r, style.foreground.g, style.foreground.b)
+}
@@ -0,0 +1,154 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use syntect::parsing::{SyntaxDefinition, SyntaxReference, SyntaxSet, SyntaxSetBuilder};
+use crate::core::app_state::AppState;
+
+/// Grammars compiled into the binary, on top of syntect's defaults. Groovy
+/// and XML come with the defaults; the Gradle and Android XML grammars add
+/// to them.
+const BUNDLED_SYNTAXES: &[(&str, &str)] = &[
+    ("Kotlin", include_str!("../resources/syntaxes/Kotlin.sublime-syntax")),
+    ("Gradle Kotlin DSL", include_str!("../resources/syntaxes/GradleKotlin.sublime-syntax")),
+    ("Gradle", include_str!("../resources/syntaxes/GradleGroovy.sublime-syntax")),
+    ("TOML", include_str!("../resources/syntaxes/TOML.sublime-syntax")),
+    ("ProGuard", include_str!("../resources/syntaxes/ProGuard.sublime-syntax")),
+    ("AIDL", include_str!("../resources/syntaxes/AIDL.sublime-syntax")),
+    ("Android XML", include_str!("../resources/syntaxes/AndroidXML.sublime-syntax")),
+];
+
+/// Line comment, block comment start and end of the bundled syntaxes, which
+/// ship without the metadata syntect reads them from for its defaults. Empty
+/// when the language has no such comments.
+const BUNDLED_COMMENTS: &[(&str, &str, &str, &str)] = &[
+    ("Kotlin", "//", "/*", "*/"),
+    ("Gradle Kotlin DSL", "//", "/*", "*/"),
+    ("Gradle", "//", "/*", "*/"),
+    ("TOML", "#", "", ""),
+    ("ProGuard", "#", "", ""),
+    ("AIDL", "//", "/*", "*/"),
+    ("Android XML", "", "<!--", "-->"),
+];
+
+/// Used instead of XML for files below a `res` folder and for manifests.
+const ANDROID_XML: &str = "Android XML";
+
+/// How comments are written in a language.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommentTokens {
+    /// Starts a comment that runs to the end of the line, e.g. `//`.
+    pub line: Option<String>,
+    /// Opens and closes a comment, e.g. `<!--` and `-->`.
+    pub block: Option<(String, String)>,
+}
+
+impl CommentTokens {
+    fn new(line: Option<&str>, block: Option<(&str, &str)>) -> Self {
+        Self {
+            line: line.map(str::trim).filter(|marker| !marker.is_empty()).map(str::to_string),
+            block: block
+                .map(|(open, close)| (open.trim(), close.trim()))
+                .filter(|(open, close)| !open.is_empty() && !close.is_empty())
+                .map(|(open, close)| (open.to_string(), close.to_string())),
+        }
+    }
+}
+
+/// syntect's default syntaxes, the bundled ones and the user's own
+/// `.sublime-syntax` files from [`user_syntax_dir`]. User syntaxes are added
+/// last, so they win over the others for the same file extension.
+pub fn load() -> SyntaxSet {
+    let mut builder = SyntaxSet::load_defaults_newlines().into_builder();
+    for (name, source) in BUNDLED_SYNTAXES {
+        match SyntaxDefinition::load_from_str(source, true, None) {
+            Ok(syntax) => builder.add(syntax),
+            Err(e) => eprintln!("Failed to load the bundled {} syntax: {}", name, e),
+        }
+    }
+    if let Some(dir) = user_syntax_dir() {
+        add_user_syntaxes(&mut builder, &dir);
+    }
+    builder.build()
+}
+
+/// Folder for additional `.sublime-syntax` files.
+pub fn user_syntax_dir() -> Option<PathBuf> {
+    AppState::config_dir().map(|dir| dir.join("syntaxes"))
+}
+
+/// Adds every `.sublime-syntax` file in `dir`. A file that doesn't parse is
+/// reported and skipped instead of failing the whole set.
+fn add_user_syntaxes(builder: &mut SyntaxSetBuilder, dir: &Path) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "sublime-syntax"))
+        .collect();
+    paths.sort();
+    for path in paths {
+        let name = path.file_stem().map(|stem| stem.to_string_lossy().into_owned());
+        let syntax = fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|source| SyntaxDefinition::load_from_str(&source, true, name.as_deref()).map_err(|e| e.to_string()));
+        match syntax {
+            Ok(syntax) => builder.add(syntax),
+            Err(e) => eprintln!("Failed to load syntax {}: {}", path.display(), e),
+        }
+    }
+}
+
+/// The syntax for `path`. Unlike [`SyntaxSet::find_syntax_for_file`] this
+/// matches multi-part extensions like `gradle.kts`, preferring the longest
+/// one, and picks Android XML for resources and manifests.
+pub fn find_syntax_for_file<'a>(syntax_set: &'a SyntaxSet, path: &Path) -> Option<&'a SyntaxReference> {
+    let file_name = path.file_name()?.to_str()?;
+    if is_android_xml(path, file_name) {
+        if let Some(syntax) = syntax_set.find_syntax_by_name(ANDROID_XML) {
+            return Some(syntax);
+        }
+    }
+    let lowercase_name = file_name.to_lowercase();
+    let mut best: Option<(usize, &SyntaxReference)> = None;
+    for syntax in syntax_set.syntaxes() {
+        for extension in &syntax.file_extensions {
+            let extension = extension.to_lowercase();
+            let matches = lowercase_name == extension
+                || lowercase_name
+                    .strip_suffix(&extension)
+                    .is_some_and(|stem| stem.ends_with('.'));
+            // Later syntaxes win ties, as they do in syntect
+            if matches && best.is_none_or(|(length, _)| extension.len() >= length) {
+                best = Some((extension.len(), syntax));
+            }
+        }
+    }
+    best.map(|(_, syntax)| syntax)
+        .or_else(|| syntax_set.find_syntax_for_file(path).ok().flatten())
+}
+
+fn is_android_xml(path: &Path, file_name: &str) -> bool {
+    if !file_name.to_lowercase().ends_with(".xml") {
+        return false;
+    }
+    file_name == "AndroidManifest.xml"
+        || path
+            .parent()
+            .and_then(Path::parent)
+            .and_then(Path::file_name)
+            .is_some_and(|name| name == "res")
+}
+
+/// The comment markers of the syntax named `syntax_name`, without the
+/// padding some grammars include. Languages without comments, like plain
+/// text, have neither.
+pub fn comment_tokens(syntax_set: &SyntaxSet, syntax_name: &str) -> CommentTokens {
+    if let Some(&(_, line, open, close)) = BUNDLED_COMMENTS.iter().find(|(name, ..)| *name == syntax_name) {
+        return CommentTokens::new(Some(line), Some((open, close)));
+    }
+    let Some(syntax) = syntax_set.find_syntax_by_name(syntax_name) else {
+        return CommentTokens::default();
+    };
+    let metadata = syntax_set.metadata().metadata_for_scope(&[syntax.scope]);
+    CommentTokens::new(metadata.line_comment(), metadata.block_comment())
+}
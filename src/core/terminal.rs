@@ -10,6 +10,7 @@ use syntect::easy::HighlightLines;
 use syntect::highlighting::{ThemeSet, Style as SyntectStyle};
 use syntect::parsing::{SyntaxSet, SyntaxReference};
 use lazy_static::lazy_static;
+use crate::utils::themes;
 
 lazy_static! {
     static ref URL_REGEX: Regex = Regex::new(r"https?://\S+").unwrap();
@@ -47,6 +48,7 @@ pub struct Terminal {
     // Syntax highlighting resources
     syntax_set: SyntaxSet,
     theme_set: ThemeSet,
+    color_scheme: String,
 }
 
 impl Terminal {
@@ -67,7 +69,8 @@ impl Terminal {
             running: Arc::clone(&running),
             auto_complete_suggestions: Arc::new(Mutex::new(Vec::new())),
             syntax_set: SyntaxSet::load_defaults_newlines(),
-            theme_set: ThemeSet::load_defaults(),
+            theme_set: themes::load_color_schemes(),
+            color_scheme: themes::DARK_COLOR_SCHEME.to_string(),
         };
 
         terminal.spawn_shell();
@@ -242,6 +245,18 @@ impl Terminal {
         self.auto_complete_suggestions.lock().unwrap().clear();
     }
 
+    /// Highlights new output with another color scheme.
+    pub fn set_color_scheme(&mut self, name: &str) {
+        if self.color_scheme != name {
+            self.color_scheme = name.to_string();
+        }
+    }
+
+    /// Reads the color schemes again, e.g. after one was imported.
+    pub fn reload_color_schemes(&mut self) {
+        self.theme_set = themes::load_color_schemes();
+    }
+
     fn detect_links_and_highlight(&self, line: &str) -> LineStyle {
         if let Some(mat) = URL_REGEX.find(line) {
             return LineStyle::Link(mat.as_str().to_string());
@@ -249,7 +264,9 @@ impl Terminal {
 
         let syntax = self.guess_syntax(line);
         if let Some(syntax) = syntax {
-            let theme = &self.theme_set.themes["Solarized (dark)"];
+            let theme = self.theme_set.themes
+                .get(&self.color_scheme)
+                .unwrap_or_else(|| &self.theme_set.themes[themes::DARK_COLOR_SCHEME]);
             let mut highlighter = HighlightLines::new(syntax, theme);
             
             if let Ok(highlighted_lines) = highlighter.highlight_line(line, &self.syntax_set) {
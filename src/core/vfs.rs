@@ -0,0 +1,329 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::core::file_system::DirectoryEntry;
+
+/// What [`FileSystem`](crate::core::file_system::FileSystem) needs to know
+/// about a path.
+#[derive(Clone, Copy, Debug)]
+pub struct VfsMetadata {
+    pub is_dir: bool,
+    pub len: u64,
+    pub modified: SystemTime,
+}
+
+/// The storage behind a [`FileSystem`](crate::core::file_system::FileSystem).
+///
+/// Errors follow `std::fs`: missing paths are `NotFound`, writing below a
+/// missing directory fails, and `read_dir` only lists direct children.
+pub trait Vfs: Send + Sync {
+    fn metadata(&self, path: &Path) -> io::Result<VfsMetadata>;
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// Creates or truncates the file at `path`. Its directory must exist.
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()>;
+
+    /// The direct children of the directory at `path`, in no particular order.
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<DirectoryEntry>>;
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    /// Marks a file as executable where that means something, e.g. `gradlew`.
+    fn set_executable(&self, path: &Path) -> io::Result<()>;
+
+    /// Whether every change fails, e.g. inside an archive.
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    /// Whether paths name real files on disk, so they can be handed to tools
+    /// that read the disk themselves.
+    fn is_disk(&self) -> bool {
+        false
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.metadata(path).is_ok()
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.metadata(path).is_ok_and(|metadata| metadata.is_dir)
+    }
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        String::from_utf8(self.read(path)?)
+            .map_err(|_| io::Error::new(ErrorKind::InvalidData, "stream did not contain valid UTF-8"))
+    }
+}
+
+/// The real file system.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DiskVfs;
+
+impl Vfs for DiskVfs {
+    fn is_disk(&self) -> bool {
+        true
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<VfsMetadata> {
+        let metadata = fs::metadata(path)?;
+        Ok(VfsMetadata {
+            is_dir: metadata.is_dir(),
+            len: metadata.len(),
+            modified: metadata.modified().unwrap_or(UNIX_EPOCH),
+        })
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        fs::write(path, contents)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<DirectoryEntry>> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if let Some(name) = entry.file_name().to_str() {
+                entries.push(DirectoryEntry {
+                    name: name.to_string(),
+                    is_dir: metadata.is_dir(),
+                    size: metadata.len(),
+                    modified: metadata.modified().unwrap_or(UNIX_EPOCH),
+                });
+            }
+        }
+        Ok(entries)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::remove_dir_all(path)
+    }
+
+    #[cfg(unix)]
+    fn set_executable(&self, path: &Path) -> io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        let mut permissions = fs::metadata(path)?.permissions();
+        permissions.set_mode(permissions.mode() | 0o111);
+        fs::set_permissions(path, permissions)
+    }
+
+    #[cfg(not(unix))]
+    fn set_executable(&self, path: &Path) -> io::Result<()> {
+        fs::metadata(path).map(|_| ())
+    }
+}
+
+#[derive(Clone, Debug)]
+enum MemoryNode {
+    File { contents: Vec<u8>, modified: SystemTime },
+    Directory { modified: SystemTime },
+}
+
+/// A file system that only exists in memory, for tests and previews. Paths are
+/// used as given, so tests should stick to absolute ones like `/project/app`.
+#[derive(Debug, Default)]
+pub struct MemoryVfs {
+    nodes: Mutex<BTreeMap<PathBuf, MemoryNode>>,
+}
+
+impl MemoryVfs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// All files with their contents, sorted by path.
+    pub fn files(&self) -> Vec<(PathBuf, Vec<u8>)> {
+        self.nodes
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(path, node)| match node {
+                MemoryNode::File { contents, .. } => Some((path.clone(), contents.clone())),
+                MemoryNode::Directory { .. } => None,
+            })
+            .collect()
+    }
+
+    fn not_found(path: &Path) -> io::Error {
+        io::Error::new(ErrorKind::NotFound, format!("{} not found", path.display()))
+    }
+
+    fn require_parent_dir(nodes: &BTreeMap<PathBuf, MemoryNode>, path: &Path) -> io::Result<()> {
+        match path.parent() {
+            None => Ok(()),
+            Some(parent) if parent.as_os_str().is_empty() => Ok(()),
+            Some(parent) => match nodes.get(parent) {
+                Some(MemoryNode::Directory { .. }) => Ok(()),
+                Some(MemoryNode::File { .. }) => Err(io::Error::other(format!("{} is not a directory", parent.display()))),
+                None => Err(Self::not_found(parent)),
+            },
+        }
+    }
+
+    /// The path and everything below it.
+    fn subtree(nodes: &BTreeMap<PathBuf, MemoryNode>, path: &Path) -> Vec<PathBuf> {
+        nodes.keys().filter(|node| node.starts_with(path)).cloned().collect()
+    }
+}
+
+impl Vfs for MemoryVfs {
+    fn metadata(&self, path: &Path) -> io::Result<VfsMetadata> {
+        match self.nodes.lock().unwrap().get(path) {
+            Some(MemoryNode::File { contents, modified }) => Ok(VfsMetadata {
+                is_dir: false,
+                len: contents.len() as u64,
+                modified: *modified,
+            }),
+            Some(MemoryNode::Directory { modified }) => Ok(VfsMetadata { is_dir: true, len: 0, modified: *modified }),
+            None => Err(Self::not_found(path)),
+        }
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        match self.nodes.lock().unwrap().get(path) {
+            Some(MemoryNode::File { contents, .. }) => Ok(contents.clone()),
+            Some(MemoryNode::Directory { .. }) => Err(io::Error::other(format!("{} is a directory", path.display()))),
+            None => Err(Self::not_found(path)),
+        }
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let mut nodes = self.nodes.lock().unwrap();
+        Self::require_parent_dir(&nodes, path)?;
+        if let Some(MemoryNode::Directory { .. }) = nodes.get(path) {
+            return Err(io::Error::other(format!("{} is a directory", path.display())));
+        }
+        nodes.insert(
+            path.to_path_buf(),
+            MemoryNode::File { contents: contents.to_vec(), modified: SystemTime::now() },
+        );
+        Ok(())
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<DirectoryEntry>> {
+        let nodes = self.nodes.lock().unwrap();
+        match nodes.get(path) {
+            Some(MemoryNode::Directory { .. }) => {}
+            Some(MemoryNode::File { .. }) => {
+                return Err(io::Error::other(format!("{} is not a directory", path.display())));
+            }
+            None => return Err(Self::not_found(path)),
+        }
+        Ok(nodes
+            .iter()
+            .filter(|(child, _)| child.parent() == Some(path))
+            .filter_map(|(child, node)| {
+                let name = child.file_name()?.to_str()?.to_string();
+                Some(match node {
+                    MemoryNode::File { contents, modified } => DirectoryEntry {
+                        name,
+                        is_dir: false,
+                        size: contents.len() as u64,
+                        modified: *modified,
+                    },
+                    MemoryNode::Directory { modified } => DirectoryEntry { name, is_dir: true, size: 0, modified: *modified },
+                })
+            })
+            .collect())
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut nodes = self.nodes.lock().unwrap();
+        let mut ancestors: Vec<&Path> = path.ancestors().filter(|ancestor| !ancestor.as_os_str().is_empty()).collect();
+        ancestors.reverse();
+        for ancestor in ancestors {
+            match nodes.get(ancestor) {
+                Some(MemoryNode::Directory { .. }) => {}
+                Some(MemoryNode::File { .. }) => {
+                    return Err(io::Error::new(
+                        ErrorKind::AlreadyExists,
+                        format!("{} is a file", ancestor.display()),
+                    ));
+                }
+                None => {
+                    nodes.insert(ancestor.to_path_buf(), MemoryNode::Directory { modified: SystemTime::now() });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut nodes = self.nodes.lock().unwrap();
+        if !nodes.contains_key(from) {
+            return Err(Self::not_found(from));
+        }
+        Self::require_parent_dir(&nodes, to)?;
+        for old_path in Self::subtree(&nodes, to) {
+            nodes.remove(&old_path);
+        }
+        for old_path in Self::subtree(&nodes, from) {
+            if let Some(node) = nodes.remove(&old_path) {
+                let suffix = old_path.strip_prefix(from).unwrap_or(Path::new(""));
+                let new_path = if suffix.as_os_str().is_empty() { to.to_path_buf() } else { to.join(suffix) };
+                nodes.insert(new_path, node);
+            }
+        }
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let mut nodes = self.nodes.lock().unwrap();
+        match nodes.get(path) {
+            Some(MemoryNode::File { .. }) => {
+                nodes.remove(path);
+                Ok(())
+            }
+            Some(MemoryNode::Directory { .. }) => {
+                Err(io::Error::other(format!("{} is a directory", path.display())))
+            }
+            None => Err(Self::not_found(path)),
+        }
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut nodes = self.nodes.lock().unwrap();
+        match nodes.get(path) {
+            Some(MemoryNode::Directory { .. }) => {
+                for removed in Self::subtree(&nodes, path) {
+                    nodes.remove(&removed);
+                }
+                Ok(())
+            }
+            Some(MemoryNode::File { .. }) => {
+                Err(io::Error::other(format!("{} is not a directory", path.display())))
+            }
+            None => Err(Self::not_found(path)),
+        }
+    }
+
+    fn set_executable(&self, path: &Path) -> io::Result<()> {
+        self.metadata(path).map(|_| ())
+    }
+}
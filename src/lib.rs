@@ -1,10 +1,29 @@
 pub mod core {
     pub mod ide;
     pub mod file_system;
+    pub mod vfs;
+    pub mod archive;
+    pub mod binary_xml;
+    pub mod file_operations;
+    pub mod file_templates;
     pub mod terminal;
     pub mod app_state;
     pub mod git_manager;
+    pub mod git_status;
     pub mod search;
+    pub mod project_walker;
+    pub mod project_files;
+    pub mod search_index;
+    pub mod file_finder;
+    pub mod file_watcher;
+    pub mod find;
+    pub mod edit_history;
+    pub mod selection;
+    pub mod folding;
+    pub mod smart_edit;
+    pub mod line_commands;
+    pub mod syntax_highlighter;
+    pub mod syntaxes;
     pub mod constants;
     pub mod app_creation;
     pub mod downloader;
@@ -19,6 +38,10 @@ pub mod utils {
 pub mod components {
     pub mod file_modal;
     pub mod code_editor;
+    pub mod editor_view;
+    pub mod gutter;
+    pub mod find_bar;
+    pub mod search_panel;
     pub mod console_panel;
     pub mod emulator_panel;
     pub mod settings_modal;
@@ -1,5 +1,14 @@
 use egui::{Color32, FontData, Stroke, Rounding, epaint::Shadow, Vec2};
 use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use syntect::highlighting::ThemeSet;
+use crate::core::app_state::AppState;
+
+/// Color schemes for themes that don't name one, picked by how dark the
+/// theme's background is. Both ship with syntect.
+pub const DARK_COLOR_SCHEME: &str = "base16-ocean.dark";
+pub const LIGHT_COLOR_SCHEME: &str = "InspiredGitHub";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Theme {
@@ -18,6 +27,10 @@ pub struct Theme {
     pub widget_border_color: Color32,
     pub widget_border_width: f32,
     pub widget_rounding: Rounding,
+    /// Name of the syntect color scheme for code in the editor and terminal.
+    /// Empty for the default that suits the background.
+    #[serde(default)]
+    pub color_scheme: String,
 }
 
 impl Theme {
@@ -35,51 +48,60 @@ impl Theme {
     }
 
     pub fn cream() -> Self {
-        Self::create_theme(
-            Color32::from_rgb(240, 240, 240),
-            Color32::from_rgb(60, 60, 60),
-            Color32::from_rgb(0, 122, 204),
-            Color32::from_rgb(180, 180, 180),
-            Color32::from_rgb(30, 30, 30),
-            Color32::from_rgb(230, 230, 230),
-            Self::create_shadow(Vec2::new(2.0, 2.0), 8.0, 0.0, 20),
-            Color32::from_rgb(173, 214, 255),
-            Color32::from_rgb(220, 220, 220),
-            Color32::from_rgb(200, 200, 200),
-            Color32::from_rgb(180, 180, 180),
-        )
+        Self {
+            color_scheme: LIGHT_COLOR_SCHEME.to_string(),
+            ..Self::create_theme(
+                Color32::from_rgb(240, 240, 240),
+                Color32::from_rgb(60, 60, 60),
+                Color32::from_rgb(0, 122, 204),
+                Color32::from_rgb(180, 180, 180),
+                Color32::from_rgb(30, 30, 30),
+                Color32::from_rgb(230, 230, 230),
+                Self::create_shadow(Vec2::new(2.0, 2.0), 8.0, 0.0, 20),
+                Color32::from_rgb(173, 214, 255),
+                Color32::from_rgb(220, 220, 220),
+                Color32::from_rgb(200, 200, 200),
+                Color32::from_rgb(180, 180, 180),
+            )
+        }
     }
 
     pub fn black() -> Self {
-        Self::create_theme(
-            Color32::from_rgb(30, 30, 40),
-            Color32::from_rgb(255, 255, 255),
-            Color32::from_rgb(0, 122, 204),
-            Color32::from_rgb(80, 80, 80),
-            Color32::from_rgb(20, 20, 20),
-            Color32::from_rgb(40, 40, 50),
-            Self::create_shadow(Vec2::new(2.0, 2.0), 8.0, 0.0, 40),
-            Color32::from_rgb(70, 130, 180),
-            Color32::from_rgb(50, 50, 60),
-            Color32::from_rgb(60, 60, 70),
-            Color32::from_rgb(70, 70, 80),
-        )
+        Self {
+            color_scheme: DARK_COLOR_SCHEME.to_string(),
+            ..Self::create_theme(
+                Color32::from_rgb(30, 30, 40),
+                Color32::from_rgb(255, 255, 255),
+                Color32::from_rgb(0, 122, 204),
+                Color32::from_rgb(80, 80, 80),
+                Color32::from_rgb(20, 20, 20),
+                Color32::from_rgb(40, 40, 50),
+                Self::create_shadow(Vec2::new(2.0, 2.0), 8.0, 0.0, 40),
+                Color32::from_rgb(70, 130, 180),
+                Color32::from_rgb(50, 50, 60),
+                Color32::from_rgb(60, 60, 70),
+                Color32::from_rgb(70, 70, 80),
+            )
+        }
     }
 
     pub fn purple() -> Self {
-        Self::create_theme(
-            Color32::from_rgb(230, 230, 250),
-            Color32::from_rgb(25, 25, 112),
-            Color32::from_rgb(255, 105, 180),
-            Color32::from_rgb(180, 180, 220),
-            Color32::from_rgb(30, 30, 30),
-            Color32::from_rgb(220, 220, 240),
-            Self::create_shadow(Vec2::new(2.0, 2.0), 8.0, 0.0, 20),
-            Color32::from_rgb(255, 182, 193),
-            Color32::from_rgb(240, 240, 255),
-            Color32::from_rgb(220, 220, 235),
-            Color32::from_rgb(180, 180, 220),
-        )
+        Self {
+            color_scheme: "Solarized (light)".to_string(),
+            ..Self::create_theme(
+                Color32::from_rgb(230, 230, 250),
+                Color32::from_rgb(25, 25, 112),
+                Color32::from_rgb(255, 105, 180),
+                Color32::from_rgb(180, 180, 220),
+                Color32::from_rgb(30, 30, 30),
+                Color32::from_rgb(220, 220, 240),
+                Self::create_shadow(Vec2::new(2.0, 2.0), 8.0, 0.0, 20),
+                Color32::from_rgb(255, 182, 193),
+                Color32::from_rgb(240, 240, 255),
+                Color32::from_rgb(220, 220, 235),
+                Color32::from_rgb(180, 180, 220),
+            )
+        }
     }
 
     fn create_theme(
@@ -111,8 +133,70 @@ impl Theme {
             widget_border_color,
             widget_border_width: Self::DEFAULT_WIDGET_BORDER_WIDTH,
             widget_rounding: Self::DEFAULT_WIDGET_ROUNDING,
+            color_scheme: String::new(),
         }
     }
+
+    pub fn is_dark(&self) -> bool {
+        let [r, g, b, _] = self.background_color.to_array();
+        let luma = 0.299 * f32::from(r) + 0.587 * f32::from(g) + 0.114 * f32::from(b);
+        luma < 128.0
+    }
+
+    /// The color scheme code is highlighted with.
+    pub fn color_scheme_name(&self) -> &str {
+        if !self.color_scheme.is_empty() {
+            &self.color_scheme
+        } else if self.is_dark() {
+            DARK_COLOR_SCHEME
+        } else {
+            LIGHT_COLOR_SCHEME
+        }
+    }
+}
+
+/// Folder for imported `.tmTheme` color schemes.
+pub fn color_scheme_dir() -> Option<PathBuf> {
+    AppState::config_dir().map(|dir| dir.join("color_schemes"))
+}
+
+/// syntect's color schemes and the imported ones, named after their file.
+/// A file that doesn't parse is reported and skipped.
+pub fn load_color_schemes() -> ThemeSet {
+    let mut theme_set = ThemeSet::load_defaults();
+    let Some(entries) = color_scheme_dir().and_then(|dir| fs::read_dir(dir).ok()) else {
+        return theme_set;
+    };
+    for path in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+        if !path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("tmtheme")) {
+            continue;
+        }
+        let Some(name) = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()) else {
+            continue;
+        };
+        match ThemeSet::get_theme(&path) {
+            Ok(theme) => {
+                theme_set.themes.insert(name, theme);
+            }
+            Err(e) => eprintln!("Failed to load color scheme {}: {}", path.display(), e),
+        }
+    }
+    theme_set
+}
+
+/// Copies a `.tmTheme` file into [`color_scheme_dir`] and returns the name it
+/// is listed under.
+pub fn import_color_scheme(path: &Path) -> Result<String, String> {
+    ThemeSet::get_theme(path).map_err(|e| format!("Not a valid color scheme: {}", e))?;
+    let name = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .ok_or_else(|| format!("Invalid file name: {}", path.display()))?;
+    let dir = color_scheme_dir().ok_or_else(|| "No config directory".to_string())?;
+    fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    fs::copy(path, dir.join(format!("{}.tmTheme", name)))
+        .map_err(|e| format!("Failed to copy {}: {}", path.display(), e))?;
+    Ok(name)
 }
 
 impl Default for Theme {